@@ -0,0 +1,550 @@
+// Authorized requests to the API, behind the ApiClient trait.
+// The access token can expire while downloading dozens of articles: requests
+// answered with 401 are retried once after refreshing the token.
+// Network errors and server errors (5xx) are retried with exponential backoff,
+// requests answered with 429 after the time asked by the server.
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_RANGE, RANGE, RETRY_AFTER};
+use reqwest::{Client, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use url::Url;
+
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use log::{debug, info};
+
+use crate::dump::{self, Dump};
+use crate::error::{Error, FileContext, Result};
+use crate::http_cache::HttpCache;
+use crate::rate_limit::RateLimiter;
+use crate::{secret, Credentials, Data, Edition, Login, Token, BASE_URL};
+
+// Not documented by the publisher, use a custom URL if it changes
+const STAGING_URL: &str = "https://api-staging.ilmanifesto.it/api/v1";
+
+// Longer waits asked with Retry-After are not worth it, the request fails
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+// API used: production, the publisher's staging API for test accounts or any
+// other URL (e.g. a local mock server)
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiEnv {
+    Prod,
+    Staging,
+    Custom(String),
+}
+
+impl ApiEnv {
+    // Parser of --api-env
+    pub fn parse(value: &str) -> std::result::Result<ApiEnv, String> {
+        match value {
+            "prod" => Ok(ApiEnv::Prod),
+            "staging" => Ok(ApiEnv::Staging),
+            url => ApiEnv::parse_url(url).map_err(|_| "expected prod, staging or an http(s) URL".to_string()),
+        }
+    }
+
+    // Parser of --base-url
+    pub fn parse_url(url: &str) -> std::result::Result<ApiEnv, String> {
+        match Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {
+                Ok(ApiEnv::Custom(url.trim_end_matches('/').to_string()))
+            }
+            _ => Err("expected an http(s) URL".to_string()),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        match self {
+            ApiEnv::Prod => BASE_URL,
+            ApiEnv::Staging => STAGING_URL,
+            ApiEnv::Custom(url) => url,
+        }
+    }
+
+    // State files of each environment are kept apart so that a test login
+    // does not replace the production one: login.json, login-staging.json,
+    // login-localhost_8080.json
+    pub fn file_name(&self, name: &str) -> String {
+        let suffix = match self {
+            ApiEnv::Prod => return name.to_string(),
+            ApiEnv::Staging => "staging".to_string(),
+            ApiEnv::Custom(url) => {
+                let url = Url::parse(url).ok();
+                let host = url.as_ref().and_then(|url| url.host_str()).unwrap_or("custom");
+                match url.as_ref().and_then(|url| url.port()) {
+                    Some(port) => format!("{}_{}", host, port),
+                    None => host.to_string(),
+                }
+            }
+        };
+
+        match name.rsplit_once('.') {
+            Some((stem, ext)) => format!("{}-{}.{}", stem, suffix, ext),
+            None => format!("{}-{}", name, suffix),
+        }
+    }
+}
+
+// Query parameters of the posts endpoint, to work around server-side quirks
+// (missing coverPosition, truncated lists) without a new release
+#[derive(clap::Args, Clone, Debug, Default)]
+#[command(next_help_heading = "Posts endpoint (advanced)")]
+pub struct PostsQuery {
+    /// Posts per page (per_page parameter of the posts endpoint)
+    #[arg(long = "posts-per-page", env = "CLIMA_POSTS_PER_PAGE")]
+    pub per_page: Option<u32>,
+
+    /// Order of the posts (orderby parameter of the posts endpoint)
+    #[arg(long = "posts-orderby", env = "CLIMA_POSTS_ORDERBY")]
+    pub orderby: Option<String>,
+
+    /// Fields of the posts, comma separated (fields parameter of the posts endpoint)
+    #[arg(long = "posts-fields", env = "CLIMA_POSTS_FIELDS")]
+    pub fields: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    // retries after the first attempt
+    pub attempts: u32,
+    // wait before the first retry, doubled at each attempt
+    pub delay: Duration,
+}
+
+impl Retry {
+    // Run a request until it succeeds, fails with a permanent error or
+    // the attempts are over
+    pub async fn run<T, F, Fut>(&self, what: &str, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match request().await {
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    let delay = match e {
+                        Error::TooManyRequests {
+                            retry_after: Some(retry_after),
+                            ..
+                        } => retry_after,
+                        _ => {
+                            // random jitter up to half of the delay
+                            let delay = self.delay * 2u32.pow(attempt);
+                            let jitter = fastrand::u64(0..=delay.as_millis() as u64 / 2);
+                            delay + Duration::from_millis(jitter)
+                        }
+                    };
+
+                    info!(event = "retry", attempt = attempt + 1; "{} failed ({}), retrying in {:?}", what, e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Http(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.is_body()
+                // connection closed while reading the body
+                || e.is_decode()
+                || e.status().is_some_and(|status| status.is_server_error())
+        }
+        Error::Incomplete { .. } => true,
+        Error::TooManyRequests { retry_after, .. } => retry_after.is_none_or(|d| d <= MAX_RETRY_AFTER),
+        _ => false,
+    }
+}
+
+// Turn server errors and 429 into errors so that they are retried
+fn server_errors(res: Response) -> Result<Response> {
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        return Err(Error::TooManyRequests {
+            url: res.url().to_string(),
+            retry_after: retry_after(&res),
+        });
+    }
+    if res.status().is_server_error() {
+        Ok(res.error_for_status()?)
+    } else {
+        Ok(res)
+    }
+}
+
+// Retry-After is either seconds or an HTTP date
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+// What the program asks to the API. Api implements it with reqwest; tests
+// run it against a mock server (see api/tests.rs).
+pub trait ApiClient {
+    // Log in with email and password, saving the login
+    async fn login(&self, credentials: &Credentials) -> Result<()>;
+    // Ask a new access token and save it
+    async fn refresh(&self) -> Result<()>;
+    async fn latest_edition(&self) -> Result<Edition>;
+    async fn edition(&self, id: i32) -> Result<Edition>;
+    async fn posts(&self, edition_id: i32, query: &PostsQuery) -> Result<Data>;
+    // ePUB file of an article
+    async fn download_epub(&self, slug: &str) -> Result<Bytes>;
+    // PDF file of an edition, written to `path` (see Api::download_to)
+    async fn download_pdf(&self, pdf: &str, path: &Path) -> Result<Bytes>;
+    async fn download_image(&self, url: &str) -> Result<Bytes>;
+}
+
+pub struct Api {
+    client: Client,
+    base_url: String,
+    retry: Retry,
+    // none until logged in
+    login: RwLock<Option<Login>>,
+    // held while refreshing the token, so that concurrent requests expired
+    // together refresh it once
+    refreshing: tokio::sync::Mutex<()>,
+    // where the refreshed token is saved
+    login_path: PathBuf,
+    passphrase: Option<String>,
+    // copy of the responses, for debugging
+    dump: Option<Dump>,
+    cache: Option<HttpCache>,
+    limiter: RateLimiter,
+}
+
+impl Api {
+    pub fn new(
+        client: Client,
+        base_url: &str,
+        retry: Retry,
+        login_path: PathBuf,
+        passphrase: Option<String>,
+        dump: Option<Dump>,
+    ) -> Api {
+        Api {
+            client,
+            base_url: base_url.to_string(),
+            retry,
+            login: RwLock::new(None),
+            refreshing: tokio::sync::Mutex::new(()),
+            login_path,
+            passphrase,
+            dump,
+            cache: None,
+            limiter: RateLimiter::new(0.0),
+        }
+    }
+
+    // Use the login saved by a previous run
+    pub fn with_login(mut self, login: Login) -> Api {
+        self.login = RwLock::new(Some(login));
+        self
+    }
+
+    // Send at most per_second requests each second
+    pub fn with_rate_limit(mut self, per_second: f64) -> Api {
+        self.limiter = RateLimiter::new(per_second);
+        self
+    }
+
+    // Make GET requests conditional, keeping the responses in the cache
+    pub fn with_cache(mut self, cache: HttpCache) -> Api {
+        self.cache = Some(cache);
+        self
+    }
+
+    // URL of an API endpoint, path starts with a slash
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    // URL of the posts of an edition
+    pub fn posts_url(&self, edition_id: i32, query: &PostsQuery) -> String {
+        let mut url = self.url(&format!("/wp/editions/{}/posts", edition_id));
+
+        let mut params = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(per_page) = query.per_page {
+            params.append_pair("per_page", &per_page.to_string());
+        }
+        if let Some(orderby) = &query.orderby {
+            params.append_pair("orderby", orderby);
+        }
+        if let Some(fields) = &query.fields {
+            params.append_pair("fields", fields);
+        }
+        let params = params.finish();
+        if !params.is_empty() {
+            url = format!("{}?{}", url, params);
+        }
+        url
+    }
+
+    // Unauthorized POST of a JSON body, parsing the JSON response
+    async fn post_json<T: DeserializeOwned>(&self, what: &str, path: &str, body: &serde_json::Value) -> Result<T> {
+        self.retry
+            .run(what, || async {
+                self.limiter.wait().await;
+                let res = self.client.post(self.url(path)).json(body).send().await?;
+                let body = dump::read(self.dump.as_ref(), "POST", self.checked(res)?).await?;
+                Ok(serde_json::from_slice::<T>(&body)?)
+            })
+            .await
+    }
+
+    // One authorized GET with additional headers, refreshing the token once
+    // if it expired
+    async fn get_once(&self, url: &str, headers: HeaderMap) -> Result<Response> {
+        let sent_token = self.access_token();
+        let res = self.send_authorized(url, headers.clone(), sent_token.as_deref()).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return self.checked(res);
+        }
+
+        {
+            let _refreshing = self.refreshing.lock().await;
+            // unless another request refreshed it in the meantime
+            if self.access_token() == sent_token {
+                info!("Access token expired, refreshing it");
+                self.refresh().await?;
+            }
+        }
+
+        let res = self.send_authorized(url, headers, self.access_token().as_deref()).await?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::Unauthorized(url.to_string()));
+        }
+        self.checked(res)
+    }
+
+    // Like server_errors, a 429 also holds back the other requests
+    fn checked(&self, res: Response) -> Result<Response> {
+        let res = server_errors(res);
+        if let Err(Error::TooManyRequests { retry_after, .. }) = &res {
+            self.limiter.pause(retry_after.unwrap_or(self.retry.delay).min(MAX_RETRY_AFTER));
+        }
+        res
+    }
+
+    // Same origin (scheme, host and port) as the base URL, with a path below
+    // its path: a string prefix would also match http://host:8080 for
+    // http://host:80, or https://api.example.evil.com for https://api.example
+    fn is_api_url(&self, url: &str) -> bool {
+        let (Ok(base), Ok(url)) = (Url::parse(&self.base_url), Url::parse(url)) else {
+            return false;
+        };
+        let base_path = base.path().trim_end_matches('/');
+        base.origin() == url.origin()
+            && url
+                .path()
+                .strip_prefix(base_path)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    fn access_token(&self) -> Option<String> {
+        self.login.read().unwrap().as_ref().map(|login| login.token.access_token.clone())
+    }
+
+    // The token is only sent to the API, not to the servers of the images
+    async fn send_authorized(&self, url: &str, headers: HeaderMap, token: Option<&str>) -> reqwest::Result<Response> {
+        self.limiter.wait().await;
+        let mut request = self.client.get(url).headers(headers);
+        if let Some(token) = token.filter(|_| self.is_api_url(url)) {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.send().await
+    }
+
+    // Body of an authorized GET, taken from the HTTP cache when the server
+    // reports that it did not change. Error statuses are returned with the
+    // body, so that error responses are dumped as well.
+    async fn get_body(&self, url: &str) -> Result<(Bytes, Option<reqwest::Error>)> {
+        let conditions = self.cache.as_ref().map(|cache| cache.conditions(url)).unwrap_or_default();
+        let res = self.get_once(url, conditions).await?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            if let Some(body) = self.cache.as_ref().and_then(|cache| cache.load(url)) {
+                debug!("{} not modified", url);
+                return Ok((body, None));
+            }
+        }
+
+        let status = res.error_for_status_ref().err();
+        let headers = res.headers().clone();
+        let body = dump::read(self.dump.as_ref(), "GET", res).await?;
+        if let Some(cache) = self.cache.as_ref().filter(|_| status.is_none()) {
+            cache.store(url, &headers, &body);
+        }
+        Ok((body, status))
+    }
+
+    // GET with the access token, parsing the JSON response and failing on
+    // error statuses
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.retry
+            .run(url, || async {
+                match self.get_body(url).await? {
+                    (_, Some(e)) => Err(e.into()),
+                    (body, None) => Ok(serde_json::from_slice::<T>(&body)?),
+                }
+            })
+            .await
+    }
+
+    // Authorized download of a file, failing on error statuses.
+    // Downloads interrupted halfway are retried as well.
+    pub async fn download(&self, url: &str) -> Result<Bytes> {
+        self.retry
+            .run(url, || async {
+                match self.get_body(url).await? {
+                    (_, Some(e)) => Err(e.into()),
+                    (body, None) => Ok(body),
+                }
+            })
+            .await
+    }
+
+    // Authorized download of a large file to `path`, kept in <path>.part until
+    // it is complete. Interrupted downloads, also of previous runs, go on from
+    // where they stopped when the server supports Range requests.
+    pub async fn download_to(&self, url: &str, path: &Path) -> Result<Bytes> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        self.retry
+            .run(url, || async {
+                let offset = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+                let mut range = HeaderMap::new();
+                if offset > 0 {
+                    range.insert(RANGE, HeaderValue::from_str(&format!("bytes={}-", offset)).unwrap());
+                }
+                let mut res = self.get_once(url, range).await?;
+                if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                    // the partial file does not belong to the current file
+                    fs::remove_file(&partial).with_path(&partial)?;
+                    res = self.get_once(url, HeaderMap::new()).await?;
+                }
+                let mut res = res.error_for_status()?;
+
+                // servers without Range support send the whole file again
+                let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+                let expected = if resumed {
+                    info!("Resuming {} from byte {}", url, offset);
+                    content_range_total(&res)
+                } else {
+                    res.content_length()
+                };
+                let mut file = if resumed {
+                    OpenOptions::new().append(true).open(&partial)
+                } else {
+                    File::create(&partial)
+                }
+                .with_path(&partial)?;
+
+                while let Some(chunk) = res.chunk().await? {
+                    file.write_all(&chunk).with_path(&partial)?;
+                }
+                drop(file);
+
+                let size = fs::metadata(&partial).with_path(&partial)?.len();
+                if let Some(expected) = expected.filter(|&expected| expected != size) {
+                    if size > expected {
+                        fs::remove_file(&partial).with_path(&partial)?;
+                    }
+                    return Err(Error::Incomplete {
+                        url: url.to_string(),
+                        size,
+                        expected,
+                    });
+                }
+
+                fs::rename(&partial, path).with_path(path)?;
+                Ok(Bytes::from(fs::read(path).with_path(path)?))
+            })
+            .await
+    }
+}
+
+impl ApiClient for Api {
+    async fn login(&self, credentials: &Credentials) -> Result<()> {
+        let login: Login = self.post_json("Login", "/auth/login", &serde_json::to_value(credentials)?).await?;
+        debug!("Logged in as {}", login.user.email);
+
+        secret::write(&self.login_path, &serde_json::to_vec(&login)?, self.passphrase.as_deref())?;
+        *self.login.write().unwrap() = Some(login);
+        Ok(())
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        let refresh_token = match self.login.read().unwrap().as_ref() {
+            Some(login) => login.token.refresh_token.clone(),
+            None => return Err(Error::NotLoggedIn),
+        };
+        let body = json!({ "refreshToken": refresh_token });
+        let token: Token = self.post_json("Token refresh", "/auth/token", &body).await?;
+
+        let mut login = self.login.write().unwrap();
+        let login = login.as_mut().ok_or(Error::NotLoggedIn)?;
+        login.token = token;
+        secret::write(&self.login_path, &serde_json::to_vec(&*login)?, self.passphrase.as_deref())?;
+
+        Ok(())
+    }
+
+    async fn latest_edition(&self) -> Result<Edition> {
+        self.get_json(&self.url("/wp/editions/latest")).await
+    }
+
+    async fn edition(&self, id: i32) -> Result<Edition> {
+        self.get_json(&self.url(&format!("/wp/editions/{}", id))).await
+    }
+
+    async fn posts(&self, edition_id: i32, query: &PostsQuery) -> Result<Data> {
+        self.get_json(&self.posts_url(edition_id, query)).await
+    }
+
+    async fn download_epub(&self, slug: &str) -> Result<Bytes> {
+        self.download(&self.url(&format!("/wp/posts/{}/download/epub", slug))).await
+    }
+
+    async fn download_pdf(&self, pdf: &str, path: &Path) -> Result<Bytes> {
+        self.download_to(&self.url(&format!("/wp/pdfs/slug/{}/download", pdf)), path).await
+    }
+
+    async fn download_image(&self, url: &str) -> Result<Bytes> {
+        self.download(url).await
+    }
+}
+
+// Total size from a Content-Range header (bytes 100-999/1000)
+fn content_range_total(res: &Response) -> Option<u64> {
+    res.headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests;
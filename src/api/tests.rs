@@ -0,0 +1,314 @@
+// The reqwest client against a mock server, with the responses of the API in
+// tests/fixtures. No network access or account is needed.
+use std::time::{Duration, Instant};
+
+use wiremock::matchers::{body_json, header, method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use super::*;
+
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn json_fixture(name: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(fixture(name), "application/json")
+}
+
+fn login_fixture() -> Login {
+    serde_json::from_str(&fixture("login.json")).unwrap()
+}
+
+// Directory of the files written by a test, e.g. the saved login, removed
+// at the end of the test
+struct TempDir(PathBuf);
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn temp_dir() -> TempDir {
+    let dir = std::env::temp_dir().join(format!("clima-test-{}", fastrand::u64(..)));
+    fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+}
+
+fn api(server: &MockServer, dir: &Path) -> Api {
+    let retry = Retry {
+        attempts: 2,
+        delay: Duration::from_millis(10),
+    };
+    Api::new(Client::new(), &server.uri(), retry, dir.join("login.json"), None, None)
+}
+
+fn no_authorization(req: &Request) -> bool {
+    !req.headers.contains_key(AUTHORIZATION)
+}
+
+#[tokio::test]
+async fn latest_edition_without_login() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/latest"))
+        .and(no_authorization)
+        .respond_with(json_fixture("edition.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let edition = api(&server, &temp_dir()).latest_edition().await.unwrap();
+    assert_eq!(edition.id, 7321);
+    assert_eq!(edition.slug, "il-manifesto-del-2024-03-15");
+    assert_eq!(edition.featured_image.unwrap().src, "https://images.example.org/2024/03/prima.jpg");
+}
+
+#[tokio::test]
+async fn login_is_saved_and_authorizes_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/auth/login"))
+        .and(body_json(serde_json::json!({ "email": "lettrice@example.org", "password": "segreta" })))
+        .respond_with(json_fixture("login.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321/posts"))
+        .and(header(AUTHORIZATION, "Bearer access-1"))
+        .respond_with(json_fixture("posts.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = temp_dir();
+    let api = api(&server, &dir);
+    let credentials = Credentials {
+        email: "lettrice@example.org".to_string(),
+        password: "segreta".to_string(),
+    };
+    api.login(&credentials).await.unwrap();
+
+    let saved: Login = serde_json::from_slice(&secret::read(&dir.join("login.json"), None).unwrap()).unwrap();
+    assert_eq!(saved.user.user_id, 42);
+
+    let posts = api.posts(7321, &PostsQuery::default()).await.unwrap();
+    assert_eq!(posts.data.len(), 2);
+    assert_eq!(posts.data[0].section.as_ref().unwrap().name, "Economia");
+    // missing cover positions sort last
+    assert_eq!(posts.data[1].cover_position, 99);
+}
+
+#[tokio::test]
+async fn expired_token_is_refreshed_once() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321/posts"))
+        .and(header(AUTHORIZATION, "Bearer access-1"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/auth/token"))
+        .and(body_json(serde_json::json!({ "refreshToken": "refresh-1" })))
+        .respond_with(json_fixture("token.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321/posts"))
+        .and(header(AUTHORIZATION, "Bearer access-2"))
+        .respond_with(json_fixture("posts.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = temp_dir();
+    let api = api(&server, &dir).with_login(login_fixture());
+    assert_eq!(api.posts(7321, &PostsQuery::default()).await.unwrap().data.len(), 2);
+
+    let saved: Login = serde_json::from_slice(&secret::read(&dir.join("login.json"), None).unwrap()).unwrap();
+    assert_eq!(saved.token.access_token, "access-2");
+}
+
+#[tokio::test]
+async fn concurrent_expired_requests_refresh_once() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321"))
+        .and(header(AUTHORIZATION, "Bearer access-1"))
+        .respond_with(ResponseTemplate::new(401).set_delay(Duration::from_millis(100)))
+        .expect(3)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/auth/token"))
+        .respond_with(json_fixture("token.json").set_delay(Duration::from_millis(100)))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321"))
+        .and(header(AUTHORIZATION, "Bearer access-2"))
+        .respond_with(json_fixture("edition.json"))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let dir = temp_dir();
+    let api = api(&server, &dir).with_login(login_fixture());
+    let (a, b, c) = tokio::join!(api.edition(7321), api.edition(7321), api.edition(7321));
+    for edition in [a, b, c] {
+        assert_eq!(edition.unwrap().id, 7321);
+    }
+}
+
+#[tokio::test]
+async fn refresh_without_login_fails() {
+    let server = MockServer::start().await;
+    let result = api(&server, &temp_dir()).refresh().await;
+    assert!(matches!(result, Err(Error::NotLoggedIn)));
+}
+
+#[tokio::test]
+async fn server_errors_are_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/posts/la-manovra-divide-il-governo/download/epub"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/wp/posts/la-manovra-divide-il-governo/download/epub"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"PK epub".to_vec()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let api = api(&server, &temp_dir()).with_login(login_fixture());
+    let epub = api.download_epub("la-manovra-divide-il-governo").await.unwrap();
+    assert_eq!(&epub[..], b"PK epub");
+}
+
+#[tokio::test]
+async fn error_statuses_are_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321"))
+        .respond_with(ResponseTemplate::new(404).set_body_raw("{}", "application/json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let api = api(&server, &temp_dir()).with_login(login_fixture());
+    match api.edition(7321).await {
+        Err(Error::Http(e)) => assert_eq!(e.status(), Some(StatusCode::NOT_FOUND)),
+        res => panic!("expected a 404 error, got {:?}", res.map(|edition| edition.id)),
+    }
+}
+
+#[tokio::test]
+async fn too_many_requests_waits_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321"))
+        .respond_with(ResponseTemplate::new(429).insert_header(RETRY_AFTER, "1"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/wp/editions/7321"))
+        .respond_with(json_fixture("edition.json"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let api = api(&server, &temp_dir()).with_login(login_fixture());
+    let start = Instant::now();
+    assert_eq!(api.edition(7321).await.unwrap().id, 7321);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn pdf_download_resumes() {
+    let pdf: Vec<u8> = (0..=255).cycle().take(4000).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp/pdfs/slug/edizione-pdf/download"))
+        .and(header(RANGE, "bytes=1000-"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header(CONTENT_RANGE, "bytes 1000-3999/4000")
+                .set_body_bytes(pdf[1000..].to_vec()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = temp_dir();
+    let target = dir.join("edizione.pdf");
+    fs::write(dir.join("edizione.pdf.part"), &pdf[..1000]).unwrap();
+
+    let api = api(&server, &dir).with_login(login_fixture());
+    let content = api.download_pdf("edizione-pdf", &target).await.unwrap();
+    assert_eq!(&content[..], &pdf[..]);
+    assert_eq!(fs::read(&target).unwrap(), pdf);
+    assert!(!dir.join("edizione.pdf.part").exists());
+}
+
+#[tokio::test]
+async fn token_is_not_sent_to_image_servers() {
+    let server = MockServer::start().await;
+    let images = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/2024/03/prima.jpg"))
+        .and(no_authorization)
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"JFIF".to_vec()))
+        .expect(1)
+        .mount(&images)
+        .await;
+
+    let api = api(&server, &temp_dir()).with_login(login_fixture());
+    let image = api.download_image(&format!("{}/2024/03/prima.jpg", images.uri())).await.unwrap();
+    assert_eq!(&image[..], b"JFIF");
+}
+
+#[tokio::test]
+async fn token_is_not_sent_to_look_alike_urls() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api-v2/prima.jpg"))
+        .and(no_authorization)
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"JFIF".to_vec()))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let retry = Retry {
+        attempts: 0,
+        delay: Duration::from_millis(10),
+    };
+    let dir = temp_dir();
+    // the mock server on another port of the same host, and a path next to
+    // the base path
+    let port = Url::parse(&server.uri()).unwrap().port().unwrap();
+    for base_url in ["http://127.0.0.1".to_string(), format!("{}/api", server.uri())] {
+        let api = Api::new(Client::new(), &base_url, retry, dir.join("login.json"), None, None).with_login(login_fixture());
+        let image = api.download_image(&format!("http://127.0.0.1:{}/api-v2/prima.jpg", port)).await.unwrap();
+        assert_eq!(&image[..], b"JFIF");
+    }
+}
@@ -0,0 +1,128 @@
+// Archive of the downloaded article ePUBs.
+// Articles are sometimes corrected during the day: every version is kept,
+// named after the hash of its text, together with an index listing the
+// versions of each article (oldest first).
+use epub::doc::EpubDoc;
+use sha2::{Digest, Sha256};
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+
+use crate::article_chapters;
+use crate::error::Result;
+use crate::verify::Verified;
+
+const INDEX_FILE: &str = "index.json";
+
+pub struct Archive {
+    dir: PathBuf,
+    // article slug -> hashes of its versions
+    index: BTreeMap<String, Vec<String>>,
+}
+
+impl Archive {
+    // Open (or create) the archive of an edition
+    pub fn open(root: &Path, edition_slug: &str) -> Result<Archive> {
+        let dir = root.join(edition_slug);
+        fs::create_dir_all(&dir)?;
+
+        let index_path = dir.join(INDEX_FILE);
+        let index = if index_path.is_file() {
+            serde_json::from_reader(BufReader::new(File::open(index_path)?))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Archive { dir, index })
+    }
+
+    // Store a downloaded article ePUB.
+    // Returns true when its text differs from the last stored version.
+    pub fn store(&mut self, post_slug: &str, content: &[u8]) -> io::Result<bool> {
+        let hash = content_hash(content);
+        let versions = self.index.entry(post_slug.to_string()).or_default();
+
+        if versions.last() == Some(&hash) {
+            return Ok(false);
+        }
+
+        let updated = !versions.is_empty();
+        fs::write(version_path(&self.dir, post_slug, &hash), content)?;
+        versions.push(hash);
+
+        Ok(updated)
+    }
+
+    // Last version of each article
+    pub fn latest(&self) -> Vec<(&str, PathBuf)> {
+        self.index
+            .iter()
+            .filter_map(|(slug, versions)| Some((slug.as_str(), version_path(&self.dir, slug, versions.last()?))))
+            .collect()
+    }
+
+    // Checksums of the files downloaded for the edition, in sha256sum format
+    pub fn save_checksums(&self, verified: &[Verified]) -> io::Result<()> {
+        if verified.is_empty() {
+            return Ok(());
+        }
+
+        let mut files: Vec<&Verified> = verified.iter().collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let sums: String = files
+            .iter()
+            .map(|file| format!("{}  {}\n", file.sha256, file.name))
+            .collect();
+        fs::write(self.dir.join("SHA256SUMS"), sums)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = File::create(self.dir.join(INDEX_FILE))?;
+        serde_json::to_writer_pretty(file, &self.index)?;
+        Ok(())
+    }
+}
+
+// Slugs of the archived editions
+pub fn editions(root: &Path) -> Result<Vec<String>> {
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut editions = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.path().join(INDEX_FILE).is_file() {
+            editions.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    editions.sort();
+    Ok(editions)
+}
+
+fn version_path(dir: &Path, post_slug: &str, hash: &str) -> PathBuf {
+    dir.join(format!("{}-{}.epub", post_slug, &hash[..12.min(hash.len())]))
+}
+
+// Hash of the article text, all of its chapters. The ePUB container itself
+// cannot be used since it changes (e.g. timestamps) even when the article
+// does not; it is only hashed when it cannot be read.
+fn content_hash(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    match EpubDoc::from_reader(Cursor::new(content)).map(|mut doc| article_chapters(&mut doc)) {
+        Ok(Ok(chapters)) => {
+            for (_, chapter) in chapters {
+                hasher.update(chapter.as_bytes());
+            }
+        }
+        _ => hasher.update(content),
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
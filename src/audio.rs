@@ -0,0 +1,196 @@
+// Audio edition (--audio m4b|mp3): the articles of the single ePUB file read
+// by a local text-to-speech engine, piper by default, and joined by ffmpeg in
+// one audiobook, with a chapter for each article named after its title and
+// the cover of the edition as artwork. The engine is run once for each
+// article with the text on its input; {output} in --tts-command is the WAV
+// file it writes.
+use clap::ValueEnum;
+use epub::doc::EpubDoc;
+
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use log::info;
+
+use crate::error::{FileContext, Result};
+use crate::text;
+use crate::xhtml::{self, plain};
+use crate::{Edition, Post};
+
+pub const PIPER: &str = "piper --model it_IT-paola-medium --output_file {output}";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // audiobook, with chapters in most players
+    M4b,
+    Mp3,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::M4b => "m4b",
+            Format::Mp3 => "mp3",
+        }
+    }
+}
+
+// The audiobook in `path`, the files of the articles are made in `work_dir`
+// and removed. Returns its duration
+pub fn write<'a>(
+    edition: &Edition,
+    posts: impl Iterator<Item = &'a Post>,
+    epub: &[u8],
+    format: Format,
+    tts_command: &str,
+    work_dir: &Path,
+    path: &Path,
+) -> Result<Duration> {
+    let result = audiobook(edition, posts.collect(), epub, format, tts_command, work_dir, path);
+    if work_dir.exists() {
+        std::fs::remove_dir_all(work_dir).with_path(work_dir)?;
+    }
+    result
+}
+
+fn audiobook(
+    edition: &Edition,
+    posts: Vec<&Post>,
+    epub: &[u8],
+    format: Format,
+    tts_command: &str,
+    work_dir: &Path,
+    path: &Path,
+) -> Result<Duration> {
+    // not left from a previous run when reading fails
+    if path.exists() {
+        std::fs::remove_file(path).with_path(path)?;
+    }
+    std::fs::create_dir_all(work_dir).with_path(work_dir)?;
+
+    let title = plain(&edition.title);
+    let mut list = String::new();
+    let mut chapters = format!(";FFMETADATA1\ntitle={}\nartist=il manifesto\n", metadata(&title));
+    let mut start = Duration::ZERO;
+    for (number, page) in text::pages(epub)?.iter().enumerate() {
+        let Some(post) = posts.iter().find(|post| post.slug == page.slug) else {
+            continue;
+        };
+        info!("Reading {} aloud", post.slug);
+        let text_path = work_dir.join(format!("{:03}.txt", number + 1));
+        let wav_path = work_dir.join(format!("{:03}.wav", number + 1));
+        std::fs::write(&text_path, text::text(xhtml::body(&page.xhtml).unwrap_or_default())).with_path(&text_path)?;
+        speak(tts_command, &text_path, &wav_path)?;
+
+        let end = start + wav_duration(&wav_path)?;
+        list.push_str(&format!("file '{}'\n", wav_path.display().to_string().replace('\'', r"'\''")));
+        chapters.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            start.as_millis(),
+            end.as_millis(),
+            metadata(&plain(&post.title))
+        ));
+        start = end;
+    }
+    if list.is_empty() {
+        return Err("no article to read".into());
+    }
+
+    let list_path = work_dir.join("list.txt");
+    std::fs::write(&list_path, list).with_path(&list_path)?;
+    let chapters_path = work_dir.join("chapters.txt");
+    std::fs::write(&chapters_path, chapters).with_path(&chapters_path)?;
+
+    let mut command = Command::new("ffmpeg");
+    command.args(["-y", "-loglevel", "error", "-f", "concat", "-safe", "0", "-i"]);
+    command.arg(&list_path).arg("-i").arg(&chapters_path);
+    let cover = EpubDoc::from_reader(Cursor::new(epub))?.get_cover().ok();
+    if let Some(cover) = cover {
+        let cover_path = work_dir.join("cover.jpg");
+        std::fs::write(&cover_path, cover).with_path(&cover_path)?;
+        command.arg("-i").arg(&cover_path);
+        command.args(["-map", "2:v", "-c:v", "copy", "-disposition:v:0", "attached_pic"]);
+    }
+    command.args(["-map", "0:a", "-map_metadata", "1", "-map_chapters", "1"]);
+    match format {
+        Format::M4b => command.args(["-c:a", "aac", "-b:a", "64k", "-f", "mp4"]),
+        Format::Mp3 => command.args(["-c:a", "libmp3lame", "-b:a", "64k", "-id3v2_version", "3", "-f", "mp3"]),
+    };
+    command.arg(path);
+    run(&mut command, "ffmpeg")?;
+    Ok(start)
+}
+
+// The text file read aloud in the WAV file
+fn speak(tts_command: &str, text_path: &Path, wav_path: &Path) -> Result<()> {
+    let output = wav_path.display().to_string();
+    let mut words = tts_command.split_whitespace().map(|word| word.replace("{output}", &output));
+    let program = words.next().ok_or("empty text-to-speech command")?;
+    let mut command = Command::new(&program);
+    command
+        .args(words)
+        .stdin(File::open(text_path).with_path(text_path)?)
+        .stdout(Stdio::null());
+    run(&mut command, &program)?;
+    if !wav_path.exists() {
+        return Err(format!("{} wrote no {}", program, wav_path.display()).into());
+    }
+    Ok(())
+}
+
+fn run(command: &mut Command, program: &str) -> Result<()> {
+    let result = command.output().map_err(|e| format!("cannot run {}: {}", program, e))?;
+    if !result.status.success() {
+        let error = String::from_utf8_lossy(&result.stderr);
+        let error = error.trim().lines().last().unwrap_or_default();
+        return Err(format!("{} failed ({}): {}", program, result.status, error).into());
+    }
+    Ok(())
+}
+
+// Length of a WAV file, from its format and data chunks
+fn wav_duration(path: &Path) -> Result<Duration> {
+    let content = std::fs::read(path).with_path(path)?;
+    if content.len() < 12 || &content[..4] != b"RIFF" || &content[8..12] != b"WAVE" {
+        return Err(format!("{}: not a WAV file", path.display()).into());
+    }
+
+    let mut byte_rate = None;
+    let mut data = None;
+    let mut at = 12;
+    while at + 8 <= content.len() {
+        let size = u32::from_le_bytes([content[at + 4], content[at + 5], content[at + 6], content[at + 7]]) as usize;
+        let body = at + 8;
+        match &content[at..at + 4] {
+            b"fmt " if body + 12 <= content.len() => {
+                let rate = &content[body + 8..body + 12];
+                byte_rate = Some(u32::from_le_bytes([rate[0], rate[1], rate[2], rate[3]]));
+            }
+            // engines writing a stream leave the size unknown (0xFFFFFFFF)
+            b"data" => data = Some(size.min(content.len() - body)),
+            _ => {}
+        }
+        at = body.saturating_add(size).saturating_add(size % 2);
+    }
+
+    match (byte_rate, data) {
+        (Some(rate), Some(size)) if rate > 0 => Ok(Duration::from_secs_f64(size as f64 / rate as f64)),
+        _ => Err(format!("{}: invalid WAV file", path.display()).into()),
+    }
+}
+
+// Value of the metadata file of ffmpeg, where = ; # \ and line breaks are
+// escaped
+fn metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
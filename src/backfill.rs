@@ -0,0 +1,49 @@
+// Editions missed by the previous runs (the program was not started, or the
+// run failed), found going back from the latest one.
+// The API has no documented listing of editions: previous editions are asked
+// by id, which grows by one for each edition.
+use chrono::{Duration, Utc};
+
+use log::{debug, info};
+
+use crate::api::ApiClient;
+use crate::error::Result;
+use crate::history::History;
+use crate::Edition;
+
+// Editions of the last `days` days without a record in the history, oldest first
+pub async fn missed(api: &impl ApiClient, history: &History, latest: &Edition, days: i64) -> Result<Vec<Edition>> {
+    if days <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let oldest = Utc::now().date_naive() - Duration::days(days);
+
+    let mut missed = Vec::new();
+    // at most one edition a day
+    for id in (latest.id - days as i32..latest.id).rev() {
+        if id <= 0 {
+            break;
+        }
+        let edition = match api.edition(id).await {
+            Ok(edition) => edition,
+            Err(e) => {
+                debug!("Edition {} not available: {}", id, e);
+                break;
+            }
+        };
+
+        if edition.day().is_some_and(|date| date < oldest) {
+            break;
+        }
+        if !history.contains(&edition.slug)? {
+            missed.push(edition);
+        }
+    }
+
+    if !missed.is_empty() {
+        info!("{} editions missed in the last {} days", missed.len(), days);
+    }
+    missed.reverse();
+    Ok(missed)
+}
@@ -0,0 +1,100 @@
+// Import of the produced e-books in a calibre library with calibredb: the
+// ePUB (or the first other format) is added as a book with the metadata of
+// the edition, the other formats are added to the same book. The book has the
+// identifier ilmanifesto:<edition id>, so that a run done again on the same
+// edition replaces its files instead of adding a copy. calibredb cannot write
+// to a library open in calibre: give the content server instead (e.g.
+// http://localhost:8080/#library), with its user in ~/.config/calibre.
+use std::path::PathBuf;
+use std::process::Command;
+
+use log::info;
+
+use crate::error::Result;
+use crate::output;
+use crate::xhtml::plain;
+use crate::Edition;
+
+const AUTHOR: &str = "il Manifesto";
+const SERIES: &str = "il manifesto";
+
+pub struct Calibre<'a> {
+    // directory or content server URL
+    pub library: &'a str,
+    // default: calibredb in the PATH
+    pub calibredb: Option<&'a str>,
+}
+
+impl Calibre<'_> {
+    pub fn add(&self, edition: &Edition, files: &[PathBuf]) -> Result<()> {
+        // a book has one file of each format: Kobo files would be a second
+        // ePUB, the copies of the PDF (cropped, split) other PDFs
+        let mut books: Vec<&PathBuf> = Vec::new();
+        for file in files.iter().filter(|file| output::is_ebook(file) && !file.to_string_lossy().ends_with(".kepub.epub")) {
+            if !books.iter().any(|book| book.extension() == file.extension()) {
+                books.push(file);
+            }
+        }
+        if books.is_empty() {
+            return Ok(());
+        }
+        let identifier = format!("ilmanifesto:{}", edition.id);
+
+        let search = format!("identifiers:\"={}\"", identifier.replacen(':', ":=", 1));
+        let formats = match self.calibredb(&["search", "--limit", "1", &search])? {
+            Some(id) => {
+                let id = id.trim().to_string();
+                info!("Replacing the files of {} in the calibre library (book {})", edition.slug, id);
+                books.into_iter().map(|book| (id.clone(), book)).collect::<Vec<_>>()
+            }
+            None => {
+                // the ePUB first, calibre reads its metadata (date, cover, series)
+                books.sort_by_key(|book| !book.to_string_lossy().to_lowercase().ends_with(".epub"));
+                let title = plain(&edition.title);
+                let mut args = vec!["add", "--duplicates", "--title", &title, "--authors", AUTHOR, "--languages", "ita"];
+                let index = edition.day().map(|day| day.format("%Y%m%d").to_string());
+                if let Some(index) = &index {
+                    args.extend(["--series", SERIES, "--series-index", index]);
+                }
+                args.extend(["--identifier", &identifier]);
+                let first = books[0].to_string_lossy();
+                args.push(&first);
+                let output = self.calibredb(&args)?.unwrap_or_default();
+                // Added book ids: 12
+                let id = output
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Added book ids:"))
+                    .map(|ids| ids.trim().to_string())
+                    .ok_or_else(|| format!("calibredb did not add {}: {}", first, output.trim()))?;
+                info!("Added {} to the calibre library (book {})", edition.slug, id);
+                books[1..].iter().map(|book| (id.clone(), *book)).collect()
+            }
+        };
+        for (id, book) in formats {
+            self.calibredb(&["add_format", &id, &book.to_string_lossy()])?;
+        }
+        Ok(())
+    }
+
+    // The output, None when a search finds nothing
+    fn calibredb(&self, args: &[&str]) -> Result<Option<String>> {
+        let mut words = self.calibredb.unwrap_or("calibredb").split_whitespace();
+        let program = words.next().ok_or("empty calibredb command")?;
+        let result = Command::new(program)
+            .args(words)
+            .args(&args[..1])
+            .args(["--with-library", self.library])
+            .args(&args[1..])
+            .output()
+            .map_err(|e| format!("cannot run {}: {}", program, e))?;
+        let error = String::from_utf8_lossy(&result.stderr);
+        if !result.status.success() {
+            if args[0] == "search" && error.contains("No books") {
+                return Ok(None);
+            }
+            let error = error.trim().lines().last().unwrap_or_default();
+            return Err(format!("calibredb {} failed ({}): {}", args[0], result.status, error).into());
+        }
+        Ok(Some(String::from_utf8_lossy(&result.stdout).to_string()))
+    }
+}
@@ -0,0 +1,92 @@
+// Comic book archive of the PDF (<name>.cbz): each page drawn by Ghostscript
+// as a JPEG image, for e-ink readers and comic apps that handle a large PDF
+// slowly. ComicInfo.xml gives the title and the date of the edition to the
+// apps that read it.
+use chrono::Datelike;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use log::debug;
+
+use crate::error::{Error, FileContext, Result};
+use crate::pdf;
+use crate::xhtml::{escape, plain};
+use crate::Edition;
+
+pub struct Options<'a> {
+    // resolution of the pages, in dots per inch
+    pub dpi: u32,
+    // shades of gray, smaller files for e-ink screens
+    pub gray: bool,
+    pub ghostscript: Option<&'a str>,
+}
+
+// The pages are drawn in `work_dir`, removed at the end
+pub fn convert(pdf: &Path, output: &Path, edition: &Edition, options: &Options, work_dir: &Path) -> Result<()> {
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir).with_path(work_dir)?;
+    }
+    fs::create_dir_all(work_dir).with_path(work_dir)?;
+    let result = archive(pdf, output, edition, options, work_dir);
+    let _ = fs::remove_dir_all(work_dir);
+    result
+}
+
+fn archive(pdf: &Path, output: &Path, edition: &Edition, options: &Options, work_dir: &Path) -> Result<()> {
+    let mut ghostscript = pdf::ghostscript(options.ghostscript)?;
+    ghostscript
+        .arg(if options.gray { "-sDEVICE=jpeggray" } else { "-sDEVICE=jpeg" })
+        .arg(format!("-r{}", options.dpi))
+        .args(["-dJPEGQ=85", "-dTextAlphaBits=4", "-dGraphicsAlphaBits=4"])
+        .arg(format!("-sOutputFile={}", work_dir.join("%04d.jpg").display()))
+        .arg(pdf);
+    pdf::run(ghostscript)?;
+
+    let mut pages: Vec<_> = fs::read_dir(work_dir)
+        .with_path(work_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "jpg"))
+        .collect();
+    if pages.is_empty() {
+        return Err(format!("no pages drawn from {}", pdf.display()).into());
+    }
+    // 0001.jpg, 0002.jpg...
+    pages.sort();
+    debug!("{} pages drawn from {}", pages.len(), pdf.display());
+
+    let file = File::create(output).with_path(output)?;
+    let mut writer = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    writer.start_file("ComicInfo.xml", options).map_err(zip_error)?;
+    writer.write_all(comic_info(edition, pages.len()).as_bytes())?;
+    // JPEG images do not get smaller
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for page in &pages {
+        let name = page.file_name().unwrap_or_default().to_string_lossy();
+        writer.start_file(name, options).map_err(zip_error)?;
+        writer.write_all(&fs::read(page).with_path(page)?)?;
+    }
+    writer.finish().map_err(zip_error)?;
+    Ok(())
+}
+
+fn comic_info(edition: &Edition, pages: usize) -> String {
+    let mut info = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ComicInfo>\n");
+    info.push_str(&format!("  <Title>{}</Title>\n", escape(&plain(&edition.title))));
+    info.push_str("  <Series>il manifesto</Series>\n");
+    if let Some(day) = edition.day() {
+        info.push_str(&format!("  <Number>{}</Number>\n", day.format("%Y%m%d")));
+        info.push_str(&format!("  <Year>{}</Year>\n  <Month>{}</Month>\n  <Day>{}</Day>\n", day.year(), day.month(), day.day()));
+    }
+    info.push_str("  <Publisher>il manifesto</Publisher>\n  <LanguageISO>it</LanguageISO>\n");
+    info.push_str(&format!("  <PageCount>{}</PageCount>\n</ComicInfo>\n", pages));
+    info
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::Other(format!("cannot write the archive: {}", e))
+}
@@ -0,0 +1,41 @@
+// Italian collation of titles and section names, in place of byte order
+// (where "Zona" comes before "economia" and "È" after every other letter).
+// As in the ICU collation of the latin alphabet, letters are compared first
+// without accents and case, then with accents, then with case. Punctuation
+// and quotes are ignored, so «Titolo» sorts with the T.
+use std::cmp::Ordering;
+
+pub fn cmp(a: &str, b: &str) -> Ordering {
+    primary(a)
+        .cmp(&primary(b))
+        .then_with(|| secondary(a).cmp(&secondary(b)))
+        // lowercase first
+        .then_with(|| b.cmp(a))
+}
+
+fn primary(text: &str) -> String {
+    secondary(text).chars().map(base_letter).collect()
+}
+
+fn secondary(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn base_letter(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+        'è' | 'é' | 'ê' | 'ë' => 'e',
+        'ì' | 'í' | 'î' | 'ï' => 'i',
+        'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
+        'ù' | 'ú' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ñ' => 'n',
+        c => c,
+    }
+}
@@ -0,0 +1,116 @@
+// Optional configuration file (config.toml in the platform config directory,
+// e.g. ~/.config/clima on Linux, or the file given with --config).
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{FileContext, Result};
+use crate::filters::{self, Filter};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Pdf,
+    // single ePUB file of the whole edition
+    Epub,
+    // one ePUB file per article
+    Articles,
+    // the single ePUB file as a Kobo ePUB
+    Kepub,
+    // AZW3 copy of the single ePUB file
+    Kindle,
+    // the whole edition as one HTML file
+    Html,
+    // the whole edition as one Markdown file
+    Md,
+    // text of each article
+    Text,
+    // JSON document of the edition
+    Dump,
+    // CSV index of the articles
+    Index,
+    // M4B audiobook of the articles
+    Audio,
+    // the PDF as a comic book archive
+    Cbz,
+}
+
+impl Format {
+    // Formats made from the single ePUB file
+    pub fn needs_single_epub(self) -> bool {
+        !matches!(self, Format::Pdf | Format::Articles | Format::Cbz)
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // produced in a single run when no format is given on the command line
+    pub formats: Vec<Format>,
+    // used when --output-dir is not given
+    pub output_dir: Option<PathBuf>,
+    // used when --backfill is not given
+    pub backfill_days: Option<i64>,
+    // used when --name-template is not given
+    pub name_template: Option<String>,
+    // seconds, used when --connect-timeout and --read-timeout are not given
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    // used when --user-agent is not given
+    pub user_agent: Option<String>,
+    // used when --profile is not given
+    pub profile: Option<String>,
+    // settings for each device, by name
+    pub profiles: HashMap<String, Profile>,
+    // used when --on-success, --on-failure and --on-file are not given
+    pub hooks: Hooks,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub on_file: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    // applied in order to every image of the single ePUB file
+    pub filters: Vec<Filter>,
+}
+
+impl Config {
+    // A file given explicitly must exist, the default one is optional
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path).with_path(&path)?;
+        toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    // Image filters of the profile given on the command line or in the file
+    pub fn filters(&self, profile: Option<&str>) -> Result<Vec<Filter>> {
+        match profile.or(self.profile.as_deref()) {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.filters.clone()),
+                None => Err(format!("profile {} not found in config.toml", name).into()),
+            },
+            None => Ok(filters::DEFAULT.to_vec()),
+        }
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("it", "ilmanifesto", "clima").map(|dirs| dirs.config_dir().join("config.toml"))
+}
@@ -0,0 +1,64 @@
+// Cookies survive between runs in cookies.json in the state directory, so
+// that CDN tokens are not negotiated again every time. The file is written
+// like login.json: encrypted with a passphrase, only readable by the owner.
+use cookie_store::CookieStore;
+use reqwest_cookie_store::CookieStoreMutex;
+
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::{debug, warn};
+
+use crate::error::Result;
+use crate::secret;
+
+pub struct CookieJar {
+    store: Arc<CookieStoreMutex>,
+    path: PathBuf,
+    passphrase: Option<String>,
+}
+
+impl CookieJar {
+    // A missing or unreadable file gives an empty jar, cookies are only an
+    // optimization
+    pub fn load(path: PathBuf, passphrase: Option<&str>) -> CookieJar {
+        let store = if path.is_file() {
+            secret::read(&path, passphrase)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    cookie_store::serde::json::load(Cursor::new(content)).map_err(|e| e.to_string())
+                })
+                .unwrap_or_else(|e| {
+                    warn!("Ignoring cookies in {}: {}", path.display(), e);
+                    CookieStore::default()
+                })
+        } else {
+            CookieStore::default()
+        };
+
+        CookieJar {
+            store: Arc::new(CookieStoreMutex::new(store)),
+            path,
+            passphrase: passphrase.map(str::to_string),
+        }
+    }
+
+    // Cookie store to give to the client
+    pub fn provider(&self) -> Arc<CookieStoreMutex> {
+        Arc::clone(&self.store)
+    }
+
+    // Session cookies are saved as well, expired ones are dropped when loading
+    pub fn save(&self) -> Result<()> {
+        let mut content = Vec::new();
+        {
+            let store = self.store.lock().map_err(|_| "cookie store poisoned")?;
+            cookie_store::serde::json::save_incl_expired_and_nonpersistent(&store, &mut content)
+                .map_err(|e| format!("cannot save cookies: {}", e))?;
+        }
+        secret::write(&self.path, &content, self.passphrase.as_deref())?;
+        debug!("Saved cookies to {}", self.path.display());
+        Ok(())
+    }
+}
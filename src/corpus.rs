@@ -0,0 +1,103 @@
+// The edition as a JSON document (--dump json), a corpus for research: the
+// metadata of the edition and, for each article of the single ePUB file in
+// reading order, its title, kicker, summary, section, authors, the URLs of
+// its photos and its text. Strings are plain text, without markup.
+use clap::ValueEnum;
+use serde::Serialize;
+
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+use crate::text;
+use crate::xhtml::{self, plain};
+use crate::{Edition, Post};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+}
+
+#[derive(Serialize)]
+struct Corpus<'a> {
+    version: u32,
+    edition: EditionData<'a>,
+    articles: Vec<Article<'a>>,
+}
+
+#[derive(Serialize)]
+struct EditionData<'a> {
+    id: i32,
+    slug: &'a str,
+    title: String,
+    date: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Article<'a> {
+    slug: &'a str,
+    title: String,
+    kicker: String,
+    summary: String,
+    section: Option<&'a str>,
+    authors: Vec<String>,
+    images: Vec<&'a str>,
+    text: String,
+}
+
+pub fn write<'a>(edition: &Edition, posts: impl Iterator<Item = &'a Post>, epub: &[u8], path: &Path) -> Result<()> {
+    let posts: Vec<&Post> = posts.collect();
+    let pages = text::pages(epub)?;
+
+    let mut articles = Vec::new();
+    for page in &pages {
+        let Some(post) = posts.iter().find(|post| post.slug == page.slug) else {
+            continue;
+        };
+        let summary = if post.summary.is_empty() { &post.excerpt } else { &post.summary };
+        let mut images: Vec<&str> = [&post.featured_image, &post.cover_image]
+            .into_iter()
+            .flatten()
+            .map(|image| image.src.as_str())
+            .filter(|src| !src.is_empty())
+            .collect();
+        images.dedup();
+
+        articles.push(Article {
+            slug: &post.slug,
+            title: plain(&post.title),
+            kicker: plain(&post.kicker),
+            // sanitize drops scripts with their content
+            summary: plain(&xhtml::sanitize(summary)),
+            section: post.section.as_ref().map(|section| section.name.as_str()),
+            authors: authors(post, &page.xhtml),
+            images,
+            text: text::text(xhtml::body(&page.xhtml).unwrap_or_default()),
+        });
+    }
+
+    let corpus = Corpus {
+        version: 1,
+        edition: EditionData {
+            id: edition.id,
+            slug: &edition.slug,
+            title: plain(&edition.title),
+            date: edition.day().map(|day| day.to_string()),
+        },
+        articles,
+    };
+    let json = serde_json::to_vec_pretty(&corpus)?;
+    std::fs::write(path, json).with_path(path)
+}
+
+// Authors given by the API, or those of the byline taken from the article file
+pub fn authors(post: &Post, page: &str) -> Vec<String> {
+    if !post.authors.is_empty() {
+        return post.authors.iter().map(|author| plain(author)).collect();
+    }
+    let Some(start) = page.find(r#"<p class="byline">"#) else {
+        return Vec::new();
+    };
+    let byline = &page[start..];
+    let byline = &byline[..byline.find("</p>").unwrap_or(byline.len())];
+    plain(byline).split(", ").map(String::from).collect()
+}
@@ -0,0 +1,178 @@
+// Typographic cover for editions without a usable photo: the masthead on a red
+// band, the title of the edition and its date.
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+
+use std::fmt;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::Result;
+
+pub const REGULAR: &[u8] = include_bytes!("../assets/fonts/DejaVuSerif.ttf");
+pub const BOLD: &[u8] = include_bytes!("../assets/fonts/DejaVuSerif-Bold.ttf");
+
+pub const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 1600;
+const MARGIN: f32 = 80.0;
+
+const RED: Rgb<u8> = Rgb([204, 0, 0]);
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+const BLACK: Rgb<u8> = Rgb([20, 20, 20]);
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum CoverMode {
+    // the photo of the edition, a generated cover if there is none
+    #[default]
+    Auto,
+    // the photo of the edition only
+    Image,
+    // always the generated cover
+    Generated,
+    None,
+    // an image chosen by the user
+    File(PathBuf),
+}
+
+// Anything else than the modes is the path of an image
+impl FromStr for CoverMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => CoverMode::Auto,
+            "image" => CoverMode::Image,
+            "generated" => CoverMode::Generated,
+            "none" => CoverMode::None,
+            "" => return Err("expected auto, image, generated, none or the path of an image".to_string()),
+            path => CoverMode::File(PathBuf::from(path)),
+        })
+    }
+}
+
+impl fmt::Display for CoverMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoverMode::Auto => write!(f, "auto"),
+            CoverMode::Image => write!(f, "image"),
+            CoverMode::Generated => write!(f, "generated"),
+            CoverMode::None => write!(f, "none"),
+            CoverMode::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// Image chosen by the user shrunk to the size of the generated covers, photos
+// from a camera would make the file a few MB larger
+pub fn fit(img: DynamicImage) -> DynamicImage {
+    if img.width() > WIDTH || img.height() > HEIGHT {
+        img.thumbnail(WIDTH, HEIGHT)
+    } else {
+        img
+    }
+}
+
+// JPEG image of the cover
+pub fn generate(title: &str, date: &str) -> Result<Vec<u8>> {
+    let regular = FontRef::try_from_slice(REGULAR).map_err(|e| e.to_string())?;
+    let bold = FontRef::try_from_slice(BOLD).map_err(|e| e.to_string())?;
+
+    let mut image = RgbImage::from_pixel(WIDTH, HEIGHT, WHITE);
+
+    // masthead
+    fill(&mut image, 0, 0, WIDTH, 380, RED);
+    draw_centered(&mut image, &bold, 150.0, "il manifesto", 250.0, WHITE);
+
+    let mut y = 640.0;
+    for line in wrap(&bold, 84.0, title, WIDTH as f32 - 2.0 * MARGIN) {
+        draw_centered(&mut image, &bold, 84.0, &line, y, BLACK);
+        y += 110.0;
+    }
+
+    fill(&mut image, MARGIN as u32, 1330, WIDTH - 2 * MARGIN as u32, 6, RED);
+    draw_centered(&mut image, &regular, 56.0, date, 1440.0, BLACK);
+
+    let mut jpeg = Cursor::new(Vec::new());
+    image.write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))?;
+    Ok(jpeg.into_inner())
+}
+
+fn fill(image: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+fn text_width(font: &FontRef, size: f32, text: &str) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+// Split the text on words in lines not wider than `width`
+fn wrap(font: &FontRef, size: f32, text: &str, width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{} {}", line, word)
+        };
+        if !line.is_empty() && text_width(font, size, &candidate) > width {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        } else {
+            line = candidate;
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Draw a line of text centered horizontally, `baseline` is the y of its baseline
+fn draw_centered(image: &mut RgbImage, font: &FontRef, size: f32, text: &str, baseline: f32, color: Rgb<u8>) {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let mut x = (image.width() as f32 - text_width(font, size, text)) / 2.0;
+    let mut previous = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(scale, point(x, baseline));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i32 + gx as i32;
+                let py = bounds.min.y as i32 + gy as i32;
+                if px < 0 || py < 0 || px >= image.width() as i32 || py >= image.height() as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(px as u32, py as u32);
+                for i in 0..3 {
+                    let blended = pixel.0[i] as f32 * (1.0 - coverage) + color.0[i] as f32 * coverage;
+                    pixel.0[i] = blended.round() as u8;
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Delivery of the produced files to an e-reader mounted over USB.
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use crate::error::{FileContext, Result};
+
+// Name of the collection editions are added to on Kindles.
+// The suffix is the locale, required by the firmware.
+const KINDLE_COLLECTION: &str = "il manifesto@en-US";
+
+// Where the documents are seen by the Kindle itself
+const KINDLE_DOCUMENTS: &str = "/mnt/us/documents";
+
+enum Device {
+    Kindle,
+    Kobo,
+    Other,
+}
+
+fn detect(mount: &Path) -> Device {
+    if mount.join("system").is_dir() && mount.join("documents").is_dir() {
+        Device::Kindle
+    } else if mount.join(".kobo").is_dir() {
+        Device::Kobo
+    } else {
+        Device::Other
+    }
+}
+
+// Copy files to the device mounted at `mount`
+pub fn deliver(mount: &Path, files: &[PathBuf]) -> Result<()> {
+    let device = detect(mount);
+
+    let target_dir = match device {
+        Device::Kindle => mount.join("documents"),
+        Device::Kobo | Device::Other => mount.to_path_buf(),
+    };
+
+    let mut names = Vec::new();
+    for file in files {
+        let name = file
+            .file_name()
+            .ok_or_else(|| format!("invalid file name {}", file.display()))?
+            .to_string_lossy()
+            .to_string();
+        fs::copy(file, target_dir.join(&name)).with_path(&target_dir)?;
+        info!("Copied {} to {}", name, target_dir.display());
+        names.push(name);
+    }
+
+    if let Device::Kindle = device {
+        let collections = mount.join("system").join("collections.json");
+        if collections.is_file() {
+            update_kindle_collections(&collections, &names)?;
+        } else {
+            info!("This Kindle does not use collections.json, collections not updated");
+        }
+    }
+
+    Ok(())
+}
+
+// Add documents to the collection in system/collections.json (older firmwares).
+// Items are referenced by "*" followed by the SHA1 of their path on the device.
+fn update_kindle_collections(path: &Path, names: &[String]) -> io::Result<()> {
+    let mut collections: Map<String, Value> =
+        serde_json::from_reader(BufReader::new(File::open(path)?)).unwrap_or_default();
+
+    let last_access = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let collection = collections
+        .entry(KINDLE_COLLECTION)
+        .or_insert_with(|| json!({ "items": [], "lastAccess": last_access }));
+
+    if !collection["items"].is_array() {
+        collection["items"] = json!([]);
+    }
+    let items = collection["items"].as_array_mut().unwrap();
+
+    for name in names {
+        let digest = Sha1::digest(format!("{}/{}", KINDLE_DOCUMENTS, name).as_bytes());
+        let id: String = std::iter::once("*".to_string())
+            .chain(digest.iter().map(|b| format!("{:02x}", b)))
+            .collect();
+
+        if !items.iter().any(|item| item.as_str() == Some(id.as_str())) {
+            items.push(Value::String(id));
+        }
+    }
+    collection["lastAccess"] = json!(last_access);
+
+    fs::write(path, serde_json::to_vec(&collections)?)?;
+    info!("Updated Kindle collection {}", KINDLE_COLLECTION);
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+// Upload of the e-books (PDF, ePUB, AZW3 or MOBI, CBZ) to a Google Drive
+// folder, created in My Drive if missing. The OAuth client (Desktop app)
+// is created by the user in the Google Cloud console, with the Drive API
+// enabled; `authorize drive` gets its token. The drive.file scope only gives
+// access to the files and folders made by clima, so a file uploaded again
+// replaces the previous one instead of making a copy.
+use serde::Deserialize;
+use serde_json::json;
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{Error, FileContext, Result};
+use crate::oauth::{Provider, Redirect};
+use crate::output;
+
+const FILES: &str = "https://www.googleapis.com/drive/v3/files";
+const UPLOAD: &str = "https://www.googleapis.com/upload/drive/v3/files";
+const FOLDER: &str = "application/vnd.google-apps.folder";
+
+pub const TOKEN_FILE: &str = "drive.json";
+
+#[derive(Deserialize)]
+struct FileList {
+    files: Vec<File>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    error: ErrorMessage,
+}
+
+#[derive(Deserialize)]
+struct ErrorMessage {
+    message: String,
+}
+
+pub fn provider<'a>(client_id: &'a str, client_secret: &'a str) -> Provider<'a> {
+    Provider {
+        name: "Drive",
+        authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+        token_url: "https://oauth2.googleapis.com/token",
+        redirect: Redirect::Loopback,
+        scope: Some("https://www.googleapis.com/auth/drive.file"),
+        // prompt gives a refresh token again when the app was authorized before
+        offline: &[("access_type", "offline"), ("prompt", "consent")],
+        client_id,
+        client_secret: Some(client_secret),
+    }
+}
+
+pub struct Drive<'a> {
+    pub client: &'a reqwest::Client,
+    pub client_id: &'a str,
+    pub client_secret: &'a str,
+    // name of the folder
+    pub folder: &'a str,
+    pub token_path: PathBuf,
+    pub passphrase: Option<&'a str>,
+}
+
+impl Drive<'_> {
+    // Items that could not be uploaded, with the reason
+    pub async fn deliver(&self, files: &[PathBuf]) -> Vec<(String, Error)> {
+        let (token, folder) = match self.folder().await {
+            Ok(folder) => folder,
+            Err(e) => return vec![(format!("Drive {}", self.folder), e)],
+        };
+        let mut failed = Vec::new();
+        for file in files.iter().filter(|file| output::is_ebook(file)) {
+            match self.upload(&token, &folder, file).await {
+                Ok(()) => info!("Uploaded {} to Drive {}", file.display(), self.folder),
+                Err(e) => failed.push((file.display().to_string(), e)),
+            }
+        }
+        failed
+    }
+
+    // Access token and id of the folder
+    async fn folder(&self) -> Result<(String, String)> {
+        let token = provider(self.client_id, self.client_secret)
+            .access_token(self.client, &self.token_path, self.passphrase)
+            .await?;
+        let query = format!("mimeType = '{}' and name = '{}' and 'root' in parents", FOLDER, escape(self.folder));
+        if let Some(folder) = self.find(&token, &query).await? {
+            return Ok((token, folder));
+        }
+        let metadata = json!({ "name": self.folder, "mimeType": FOLDER });
+        let response = self.client.post(FILES).bearer_auth(&token).json(&metadata).send().await?;
+        let folder: File = checked(response).await?.json().await?;
+        info!("Created the Drive folder {}", self.folder);
+        Ok((token, folder.id))
+    }
+
+    async fn find(&self, token: &str, query: &str) -> Result<Option<String>> {
+        let query = format!("{} and trashed = false", query);
+        let response = self
+            .client
+            .get(FILES)
+            .bearer_auth(token)
+            .query(&[("q", query.as_str()), ("fields", "files(id)"), ("spaces", "drive")])
+            .send()
+            .await?;
+        let list: FileList = checked(response).await?.json().await?;
+        Ok(list.files.into_iter().next().map(|file| file.id))
+    }
+
+    // A resumable upload, the simple one is for files up to 5 MB
+    async fn upload(&self, token: &str, folder: &str, file: &Path) -> Result<()> {
+        let name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = file.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let mime = output::content_type(&extension);
+        let content = std::fs::read(file).with_path(file)?;
+
+        let query = format!("name = '{}' and '{}' in parents", escape(&name), escape(folder));
+        let session = match self.find(token, &query).await? {
+            Some(id) => self.client.patch(format!("{}/{}", UPLOAD, id)).json(&json!({})),
+            None => self.client.post(UPLOAD).json(&json!({ "name": name, "parents": [folder] })),
+        };
+        let response = session
+            .bearer_auth(token)
+            .query(&[("uploadType", "resumable")])
+            .header("X-Upload-Content-Type", mime)
+            .send()
+            .await?;
+        let location = checked(response)
+            .await?
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(String::from)
+            .ok_or("Drive did not give an upload URL")?;
+
+        let response = self
+            .client
+            .put(location)
+            .header(reqwest::header::CONTENT_TYPE, mime)
+            .body(content)
+            .send()
+            .await?;
+        checked(response).await?;
+        Ok(())
+    }
+}
+
+// The message of the error in the body
+async fn checked(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let error = match response.json::<ApiError>().await {
+        Ok(e) => e.error.message,
+        Err(_) => status.to_string(),
+    };
+    Err(format!("Drive refused the request: {}", error).into())
+}
+
+// Strings in the search queries
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
@@ -0,0 +1,115 @@
+// Upload of the PDF, ePUB (also the Kobo one), Kindle and CBZ files to a
+// Dropbox folder, e.g. /Apps/Rakuten Kobo that Kobo e-readers synchronize. The
+// app is created by the user at https://www.dropbox.com/developers/apps with
+// the files.content.write permission; `authorize dropbox` gets its token.
+use serde::Deserialize;
+use serde_json::json;
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{Error, FileContext, Result};
+use crate::oauth::{Provider, Redirect};
+use crate::output;
+
+const UPLOAD: &str = "https://content.dropboxapi.com/2/files/upload";
+
+// Largest file of a single upload request
+const MAX_SIZE: u64 = 150 << 20;
+
+pub const TOKEN_FILE: &str = "dropbox.json";
+
+#[derive(Deserialize)]
+struct ApiError {
+    error_summary: String,
+}
+
+pub fn provider(app_key: &str) -> Provider<'_> {
+    Provider {
+        name: "Dropbox",
+        authorize_url: "https://www.dropbox.com/oauth2/authorize",
+        token_url: "https://api.dropboxapi.com/oauth2/token",
+        redirect: Redirect::Paste,
+        scope: None,
+        offline: &[("token_access_type", "offline")],
+        client_id: app_key,
+        client_secret: None,
+    }
+}
+
+pub struct Dropbox<'a> {
+    pub client: &'a reqwest::Client,
+    pub app_key: &'a str,
+    // e.g. /Apps/Rakuten Kobo
+    pub folder: &'a str,
+    pub token_path: PathBuf,
+    pub passphrase: Option<&'a str>,
+}
+
+impl Dropbox<'_> {
+    // Items that could not be uploaded, with the reason
+    pub async fn deliver(&self, files: &[PathBuf]) -> Vec<(String, Error)> {
+        let token = match provider(self.app_key)
+            .access_token(self.client, &self.token_path, self.passphrase)
+            .await
+        {
+            Ok(token) => token,
+            Err(e) => return vec![("Dropbox".to_string(), e)],
+        };
+        let mut failed = Vec::new();
+        for file in files.iter().filter(|file| output::is_ebook(file)) {
+            match self.upload(&token, file).await {
+                Ok(()) => info!("Uploaded {} to Dropbox {}", file.display(), self.folder),
+                Err(e) => failed.push((file.display().to_string(), e)),
+            }
+        }
+        failed
+    }
+
+    async fn upload(&self, token: &str, file: &Path) -> Result<()> {
+        let size = std::fs::metadata(file).with_path(file)?.len();
+        if size > MAX_SIZE {
+            return Err(format!("{} MB, Dropbox uploads are up to {} MB", size >> 20, MAX_SIZE >> 20).into());
+        }
+        let name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let path = format!("/{}/{}", self.folder.trim_matches('/'), name).replace("//", "/");
+        let content = std::fs::read(file).with_path(file)?;
+
+        let argument = json!({ "path": path, "mode": "overwrite", "mute": true });
+        let response = self
+            .client
+            .post(UPLOAD)
+            .bearer_auth(token)
+            .header("Dropbox-API-Arg", ascii(&argument.to_string()))
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(content)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = match response.json::<ApiError>().await {
+                Ok(e) => e.error_summary,
+                Err(_) => status.to_string(),
+            };
+            return Err(format!("Dropbox refused the file: {}", error).into());
+        }
+        Ok(())
+    }
+}
+
+// HTTP headers are ASCII, Dropbox wants the other characters of the JSON
+// escaped
+fn ascii(json: &str) -> String {
+    let mut escaped = String::new();
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    escaped
+}
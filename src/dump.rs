@@ -0,0 +1,149 @@
+// Copy of the raw API responses (--dump-api), to adapt the deserializers when
+// the publisher changes the API. Every response is saved in two files: the
+// body (NNNN-<path>.body) and its description with URL, status, headers and
+// time (NNNN-<path>.json). Tokens, passwords and cookies are redacted so that
+// the dumps can be shared.
+use bytes::Bytes;
+use chrono::Utc;
+use reqwest::header::HeaderMap;
+use reqwest::{Response, StatusCode};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::warn;
+
+use crate::error::{FileContext, Result};
+
+const REDACTED: &str = "<redacted>";
+
+// Response without its body
+struct Meta {
+    status: StatusCode,
+    url: Url,
+    headers: HeaderMap,
+}
+
+pub struct Dump {
+    dir: PathBuf,
+    // responses saved so far, numbers the files
+    count: AtomicUsize,
+}
+
+impl Dump {
+    pub fn new(dir: &Path) -> Result<Dump> {
+        fs::create_dir_all(dir).with_path(dir)?;
+        Ok(Dump {
+            dir: dir.to_path_buf(),
+            count: AtomicUsize::new(0),
+        })
+    }
+
+    fn save(&self, method: &str, res: &Meta, body: &[u8]) -> Result<()> {
+        let number = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let name = format!("{:04}-{}", number, path_name(res.url.path()));
+
+        let headers: Map<String, Value> = res
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if name == "set-cookie" {
+                    REDACTED.to_string()
+                } else {
+                    value.to_str().unwrap_or_default().to_string()
+                };
+                (name.to_string(), Value::String(value))
+            })
+            .collect();
+
+        // bodies are saved as received, unless they contain secrets
+        let body = match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                if redact(&mut value) {
+                    serde_json::to_vec_pretty(&value)?
+                } else {
+                    body.to_vec()
+                }
+            }
+            Err(_) => body.to_vec(),
+        };
+
+        let description = json!({
+            "time": Utc::now().to_rfc3339(),
+            "method": method,
+            "url": res.url.as_str(),
+            "status": res.status.as_u16(),
+            "headers": headers,
+            "body": format!("{}.body", name),
+            "size": body.len(),
+        });
+
+        let body_path = self.dir.join(format!("{}.body", name));
+        fs::write(&body_path, &body).with_path(&body_path)?;
+        let path = self.dir.join(format!("{}.json", name));
+        fs::write(&path, serde_json::to_vec_pretty(&description)?).with_path(&path)?;
+        Ok(())
+    }
+}
+
+// Read the body of a response, saving it when dumps are enabled.
+// A dump that cannot be written does not stop the run.
+pub async fn read(dump: Option<&Dump>, method: &str, res: Response) -> Result<Bytes> {
+    let Some(dump) = dump else {
+        return Ok(res.bytes().await?);
+    };
+
+    // reading the body consumes the response
+    let meta = Meta {
+        status: res.status(),
+        url: res.url().clone(),
+        headers: res.headers().clone(),
+    };
+    let body = res.bytes().await?;
+
+    if let Err(e) = dump.save(method, &meta, &body) {
+        warn!("Cannot dump API response: {}", e);
+    }
+    Ok(body)
+}
+
+// File name from the URL path: /api/v1/wp/editions/latest -> wp-editions-latest
+fn path_name(path: &str) -> String {
+    let name: String = path
+        .trim_start_matches("/api/v1")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let name = name.trim_matches('-');
+    let name = if name.is_empty() { "root" } else { name };
+    name.chars().take(80).collect()
+}
+
+// Replace the values of secret fields (accessToken, refreshToken, password...),
+// returns true if there were any
+fn redact(value: &mut Value) -> bool {
+    let mut redacted = false;
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if key.contains("token") || key.contains("password") {
+                    *value = Value::String(REDACTED.to_string());
+                    redacted = true;
+                } else {
+                    redacted |= redact(value);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                redacted |= redact(value);
+            }
+        }
+        _ => {}
+    }
+    redacted
+}
@@ -0,0 +1,121 @@
+// Errors reported to the user
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("network error: {}", with_causes(.0))]
+    Http(#[from] reqwest::Error),
+
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("unexpected data: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("PDF error: {0}")]
+    Pdf(#[from] lopdf::Error),
+
+    #[error("cannot read ePUB: {0}")]
+    EpubRead(anyhow::Error),
+
+    #[error("cannot create ePUB: {0}")]
+    EpubWrite(eyre::Report),
+
+    #[error("state database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("invalid encrypted file: {0}")]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("credentials required, use --email and --password or create credentials.json")]
+    CredentialsRequired,
+
+    #[error("not logged in")]
+    NotLoggedIn,
+
+    #[error("unauthorized request to {0}, login again")]
+    Unauthorized(String),
+
+    #[error("too many requests to {url}, the server asked to slow down")]
+    TooManyRequests {
+        url: String,
+        // as asked with the Retry-After header
+        retry_after: Option<std::time::Duration>,
+    },
+
+    #[error("download of {url} stopped at {size} of {expected} bytes")]
+    Incomplete {
+        url: String,
+        size: u64,
+        expected: u64,
+    },
+
+    #[error("{0}")]
+    Other(String),
+}
+
+// reqwest only prints the outer error, the cause (DNS, TLS...) is in the sources
+fn with_causes(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Attach the file path to I/O errors
+pub trait FileContext<T> {
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T>;
+}
+
+impl<T> FileContext<T> for std::io::Result<T> {
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T> {
+        self.map_err(|source| Error::File {
+            path: path.into(),
+            source,
+        })
+    }
+}
+
+// The ePUB crates report errors with anyhow and eyre
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Error::EpubRead(e)
+    }
+}
+
+impl From<eyre::Report> for Error {
+    fn from(e: eyre::Report) -> Self {
+        Error::EpubWrite(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Other(message.to_string())
+    }
+}
@@ -0,0 +1,146 @@
+// Processing of the images added to the single ePUB file, as an ordered chain
+// of filters. The chain is chosen per device profile in config.toml, e.g.
+//
+//   [profiles.kobo]
+//   filters = ["watermark-strip=40", "resize=1072x1448", "grayscale", "sharpen=0.8", "quantize=16"]
+//
+// Without a profile images are only resized to 600x600, as before.
+// --eink adds the EINK filters at the end of the chain.
+use image::{DynamicImage, GrayImage, RgbImage};
+use serde::Deserialize;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Filter {
+    // fit in width x height, keeping the proportions
+    Resize(u32, u32),
+    Grayscale,
+    // brightness of the mid tones, above 1 lighter
+    Gamma(f32),
+    // unsharp mask with the given sigma
+    Sharpen(f32),
+    // levels for each channel, 16 for most e-ink screens
+    Quantize(u8),
+    // rows cut from the bottom, where the agency credits are printed
+    WatermarkStrip(u32),
+}
+
+pub const DEFAULT: &[Filter] = &[Filter::Resize(600, 600)];
+
+// E-ink screens have no colors and show the mid tones darker than LCDs;
+// grayscale JPEG files are also about a third smaller
+pub const EINK: &[Filter] = &[Filter::Grayscale, Filter::Gamma(1.5)];
+
+impl Filter {
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        match *self {
+            Filter::Resize(width, height) => img.thumbnail(width, height),
+            Filter::Grayscale => img.grayscale(),
+            Filter::Gamma(gamma) => map_levels(img, |v| (255.0 * (v as f32 / 255.0).powf(1.0 / gamma)).round() as u8),
+            Filter::Sharpen(sigma) => img.unsharpen(sigma, 0),
+            Filter::Quantize(levels) => quantize(img, levels),
+            Filter::WatermarkStrip(rows) => {
+                // never leave an empty image
+                let height = img.height().saturating_sub(rows).max(1);
+                img.crop_imm(0, 0, img.width(), height)
+            }
+        }
+    }
+}
+
+pub fn apply(filters: &[Filter], img: DynamicImage) -> DynamicImage {
+    filters.iter().fold(img, |img, filter| filter.apply(img))
+}
+
+fn quantize(img: DynamicImage, levels: u8) -> DynamicImage {
+    let levels = levels.max(2) as f32 - 1.0;
+    map_levels(img, |v| ((v as f32 / 255.0 * levels).round() / levels * 255.0) as u8)
+}
+
+// Change every channel of every pixel with `f`, through a table of the 256 levels
+fn map_levels(img: DynamicImage, f: impl Fn(u8) -> u8) -> DynamicImage {
+    let table: Vec<u8> = (0..=255).map(f).collect();
+    let map = |v: &mut u8| *v = table[*v as usize];
+    match img {
+        DynamicImage::ImageLuma8(mut gray) => {
+            gray.iter_mut().for_each(map);
+            DynamicImage::ImageLuma8(gray)
+        }
+        img if img.color().has_color() => {
+            let mut rgb: RgbImage = img.to_rgb8();
+            rgb.iter_mut().for_each(map);
+            DynamicImage::ImageRgb8(rgb)
+        }
+        img => {
+            let mut gray: GrayImage = img.to_luma8();
+            gray.iter_mut().for_each(map);
+            DynamicImage::ImageLuma8(gray)
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        let invalid = || format!("invalid value in image filter {}", s);
+
+        match (name, value) {
+            ("resize", Some(value)) => {
+                let (width, height) = value.split_once('x').unwrap_or((value, value));
+                Ok(Filter::Resize(
+                    width.parse().map_err(|_| invalid())?,
+                    height.parse().map_err(|_| invalid())?,
+                ))
+            }
+            ("grayscale", None) => Ok(Filter::Grayscale),
+            ("gamma", Some(value)) => match value.parse() {
+                Ok(gamma) if gamma > 0.0 => Ok(Filter::Gamma(gamma)),
+                _ => Err(invalid()),
+            },
+            ("sharpen", value) => Ok(Filter::Sharpen(match value {
+                Some(value) => value.parse().map_err(|_| invalid())?,
+                None => 1.0,
+            })),
+            ("quantize", value) => Ok(Filter::Quantize(match value {
+                Some(value) => value.parse().map_err(|_| invalid())?,
+                None => 16,
+            })),
+            ("watermark-strip", Some(value)) => Ok(Filter::WatermarkStrip(value.parse().map_err(|_| invalid())?)),
+            ("resize" | "gamma" | "watermark-strip", None) => Err(format!("image filter {} needs a value", name)),
+            ("grayscale", Some(_)) => Err(format!("image filter {} takes no value", name)),
+            _ => Err(format!(
+                "unknown image filter {}, expected resize, grayscale, gamma, sharpen, quantize or watermark-strip",
+                name
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Resize(width, height) => write!(f, "resize={}x{}", width, height),
+            Filter::Grayscale => write!(f, "grayscale"),
+            Filter::Gamma(gamma) => write!(f, "gamma={}", gamma),
+            Filter::Sharpen(sigma) => write!(f, "sharpen={}", sigma),
+            Filter::Quantize(levels) => write!(f, "quantize={}", levels),
+            Filter::WatermarkStrip(rows) => write!(f, "watermark-strip={}", rows),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// Downloaded editions, articles and produced files, with their checksums, in
+// a SQLite database (state.db) in the state directory.
+// The history.jsonl file of older versions is imported when the database is
+// created.
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{FileContext, Result};
+use crate::Edition;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS editions (
+    slug TEXT PRIMARY KEY,
+    id INTEGER,
+    title TEXT NOT NULL DEFAULT '',
+    date TEXT NOT NULL DEFAULT '',
+    downloaded TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS articles (
+    edition TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    title TEXT NOT NULL,
+    sha256 TEXT,
+    downloaded TEXT NOT NULL,
+    PRIMARY KEY (edition, slug)
+);
+CREATE TABLE IF NOT EXISTS files (
+    time TEXT NOT NULL,
+    edition TEXT NOT NULL,
+    format TEXT NOT NULL,
+    path TEXT NOT NULL,
+    sha256 TEXT,
+    articles INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS files_edition ON files (edition);
+CREATE TABLE IF NOT EXISTS failures (
+    time TEXT NOT NULL,
+    edition TEXT NOT NULL,
+    -- edition, pdf, articles, images
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    error TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS exports (
+    -- readwise, readeck
+    service TEXT NOT NULL,
+    edition TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    time TEXT NOT NULL,
+    PRIMARY KEY (service, slug)
+);
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    pub edition: String,
+    // file extension: pdf, epub
+    pub format: String,
+    pub path: PathBuf,
+    // articles downloaded in the same run
+    #[serde(default)]
+    pub articles: usize,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+pub struct EditionRecord {
+    pub slug: String,
+    pub title: String,
+    pub downloaded: DateTime<Utc>,
+    pub articles: usize,
+}
+
+pub struct Failure {
+    pub time: DateTime<Utc>,
+    pub edition: String,
+    pub kind: String,
+    pub name: String,
+    pub error: String,
+}
+
+// Article downloaded for an edition
+pub struct Article<'a> {
+    pub slug: &'a str,
+    pub title: &'a str,
+    pub sha256: Option<&'a str>,
+}
+
+pub struct History {
+    db: Connection,
+}
+
+impl History {
+    pub fn open(path: &Path, legacy: &Path) -> Result<History> {
+        let created = !path.exists();
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+
+        let history = History { db };
+        if created && legacy.is_file() {
+            history.import(legacy)?;
+        }
+        Ok(history)
+    }
+
+    // Records of history.jsonl, lines that cannot be parsed are skipped
+    fn import(&self, path: &Path) -> Result<()> {
+        let records: Vec<Record> = BufReader::new(File::open(path).with_path(path)?)
+            .lines()
+            .map_while(std::result::Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect();
+
+        let tx = self.db.unchecked_transaction()?;
+        for record in &records {
+            tx.execute(
+                "INSERT OR IGNORE INTO editions (slug, downloaded) VALUES (?1, ?2)",
+                params![record.edition, record.time.to_rfc3339()],
+            )?;
+            insert_file(&tx, record)?;
+        }
+        tx.commit()?;
+
+        info!("Imported {} records from {}", records.len(), path.display());
+        Ok(())
+    }
+
+    // Save the edition with the articles downloaded and the files produced.
+    // Runs downloading only the article files are recorded with format "articles".
+    pub fn record(&self, edition: &Edition, files: &[PathBuf], articles: &[Article]) -> Result<()> {
+        let now = Utc::now();
+        let tx = self.db.unchecked_transaction()?;
+
+        tx.execute(
+            "INSERT INTO editions (slug, id, title, date, downloaded) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (slug) DO UPDATE SET id = ?2, title = ?3, date = ?4, downloaded = ?5",
+            params![edition.slug, edition.id, edition.title, edition.date, now.to_rfc3339()],
+        )?;
+        for article in articles {
+            tx.execute(
+                "INSERT OR REPLACE INTO articles (edition, slug, title, sha256, downloaded)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![edition.slug, article.slug, article.title, article.sha256, now.to_rfc3339()],
+            )?;
+        }
+
+        let mut records: Vec<Record> = files
+            .iter()
+            .map(|path| Record {
+                time: now,
+                edition: edition.slug.clone(),
+                format: path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                path: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+                articles: articles.len(),
+                sha256: checksum(path),
+            })
+            .collect();
+        if records.is_empty() && !articles.is_empty() {
+            records.push(Record {
+                time: now,
+                edition: edition.slug.clone(),
+                format: "articles".to_string(),
+                path: PathBuf::new(),
+                articles: articles.len(),
+                sha256: None,
+            });
+        }
+        for record in &records {
+            insert_file(&tx, record)?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let mut statement = self
+            .db
+            .prepare("SELECT time, edition, format, path, articles, sha256 FROM files ORDER BY time")?;
+        let rows = statement.query_map([], |row| {
+            let time: String = row.get(0)?;
+            let path: String = row.get(3)?;
+            Ok(Record {
+                time: parse_time(&time),
+                edition: row.get(1)?,
+                format: row.get(2)?,
+                path: PathBuf::from(path),
+                articles: row.get(4)?,
+                sha256: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    pub fn record_failure(&self, edition: &str, kind: &str, name: &str, error: &str) -> Result<()> {
+        self.db.execute(
+            "INSERT INTO failures (time, edition, kind, name, error) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![Utc::now().to_rfc3339(), edition, kind, name, error],
+        )?;
+        Ok(())
+    }
+
+    // Editions downloaded (for the last time) since the given time, oldest first
+    pub fn editions(&self, since: DateTime<Utc>) -> Result<Vec<EditionRecord>> {
+        let mut statement = self.db.prepare(
+            "SELECT slug, title, downloaded,
+                 (SELECT COUNT(*) FROM articles WHERE articles.edition = editions.slug)
+             FROM editions WHERE downloaded >= ?1 ORDER BY downloaded",
+        )?;
+        let rows = statement.query_map([since.to_rfc3339()], |row| {
+            let downloaded: String = row.get(2)?;
+            Ok(EditionRecord {
+                slug: row.get(0)?,
+                title: row.get(1)?,
+                downloaded: parse_time(&downloaded),
+                articles: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    pub fn failures(&self, since: DateTime<Utc>) -> Result<Vec<Failure>> {
+        let mut statement = self.db.prepare(
+            "SELECT time, edition, kind, name, error FROM failures WHERE time >= ?1 ORDER BY time",
+        )?;
+        let rows = statement.query_map([since.to_rfc3339()], |row| {
+            let time: String = row.get(0)?;
+            Ok(Failure {
+                time: parse_time(&time),
+                edition: row.get(1)?,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+                error: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    // Titles of the articles downloaded for the edition, by slug
+    pub fn article_titles(&self, edition: &str) -> Result<HashMap<String, String>> {
+        let mut statement = self.db.prepare("SELECT slug, title FROM articles WHERE edition = ?1")?;
+        let rows = statement.query_map([edition], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+
+    // Whether the article has already been sent to the service
+    pub fn exported(&self, service: &str, slug: &str) -> Result<bool> {
+        Ok(self
+            .db
+            .query_row("SELECT 1 FROM exports WHERE service = ?1 AND slug = ?2", [service, slug], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    pub fn record_export(&self, service: &str, edition: &str, slug: &str) -> Result<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO exports (service, edition, slug, time) VALUES (?1, ?2, ?3, ?4)",
+            params![service, edition, slug, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    // Whether the edition has already been downloaded
+    pub fn contains(&self, edition: &str) -> Result<bool> {
+        Ok(self
+            .db
+            .query_row("SELECT 1 FROM editions WHERE slug = ?1", [edition], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    // Most recent file still on disk, optionally of the given format
+    pub fn latest(&self, format: Option<&str>) -> Result<Option<Record>> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|record| format.is_none_or(|format| record.format == format))
+            .filter(|record| record.path.is_file())
+            .max_by_key(|record| record.time))
+    }
+}
+
+fn insert_file(db: &Connection, record: &Record) -> Result<()> {
+    db.execute(
+        "INSERT INTO files (time, edition, format, path, sha256, articles) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            record.time.to_rfc3339(),
+            record.edition,
+            record.format,
+            record.path.to_string_lossy(),
+            record.sha256,
+            record.articles,
+        ],
+    )?;
+    Ok(())
+}
+
+fn parse_time(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_default()
+}
+
+fn checksum(path: &Path) -> Option<String> {
+    let content = std::fs::read(path).ok()?;
+    Some(
+        Sha256::digest(content)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    )
+}
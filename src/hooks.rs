@@ -0,0 +1,84 @@
+// Commands run after each edition, to plug in other programs: on_file once
+// for every produced file (its path as argument), then on_success (all the
+// paths as arguments), or on_failure when the edition cannot be downloaded.
+// They are run by the shell (sh -c, cmd /C on Windows) with the edition in
+// the environment: CLIMA_EVENT (success, failure, file), CLIMA_EDITION (the
+// slug), CLIMA_EDITION_ID, CLIMA_TITLE, CLIMA_DATE, CLIMA_FILES (the paths,
+// one per line), CLIMA_FILE and CLIMA_ERROR. A failing hook is only logged.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::{debug, warn};
+
+use crate::notify::Outcome;
+use crate::xhtml::plain;
+use crate::Edition;
+
+pub struct Hooks {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    pub on_file: Option<String>,
+}
+
+impl Hooks {
+    // The edition is not known when the run fails before getting it
+    pub fn run(&self, edition: Option<&Edition>, result: Outcome<'_>) {
+        match result {
+            Ok(files) => {
+                if let Some(hook) = &self.on_file {
+                    for file in files {
+                        let mut command = shell(hook, &[file.as_path()]);
+                        command.env("CLIMA_FILE", file);
+                        execute("on_file", "file", command, edition, files);
+                    }
+                }
+                if let Some(hook) = &self.on_success {
+                    let paths: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+                    execute("on_success", "success", shell(hook, &paths), edition, files);
+                }
+            }
+            Err(e) => {
+                if let Some(hook) = &self.on_failure {
+                    let mut command = shell(hook, &[]);
+                    command.env("CLIMA_ERROR", e.to_string());
+                    execute("on_failure", "failure", command, edition, &[]);
+                }
+            }
+        }
+    }
+}
+
+fn execute(name: &str, event: &str, mut command: Command, edition: Option<&Edition>, files: &[PathBuf]) {
+    command.env("CLIMA_EVENT", event);
+    if let Some(edition) = edition {
+        command
+            .env("CLIMA_EDITION", &edition.slug)
+            .env("CLIMA_EDITION_ID", edition.id.to_string())
+            .env("CLIMA_TITLE", plain(&edition.title))
+            .env("CLIMA_DATE", edition.day().map(|day| day.to_string()).unwrap_or_default());
+    }
+    let paths: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+    command.env("CLIMA_FILES", paths.join("\n"));
+
+    debug!("Running the {} hook", name);
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("The {} hook failed ({})", name, status),
+        Err(e) => warn!("Cannot run the {} hook: {}", name, e),
+    }
+}
+
+// The arguments are $1, $2... of the command
+fn shell(hook: &str, args: &[&Path]) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook).arg("il_manifesto");
+        command
+    };
+    command.args(args);
+    command
+}
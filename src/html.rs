@@ -0,0 +1,127 @@
+// The whole edition in one HTML file, made from the single ePUB file, for
+// reading in a browser or archiving with tools that do not know ePUB. The
+// pages follow the reading order, each in a <section> of its own (printed on
+// a new page); the stylesheet and the images are inside the file (as data:
+// URIs), links between the pages point to their sections.
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use epub::doc::EpubDoc;
+
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+use crate::error::Result;
+use crate::xhtml;
+
+macro_rules! HTML {
+    () => {
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+<meta charset="utf-8" />
+<title>{title}</title>
+<style>
+{stylesheet}section.page {{
+  break-before: page;
+}}
+</style>
+</head>
+<body>
+{pages}</body>
+</html>
+"#
+    };
+}
+
+pub fn render(epub: &[u8]) -> Result<String> {
+    let mut doc = EpubDoc::from_reader(Cursor::new(epub))?;
+    let stylesheet = doc.get_resource_str_by_path("OEBPS/stylesheet.css").unwrap_or_default();
+
+    let mut pages = String::new();
+    for id in doc.spine.clone() {
+        let Some((path, mime)) = doc.resources.get(&id).cloned() else {
+            continue;
+        };
+        if mime != "application/xhtml+xml" {
+            continue;
+        }
+        let page = doc.get_resource_str(&id)?;
+        let Some(body) = xhtml::body(&page) else {
+            continue;
+        };
+        let body = inline(&mut doc, &path, body);
+        pages.push_str(&format!(r#"<section class="page" id="{}">{}</section>"#, page_id(&path), body));
+        pages.push('\n');
+    }
+
+    Ok(format!(
+        HTML!(),
+        lang = xhtml::escape(&doc.mdata("language").unwrap_or_default()),
+        title = xhtml::escape(&doc.mdata("title").unwrap_or_default()),
+        stylesheet = stylesheet,
+        pages = pages
+    ))
+}
+
+// Anchor of the section of a page
+fn page_id(path: &Path) -> String {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    format!("page-{}", stem)
+}
+
+// The body of a page with its images (and SVG tables) as data: URIs and the
+// links to the other pages pointing to their sections
+fn inline<R: Read + Seek>(doc: &mut EpubDoc<R>, page: &Path, body: &str) -> String {
+    let mut output = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some((start, end)) = xhtml::next_tag(rest) {
+        output.push_str(&rest[..start]);
+        let mut tag = rest[start..=end].to_string();
+        rest = &rest[end + 1..];
+
+        for name in ["src", "data", "href"] {
+            let Some(value) = xhtml::attribute(&tag, name).filter(|value| !value.contains(':') && !value.starts_with('#'))
+            else {
+                continue;
+            };
+            let at = value.as_ptr() as usize - tag.as_ptr() as usize;
+            let length = value.len();
+            let replacement = match name {
+                "href" => link(value),
+                _ => data_uri(doc, page, value),
+            };
+            if let Some(replacement) = replacement {
+                tag.replace_range(at..at + length, &replacement);
+            }
+        }
+
+        // <a id="x"/> would be left open by HTML parsers
+        let name = xhtml::tag_name(&tag).to_ascii_lowercase();
+        if tag.ends_with("/>") && !xhtml::VOID_TAGS.contains(&name.as_str()) {
+            let open = tag[..tag.len() - 2].trim_end();
+            output.push_str(&format!("{}></{}>", open, name));
+        } else {
+            output.push_str(&tag);
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn link(href: &str) -> Option<String> {
+    let (file, fragment) = href.split_once('#').unwrap_or((href, ""));
+    if !fragment.is_empty() {
+        Some(format!("#{}", fragment))
+    } else if file.ends_with(".xhtml") {
+        Some(format!("#{}", page_id(Path::new(file))))
+    } else {
+        None
+    }
+}
+
+fn data_uri<R: Read + Seek>(doc: &mut EpubDoc<R>, page: &Path, src: &str) -> Option<String> {
+    let path = xhtml::resolve(page, src);
+    let content = doc.get_resource_by_path(&path).ok()?;
+    let mime = doc.get_resource_mime_by_path(&path).ok()?;
+    Some(format!("data:{};base64,{}", mime, BASE64.encode(content)))
+}
@@ -0,0 +1,151 @@
+// Cache of the API responses and images, keyed by URL, with their ETag and
+// Last-Modified headers. Later requests of the same URL are conditional: the
+// server answers 304 Not Modified without body if nothing changed, so that
+// running the program again the same day downloads almost nothing.
+// It is kept in the `http` folder of the cache directory, which survives the
+// removal of the articles after building the single ePUB file. Entries not
+// used for MAX_AGE are removed.
+use bytes::Bytes;
+use directories::ProjectDirs;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+// Folder of the cache in the cache directory
+pub const DIR_NAME: &str = "http";
+
+// Entries not requested for this long are removed
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+impl HttpCache {
+    // Cache in the given cache directory, or in the platform one
+    // (e.g. ~/.cache/clima/http on Linux)
+    pub fn new(cache_dir: Option<&Path>) -> HttpCache {
+        let dir = match cache_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => ProjectDirs::from("it", "ilmanifesto", "clima")
+                .map(|dirs| dirs.cache_dir().to_path_buf())
+                .unwrap_or_else(|| std::env::temp_dir().join("clima-rs-cache")),
+        };
+        let cache = HttpCache {
+            dir: dir.join(DIR_NAME),
+        };
+        cache.prune();
+        cache
+    }
+
+    // Remove the entries older than MAX_AGE, and the descriptions left
+    // without body
+    fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let body_path = path.with_extension("body");
+            let expired = fs::metadata(&body_path)
+                .and_then(|metadata| metadata.modified())
+                .map(|modified| now.duration_since(modified).unwrap_or_default() > MAX_AGE)
+                .unwrap_or(true);
+            if expired {
+                if let Err(e) = fs::remove_file(&path) {
+                    warn!("Cannot remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    // Files of the entry of an URL: description and body
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let hash: String = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        (
+            self.dir.join(format!("{}.json", hash)),
+            self.dir.join(format!("{}.body", hash)),
+        )
+    }
+
+    fn entry(&self, url: &str) -> Option<Entry> {
+        let (entry_path, body_path) = self.paths(url);
+        if !body_path.is_file() {
+            return None;
+        }
+        let entry: Entry = serde_json::from_slice(&fs::read(entry_path).ok()?).ok()?;
+        // hash collisions
+        (entry.url == url).then_some(entry)
+    }
+
+    // Headers making the request of a cached URL conditional
+    pub fn conditions(&self, url: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let Some(entry) = self.entry(url) else {
+            return headers;
+        };
+        if let Some(value) = entry.etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+            headers.insert(IF_NONE_MATCH, value);
+        }
+        if let Some(value) = entry.last_modified.and_then(|date| HeaderValue::from_str(&date).ok()) {
+            headers.insert(IF_MODIFIED_SINCE, value);
+        }
+        headers
+    }
+
+    // Body of a response Not Modified
+    pub fn load(&self, url: &str) -> Option<Bytes> {
+        self.entry(url)?;
+        let body_path = self.paths(url).1;
+        // still in use: postpone the expiration
+        let _ = File::options()
+            .write(true)
+            .open(&body_path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        fs::read(body_path).ok().map(Bytes::from)
+    }
+
+    // Save a successful response, if the server sent a validator for it.
+    // The cache is only an optimization: errors are reported and ignored.
+    pub fn store(&self, url: &str, headers: &HeaderMap, body: &[u8]) {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let entry = Entry {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        if entry.etag.is_none() && entry.last_modified.is_none() {
+            return;
+        }
+
+        let (entry_path, body_path) = self.paths(url);
+        let res = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&body_path, body))
+            .and_then(|_| fs::write(&entry_path, serde_json::to_vec(&entry)?));
+        if let Err(e) = res {
+            warn!("Cannot cache {}: {}", url, e);
+        }
+    }
+}
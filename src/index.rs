@@ -0,0 +1,92 @@
+// Index of the articles of an edition (--index csv|json), for reading lists
+// and spreadsheets: one row for each article of the single ePUB file, with
+// its slug, title, section, authors, number of words and page in the reading
+// order (starting from 1).
+use clap::ValueEnum;
+use serde::Serialize;
+
+use std::path::Path;
+
+use crate::corpus;
+use crate::error::{FileContext, Result};
+use crate::text;
+use crate::xhtml::{self, plain};
+use crate::Post;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "index.csv",
+            Format::Json => "index.json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    slug: &'a str,
+    title: String,
+    section: &'a str,
+    author: String,
+    words: usize,
+    page: usize,
+}
+
+const HEADER: [&str; 6] = ["slug", "title", "section", "author", "words", "page"];
+
+pub fn write<'a>(posts: impl Iterator<Item = &'a Post>, epub: &[u8], format: Format, path: &Path) -> Result<()> {
+    let posts: Vec<&Post> = posts.collect();
+    let pages = text::pages(epub)?;
+
+    let rows: Vec<Row> = pages
+        .iter()
+        .filter_map(|page| {
+            let post = posts.iter().find(|post| post.slug == page.slug)?;
+            let text = text::text(xhtml::body(&page.xhtml).unwrap_or_default());
+            Some(Row {
+                slug: &post.slug,
+                title: plain(&post.title),
+                section: post.section.as_ref().map_or("", |section| section.name.as_str()),
+                author: corpus::authors(post, &page.xhtml).join(", "),
+                words: text.split_whitespace().count(),
+                page: page.position + 1,
+            })
+        })
+        .collect();
+
+    let content = match format {
+        Format::Json => serde_json::to_vec_pretty(&rows)?,
+        Format::Csv => {
+            let mut csv = HEADER.join(",") + "\r\n";
+            for row in &rows {
+                let fields = [
+                    field(row.slug),
+                    field(&row.title),
+                    field(row.section),
+                    field(&row.author),
+                    row.words.to_string(),
+                    row.page.to_string(),
+                ];
+                csv.push_str(&fields.join(","));
+                csv.push_str("\r\n");
+            }
+            csv.into_bytes()
+        }
+    };
+    std::fs::write(path, content).with_path(path)
+}
+
+// Quoted when it has commas, quotes or line breaks (RFC 4180)
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
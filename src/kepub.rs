@@ -0,0 +1,154 @@
+// Kobo ePUB (kepub) of the single ePUB file, opened by Kobo devices with
+// their faster reader, which shows reading statistics and the time left in
+// the chapter. The reader finds its place in the text through koboSpan
+// elements: every sentence and image gets one, numbered by paragraph
+// (kobo.<paragraph>.<sentence>). The pages are wrapped in the book-columns and
+// book-inner divs it lays out in columns.
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use std::io::{Cursor, Read, Write};
+
+use crate::error::{Error, Result};
+use crate::xhtml;
+
+// Elements starting a new paragraph of the numbering
+const PARAGRAPHS: &[&str] = &[
+    "p", "h1", "h2", "h3", "h4", "h5", "h6", "li", "dt", "dd", "td", "th", "div", "blockquote", "figcaption", "pre",
+];
+
+// Elements whose text is not split
+const UNSPLIT: &[&str] = &["script", "style", "svg", "math"];
+
+// Added by kepubify and the Kobo store to every page
+const STYLE: &str = r#"<style type="text/css" id="kobostylehacks">div#book-inner { margin-top: 0; margin-bottom: 0; }</style>"#;
+
+pub fn convert(epub: Vec<u8>) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(epub)).map_err(zip_error)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        let name = file.name().to_string();
+        // the navigation document is not read as a page
+        let page = name.ends_with(".xhtml") && !name.ends_with("/nav.xhtml");
+        if !page && !name.ends_with(".opf") {
+            // the mimetype entry stays first and uncompressed
+            writer.raw_copy_file(file).map_err(zip_error)?;
+            continue;
+        }
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let content = if page { spans(&content) } else { cover_property(&content) };
+
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file(name, options).map_err(zip_error)?;
+        writer.write_all(content.as_bytes())?;
+    }
+    Ok(writer.finish().map_err(zip_error)?.into_inner())
+}
+
+// The page with the text of the body in koboSpan elements
+fn spans(page: &str) -> String {
+    let Some(body) = page.find("<body") else {
+        return page.to_string();
+    };
+    let mut output = String::with_capacity(page.len() * 2);
+    let (head, mut rest) = page.split_at(body);
+    match head.rfind("</head>") {
+        Some(end) => {
+            output.push_str(&head[..end]);
+            output.push_str(STYLE);
+            output.push_str(&head[end..]);
+        }
+        None => output.push_str(head),
+    }
+
+    let mut paragraph = 0;
+    let mut sentence = 0;
+    // depth inside the UNSPLIT elements
+    let mut unsplit: u32 = 0;
+    while let Some((start, end)) = xhtml::next_tag(rest) {
+        if unsplit == 0 {
+            push_sentences(&mut output, &rest[..start], paragraph, &mut sentence);
+        } else {
+            output.push_str(&rest[..start]);
+        }
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with("</");
+        let name = xhtml::tag_name(tag).to_ascii_lowercase();
+        if name == "body" && closing {
+            output.push_str("</div></div>");
+        }
+        output.push_str(tag);
+        if name == "body" && !closing {
+            output.push_str(r#"<div id="book-columns"><div id="book-inner">"#);
+        } else if UNSPLIT.contains(&name.as_str()) && !tag.ends_with("/>") {
+            unsplit = if closing { unsplit.saturating_sub(1) } else { unsplit + 1 };
+        } else if PARAGRAPHS.contains(&name.as_str()) && !closing {
+            paragraph += 1;
+            sentence = 0;
+        } else if name == "img" && unsplit == 0 {
+            // the image in a span of its own
+            output.truncate(output.len() - tag.len());
+            sentence += 1;
+            output.push_str(&format!(r#"<span class="koboSpan" id="kobo.{}.{}">{}</span>"#, paragraph, sentence, tag));
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+// Each sentence of the text in a span, the spaces between them are kept
+// outside
+fn push_sentences(output: &mut String, text: &str, paragraph: u32, sentence: &mut u32) {
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_start();
+        output.push_str(&rest[..rest.len() - trimmed.len()]);
+        if trimmed.is_empty() {
+            return;
+        }
+        let text = trimmed[..sentence_end(trimmed)].trim_end();
+        *sentence += 1;
+        output.push_str(&format!(r#"<span class="koboSpan" id="kobo.{}.{}">{}</span>"#, paragraph, *sentence, text));
+        rest = &trimmed[text.len()..];
+    }
+}
+
+// End of the first sentence: after a full stop, question or exclamation
+// mark followed by a space
+fn sentence_end(text: &str) -> usize {
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?' | '…') {
+            if let Some(&(next, space)) = chars.peek() {
+                if space.is_whitespace() {
+                    return next;
+                }
+            }
+        }
+    }
+    text.len()
+}
+
+// The Kobo library shows the cover marked with the cover-image property, which
+// ePUB 2 package documents do not have
+fn cover_property(package: &str) -> String {
+    let Some(start) = package.find(r#"id="cover-image""#).and_then(|i| package[..i].rfind("<item")) else {
+        return package.to_string();
+    };
+    let end = start + package[start..].find('>').unwrap_or(0);
+    if package[start..end].contains("properties=") {
+        return package.to_string();
+    }
+    let mut package = package.to_string();
+    package.insert_str(start + "<item".len(), r#" properties="cover-image""#);
+    package
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::EpubWrite(e.into())
+}
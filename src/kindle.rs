@@ -0,0 +1,87 @@
+// Kindle copy of the single ePUB file, written next to it (<name>.azw3 or
+// <name>.mobi). Kindles do not open ePUB files: the conversion is left to
+// calibre's ebook-convert or to kindlegen, found in the PATH or given with
+// --converter.
+use clap::ValueEnum;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{FileContext, Result};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    // Kindle Format 8, for devices since the Kindle Touch
+    Azw3,
+    // Mobipocket, for the older ones
+    Mobi,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Azw3 => "azw3",
+            Format::Mobi => "mobi",
+        }
+    }
+}
+
+// Path of the converted file
+pub fn convert(epub: &Path, format: Format, converter: Option<&str>) -> Result<PathBuf> {
+    let output = epub.with_extension(format.extension());
+    let (program, args) = match converter {
+        Some(converter) => {
+            let mut words = converter.split_whitespace();
+            let program = PathBuf::from(words.next().ok_or("empty converter command")?);
+            (program, words.map(str::to_string).collect())
+        }
+        None => {
+            // kindlegen only writes MOBI files
+            let programs: &[&str] = match format {
+                Format::Azw3 => &["ebook-convert"],
+                Format::Mobi => &["ebook-convert", "kindlegen"],
+            };
+            let program = programs
+                .iter()
+                .find_map(|name| find_program(name))
+                .ok_or_else(|| format!("{} not found in the PATH, install calibre or use --converter", programs.join(" or ")))?;
+            (program, Vec::new())
+        }
+    };
+
+    // a file left by a previous run would look like the result
+    if output.exists() {
+        std::fs::remove_file(&output).with_path(&output)?;
+    }
+
+    let mut command = Command::new(&program);
+    command.args(args);
+    if program.file_stem().is_some_and(|stem| stem == "kindlegen") {
+        // the name of the output, written next to the input
+        let name = output.file_name().ok_or("invalid output file name")?;
+        command.arg(epub).arg("-o").arg(name);
+    } else {
+        command.arg(epub).arg(&output);
+    }
+    let result = command
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", program.display(), e))?;
+
+    // kindlegen exits with 1 when the file is written with warnings
+    if !output.exists() {
+        let error = String::from_utf8_lossy(&result.stderr);
+        let error = match error.trim().lines().last() {
+            Some(line) => line.to_string(),
+            None => String::from_utf8_lossy(&result.stdout).trim().lines().last().unwrap_or_default().to_string(),
+        };
+        return Err(format!("{} failed ({}): {}", program.display(), result.status, error).into());
+    }
+    Ok(output)
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let name = if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() };
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,39 @@
+// Text of the pages generated in the single ePUB file.
+// Italian is the default, add a language by adding a Strings value.
+pub struct Strings {
+    pub omitted_title: &'static str,
+    pub updated_notice: &'static str,
+    pub updated_suffix: &'static str,
+    pub credits_title: &'static str,
+    pub photo_credits: &'static str,
+    // {date} is replaced with the edition date
+    pub copyright: &'static str,
+    pub podcast_description: &'static str,
+}
+
+const IT: Strings = Strings {
+    omitted_title: "Articoli non inclusi",
+    updated_notice: "Articolo aggiornato",
+    updated_suffix: "aggiornato",
+    credits_title: "Crediti",
+    photo_credits: "Fotografie",
+    copyright: "© il manifesto, edizione del {date}. Tutti i diritti riservati.",
+    podcast_description: "Le edizioni de il manifesto lette ad alta voce",
+};
+
+const EN: Strings = Strings {
+    omitted_title: "Omitted articles",
+    updated_notice: "Updated article",
+    updated_suffix: "updated",
+    credits_title: "Credits",
+    photo_credits: "Photographs",
+    copyright: "© il manifesto, edition of {date}. All rights reserved.",
+    podcast_description: "The editions of il manifesto read aloud",
+};
+
+pub fn strings(lang: &str) -> &'static Strings {
+    match lang {
+        "en" => &EN,
+        _ => &IT,
+    }
+}
@@ -0,0 +1,109 @@
+// Index of the single ePUB file: where each article is in the reading order
+// and its headings. It is added to the ePUB as locators.json and written next
+// to it, so that other tools can jump to an article without unzipping it.
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::xhtml::plain;
+use crate::Post;
+
+pub const FILE_NAME: &str = "locators.json";
+
+#[derive(Serialize, Debug)]
+pub struct Locators {
+    version: u32,
+    edition: String,
+    // pages in reading order, relative to OEBPS/
+    spine: Vec<String>,
+    articles: Vec<Article>,
+}
+
+#[derive(Serialize, Debug)]
+struct Article {
+    slug: String,
+    title: String,
+    section: Option<String>,
+    href: String,
+    // index in the spine
+    position: usize,
+    headings: Vec<Heading>,
+}
+
+#[derive(Serialize, Debug)]
+struct Heading {
+    level: u8,
+    text: String,
+    // with the fragment when the heading has an id
+    href: String,
+}
+
+impl Locators {
+    pub fn new(edition: &str) -> Locators {
+        Locators {
+            version: 1,
+            edition: edition.to_string(),
+            spine: Vec::new(),
+            articles: Vec::new(),
+        }
+    }
+
+    // A page was added at the end of the reading order, returns its position
+    pub fn page(&mut self, href: &str) -> usize {
+        self.spine.push(href.to_string());
+        self.spine.len() - 1
+    }
+
+    pub fn article(&mut self, post: &Post, href: &str, position: usize, content: &str) {
+        self.articles.push(Article {
+            slug: post.slug.clone(),
+            title: post.title.clone(),
+            section: post.section.as_ref().map(|section| section.name.clone()),
+            href: href.to_string(),
+            position,
+            headings: headings(content, href),
+        });
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+}
+
+fn headings(content: &str, href: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<h") {
+        let tag = &rest[start..];
+        let level = tag.as_bytes().get(2).copied().unwrap_or(0);
+        let open_end = tag.find('>');
+        if !(b'1'..=b'6').contains(&level) || open_end.is_none() {
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let open_end = open_end.unwrap();
+        let close = format!("</h{}>", level as char);
+        let close_start = tag.find(&close).unwrap_or(tag.len());
+
+        let id = attribute(&tag[..open_end], "id");
+        let text = plain(&tag[(open_end + 1).min(close_start)..close_start]);
+        if !text.is_empty() {
+            headings.push(Heading {
+                level: level - b'0',
+                text,
+                href: match id {
+                    Some(id) => format!("{}#{}", href, id),
+                    None => href.to_string(),
+                },
+            });
+        }
+        rest = &tag[(close_start + close.len()).min(tag.len())..];
+    }
+    headings
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
@@ -0,0 +1,67 @@
+// Messages on stderr, as plain text or as one JSON object per line for
+// systemd and containers. Events worth parsing carry an `event` key
+// (edition, article, image, file) with their details as extra keys.
+use chrono::Utc;
+use clap::ValueEnum;
+use log::kv::{self, Key, Value as KvValue, VisitSource};
+use log::LevelFilter;
+use serde_json::{Map, Value};
+
+use std::io::Write;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Warnings are printed by default, information as well with JSON.
+// RUST_LOG, if set, takes precedence over the verbosity flags
+// (e.g. RUST_LOG=il_manifesto=debug).
+pub fn init(verbose: u8, quiet: bool, format: LogFormat) {
+    let level = match (quiet, verbose, format) {
+        (true, _, _) => LevelFilter::Error,
+        (_, 0, LogFormat::Text) => LevelFilter::Warn,
+        (_, 0, LogFormat::Json) | (_, 1, _) => LevelFilter::Info,
+        (_, 2, _) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level).format_timestamp(None);
+    // lopdf warns about each font of the PDF without a Unicode table
+    let lopdf = if level >= LevelFilter::Debug { level } else { LevelFilter::Error };
+    builder.filter_module("lopdf", lopdf);
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("time".into(), Value::String(Utc::now().to_rfc3339()));
+            line.insert("level".into(), Value::String(record.level().to_string()));
+            line.insert("target".into(), Value::String(record.target().to_string()));
+            line.insert("message".into(), Value::String(record.args().to_string()));
+            let _ = record.key_values().visit(&mut Fields(&mut line));
+            writeln!(buf, "{}", Value::Object(line))
+        });
+    }
+
+    builder.parse_default_env().init();
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            Value::from(n)
+        } else if let Some(n) = value.to_i64() {
+            Value::from(n)
+        } else if let Some(b) = value.to_bool() {
+            Value::Bool(b)
+        } else {
+            Value::String(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+// Delivery by email of the files readers open (PDF, ePUB and Kobo ePUB, AZW3 or
+// MOBI, CBZ), one message for each file so that each format is checked against
+// the size accepted by the server (and Send to Kindle addresses get one
+// document per message). The server is reached with STARTTLS (port 587) by
+// default, with TLS from the start (port 465) or, on a local relay, in clear
+// (port 25). A Send to Kindle address gets only the edition in the best format
+// Amazon accepts (ePUB, else PDF; MOBI and AZW3 are no longer accepted) within
+// its size limit. Amazon drops messages whose sender is not in the approved
+// list of the account, without telling the sender.
+use clap::ValueEnum;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{Error, FileContext, Result};
+use crate::output;
+
+// Largest message accepted by Send to Kindle
+const KINDLE_MAX_SIZE: u64 = 50 << 20;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Security {
+    Starttls,
+    Tls,
+    None,
+}
+
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    // Send to Kindle address
+    kindle: Option<Mailbox>,
+    // bytes
+    max_size: u64,
+}
+
+// Server, account and recipients
+pub struct Settings<'a> {
+    // host or host:port
+    pub server: &'a str,
+    pub security: Security,
+    pub user: Option<&'a str>,
+    pub password: Option<&'a str>,
+    // the user when not given
+    pub from: Option<&'a str>,
+    pub to: &'a [String],
+    pub kindle: Option<&'a str>,
+    // megabytes
+    pub max_size: u64,
+}
+
+impl Mailer {
+    pub fn new(settings: &Settings) -> Result<Mailer> {
+        let (host, port) = match settings.server.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse().map_err(|_| format!("invalid SMTP port in {}", settings.server))?;
+                (host, Some(port))
+            }
+            None => (settings.server, None),
+        };
+        let mut builder = match settings.security {
+            Security::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(smtp_error)?,
+            Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(smtp_error)?,
+            Security::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some(user) = settings.user {
+            let password = settings.password.unwrap_or_default();
+            builder = builder.credentials(Credentials::new(user.to_string(), password.to_string()));
+        }
+
+        let from = settings.from.or(settings.user).ok_or("--smtp-from is needed without --smtp-user")?;
+        if settings.to.is_empty() && settings.kindle.is_none() {
+            return Err("no recipient given with --smtp-to or --send-to-kindle".into());
+        }
+        Ok(Mailer {
+            transport: builder.build(),
+            from: mailbox(from)?,
+            to: settings.to.iter().map(|to| mailbox(to)).collect::<Result<_>>()?,
+            kindle: settings.kindle.map(mailbox).transpose()?,
+            max_size: settings.max_size << 20,
+        })
+    }
+
+    // Files that could not be sent, with the reason
+    pub async fn deliver(&self, title: &str, files: &[PathBuf]) -> Vec<(PathBuf, Error)> {
+        let mut failed = Vec::new();
+        if !self.to.is_empty() {
+            for file in files.iter().filter(|file| output::is_ebook(file)) {
+                match self.send(&self.to, title, file, self.max_size).await {
+                    Ok(()) => info!("Mailed {} to {}", file.display(), recipients(&self.to)),
+                    Err(e) => failed.push((file.clone(), e)),
+                }
+            }
+        }
+
+        if let Some(kindle) = &self.kindle {
+            let to = std::slice::from_ref(kindle);
+            match kindle_document(files) {
+                Some(file) => match self.send(to, title, file, self.max_size.min(KINDLE_MAX_SIZE)).await {
+                    Ok(()) => info!("Sent {} to Kindle ({})", file.display(), kindle),
+                    Err(e) => failed.push((file.clone(), e)),
+                },
+                None => failed.push((PathBuf::from(kindle.to_string()), "no ePUB or PDF file to send to Kindle".into())),
+            }
+        }
+        failed
+    }
+
+    // Text message to the --smtp-to recipients, without attachments
+    pub async fn send_text(&self, subject: &str, body: String) -> Result<()> {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.singlepart(SinglePart::plain(body)).map_err(|e| e.to_string())?;
+        self.transport.send(message).await.map_err(smtp_error)?;
+        info!("Mailed \"{}\" to {}", subject, recipients(&self.to));
+        Ok(())
+    }
+
+    async fn send(&self, to: &[Mailbox], title: &str, file: &Path, max_size: u64) -> Result<()> {
+        let size = std::fs::metadata(file).with_path(file)?.len();
+        if size > max_size {
+            let megabytes = size as f64 / (1 << 20) as f64;
+            return Err(format!("{:.1} MB, more than the {} MB that can be sent", megabytes, max_size >> 20).into());
+        }
+        let name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = file.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let content_type = ContentType::parse(output::content_type(&extension)).map_err(|e| e.to_string())?;
+        let content = std::fs::read(file).with_path(file)?;
+
+        let mut message = Message::builder().from(self.from.clone()).subject(title);
+        for to in to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .multipart(
+                MultiPart::mixed()
+                    .singlepart(SinglePart::plain(format!("{}\n", name)))
+                    .singlepart(Attachment::new(name).body(content, content_type)),
+            )
+            .map_err(|e| e.to_string())?;
+        self.transport.send(message).await.map_err(smtp_error)?;
+        Ok(())
+    }
+}
+
+fn recipients(to: &[Mailbox]) -> String {
+    to.iter().map(|to| to.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+// The single ePUB file (Kobo files have Kobo markup), else the PDF
+fn kindle_document(files: &[PathBuf]) -> Option<&PathBuf> {
+    let name = |file: &PathBuf| file.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    files
+        .iter()
+        .find(|file| name(file).ends_with(".epub") && !name(file).ends_with(".kepub.epub"))
+        .or_else(|| files.iter().find(|file| name(file).ends_with(".pdf")))
+}
+
+fn mailbox(address: &str) -> Result<Mailbox> {
+    address.parse().map_err(|e| format!("invalid email address {}: {}", address, e).into())
+}
+
+fn smtp_error(e: lettre::transport::smtp::Error) -> Error {
+    format!("SMTP error: {}", e).into()
+}
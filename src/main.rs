@@ -1,31 +1,218 @@
 // CLI argument parsing
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+// Errors reported to the user
+mod error;
+use error::{Error, FileContext, Result};
+
+// Text of generated pages
+mod locale;
+use locale::Strings;
+
+// Login and credentials location
+mod state;
+use state::StateDir;
+
+// Versions of the downloaded articles
+mod archive;
+use archive::Archive;
+
+// Encryption of login.json
+mod secret;
+
+// Copy to e-readers
+mod device;
+
+// Copy to synchronized folders
+mod sync;
+
+// Import in a calibre library
+mod calibre;
+use calibre::Calibre;
+
+// Authorized requests
+mod api;
+use api::{Api, ApiClient, ApiEnv, PostsQuery, Retry};
+
+// Produced files
+mod history;
+use history::History;
+
+// Full-text search of the articles
+mod search;
+use search::Search;
+
+// Web viewer
+mod serve;
+
+// End of run notifications
+mod notify;
+use notify::Notifier;
+
+// Read-it-later export of the articles
+mod read_later;
+mod wallabag;
+use wallabag::Wallabag;
+mod readwise;
+use readwise::Readwise;
+mod readeck;
+use readeck::Readeck;
+
+// Commands run after each edition
+mod hooks;
+use hooks::Hooks;
+
+mod telegram;
+use telegram::Telegram;
+
+mod matrix;
+use matrix::Matrix;
+
+mod mail;
+use mail::Mailer;
+
+// Upload to remote hosts
+mod sftp;
+use sftp::Sftp;
+
+// Upload to cloud storage
+mod oauth;
+mod dropbox;
+use dropbox::Dropbox;
+mod drive;
+use drive::Drive;
+
+mod usage;
+
+mod weekly;
+
+mod config;
+use config::{Config, Format};
+
+mod template;
+
+mod logging;
+use logging::LogFormat;
+
+mod progress;
+use progress::{Event, Progress};
+
+mod tables;
+
+mod report;
+use report::Output;
+
+mod cookies;
+use cookies::CookieJar;
+
+mod locators;
+use locators::Locators;
+
+mod collate;
+
+mod backfill;
+
+mod output;
+use output::Existing;
+
+mod cover;
+use cover::CoverMode;
+
+mod filters;
+use filters::Filter;
+
+mod dump;
+use dump::Dump;
+
+mod http_cache;
+use http_cache::HttpCache;
+
+mod rate_limit;
+
+mod manifest;
+use manifest::Manifest;
+
+mod preview;
+
+mod svg;
+
+mod metadata;
+mod kepub;
+
+mod kindle;
+
+// Cropped, compressed and split copies of the PDF
+mod pdf;
+mod cbz;
+
+mod html;
+
+mod markdown;
+
+mod text;
+
+mod corpus;
+
+mod index;
+
+mod audio;
+
+mod podcast;
+
+mod themes;
+use themes::Theme;
+
+mod pages;
+use pages::Layout;
+
+mod xhtml;
+
+// Checksums of downloaded files
+mod verify;
+use verify::Verifier;
+
+// Downloaded, skipped and failed items
+mod summary;
+use summary::{Status, Summary};
 
 // JSON
-use serde::{de, Deserializer, Deserialize, Serialize};
+use serde::{Deserializer, Deserialize, Serialize};
+use serde_json::json;
 
 // Files
 use bytes::Bytes;
-use std::fs::{create_dir, remove_dir_all, File};
-use std::io::{BufReader, Cursor, ErrorKind, Seek, Write};
+use std::collections::HashSet;
+use std::fs::{create_dir_all, remove_dir_all, File};
+use std::io::{BufReader, Cursor, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-// HTTP requests and URL parsing
-use reqwest::header::AUTHORIZATION;
+// URL parsing
 use url::Url;
 
+// Article filters
+use regex::{Regex, RegexBuilder};
+
+// Concurrent downloads
+use futures::stream::{self, StreamExt};
+
 // Epubs
 use epub::doc::EpubDoc;
-use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, Result, ZipLibrary};
+use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, ZipLibrary};
 
 use random_string::generate;
 
 // Image
 use image::io::Reader as ImageReader;
-use image::ImageFormat;
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
 
 // Logging
-use log::{info, warn};
+use log::{debug, info, warn};
+
+// Dates
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 
 // API base URL
 pub const BASE_URL: &str = "https://api.ilmanifesto.it/api/v1";
@@ -37,21 +224,47 @@ pub const BASE_URL: &str = "https://api.ilmanifesto.it/api/v1";
 // The correct way is probably using uniquely defined names (md5?).
 pub const CHARSET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
-// Hacky way to have a templete for format
-macro_rules! IMAGE_HTML {
+// Sections of the supplements published with the daily edition (their slug starts with these)
+const SUPPLEMENT_SECTIONS: [&str; 2] = ["inserto", "supplement"];
+
+// Page breaks for e-ink readers: every article starts on a new page,
+// images and titles are not split from what follows
+const PAGINATION_CSS: &str = r#"body {
+  page-break-before: always;
+  break-before: page;
+}
+img, figure, .figure, blockquote {
+  page-break-inside: avoid;
+  break-inside: avoid;
+}
+h1, h2, h3, h4 {
+  page-break-after: avoid;
+  break-after: avoid;
+}
+img {
+  max-width: 100%;
+}
+.table-image object {
+  max-width: 100%;
+}
+.byline {
+  font-style: italic;
+}
+"#;
+
+const STYLESHEET_LINK: &str = r#"<link rel="stylesheet" type="text/css" href="stylesheet.css" />"#;
+
+// Authors under the title of the article
+macro_rules! BYLINE_HTML {
+    () => {
+        r#"<p class="byline">{authors}</p>"#
+    };
+}
+
+// Notice added to articles updated since the previous download
+macro_rules! UPDATED_HTML {
     () => {
-        r#"<?xml version="1.0" encoding="utf-8"?>
-<html xmlns="http://www.w3.org/1999/xhtml">
-  <head>
-    <title>{title}</title>
-  </head>
-  <body>
-    <{tsize}>{title}</{tsize}>
-    <img src="{imgurl}" />
-    {summary}
-  </body>
-</html>
-"#
+        r#"<p class="updated"><em>{notice}</em></p>"#
     };
 }
 
@@ -61,26 +274,81 @@ macro_rules! IMAGE_HTML {
 fn de_format_cover_position<'de, D>(deserializer: D) -> Result<i32, D::Error>
     where D: Deserializer<'de>
 {
-    Ok(i32::deserialize(deserializer).or(Ok(99))?)
+    i32::deserialize(deserializer).or(Ok(99))
+}
+
+// The section is returned either as an object with name and slug,
+// as a list of them or as a plain string. Anything else is ignored.
+fn de_format_section<'de, D>(deserializer: D) -> Result<Option<Section>, D::Error>
+    where D: Deserializer<'de>
+{
+    let value = serde_json::Value::deserialize(deserializer).unwrap_or_default();
+    let value = match value {
+        serde_json::Value::Array(values) => values.into_iter().next().unwrap_or_default(),
+        value => value,
+    };
+
+    Ok(match value {
+        serde_json::Value::String(name) if !name.is_empty() => Some(Section {
+            slug: name.to_lowercase().replace(' ', "-"),
+            name,
+        }),
+        serde_json::Value::Object(_) => serde_json::from_value(value).ok(),
+        _ => None,
+    })
+}
+
+// Authors are returned as a list of names or of objects with a name, or as a
+// single name. Anything else is ignored.
+fn de_format_authors<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where D: Deserializer<'de>
+{
+    let value = serde_json::Value::deserialize(deserializer).unwrap_or_default();
+    let values = match value {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    Ok(values
+        .into_iter()
+        .filter_map(|value| match value {
+            serde_json::Value::String(name) => Some(name),
+            serde_json::Value::Object(mut author) => match author.remove("name") {
+                Some(serde_json::Value::String(name)) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect())
+}
+
+// --match and --skip patterns, e.g. "programmi tv" or "^lettere"
+fn pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Downloads PDF version
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, env = "CLIMA_PDF", default_value_t = false)]
     pdf: bool,
 
     /// Downloads ePUB files
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, env = "CLIMA_EPUB", default_value_t = false)]
     epub: bool,
 
     /// Generates a single ePUB file
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, env = "CLIMA_SINGLE_EPUB", default_value_t = false)]
     single_epub: bool,
 
     /// Keep downloaded ePUB files (mainly for debugging)
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, env = "CLIMA_KEEP_FILES", default_value_t = false)]
     keep_files: bool,
 
     /// List editions and quit
@@ -92,12 +360,674 @@ struct Args {
     get_edition: i32,
 
     /// Email
-    #[arg(long, default_value = "")]
+    #[arg(long, env = "CLIMA_EMAIL", default_value = "")]
     email: String,
 
     /// Password
-    #[arg(long, default_value = "")]
+    #[arg(long, env = "CLIMA_PASSWORD", hide_env_values = true, default_value = "")]
     password: String,
+
+    /// Include at most this many articles, in order of importance in the front page
+    #[arg(long, env = "CLIMA_MAX_ARTICLES")]
+    max_articles: Option<usize>,
+
+    /// Include only the articles of these authors, separated by commas (e.g.
+    /// "Nome Cognome,Altro Nome"), the others are listed as omitted
+    #[arg(long, env = "CLIMA_AUTHORS", value_delimiter = ',')]
+    authors: Vec<String>,
+
+    /// Include only the articles whose title, kicker or summary match this
+    /// regular expression or word, in any case (can be repeated)
+    #[arg(long = "match", env = "CLIMA_MATCH", value_parser = pattern)]
+    match_patterns: Vec<Regex>,
+
+    /// Leave out the articles whose title, kicker or summary match this
+    /// regular expression or word, in any case (can be repeated)
+    #[arg(long = "skip", env = "CLIMA_SKIP", value_parser = pattern)]
+    skip_patterns: Vec<Regex>,
+
+    /// Language of the generated pages (it, en)
+    #[arg(long, env = "CLIMA_LANG", default_value = "it")]
+    lang: String,
+
+    /// Download again articles already fetched and report the updated ones
+    #[arg(long, env = "CLIMA_CHECK_UPDATES", default_value_t = false)]
+    check_updates: bool,
+
+    /// Mark updated articles in the single ePUB file (requires --check-updates)
+    #[arg(long, env = "CLIMA_MARK_UPDATES", default_value_t = false)]
+    mark_updates: bool,
+
+    /// Passphrase used to encrypt login.json (unencrypted if not set)
+    #[arg(long, env = "CLIMA_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Directory for login.json and credentials.json (default: platform state directory)
+    #[arg(long, env = "CLIMA_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Directory for the downloaded articles and images (default: system temporary directory)
+    #[arg(long, env = "CLIMA_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Do not keep API responses and images in the cache directory: by
+    /// default they are asked again only if they changed (ETag, Last-Modified)
+    #[arg(long, env = "CLIMA_NO_HTTP_CACHE", default_value_t = false)]
+    no_http_cache: bool,
+
+    /// Copy the PDF and the single ePUB file to the e-reader mounted here
+    #[arg(long, env = "CLIMA_DEVICE")]
+    device: Option<PathBuf>,
+
+    /// Copy the PDF and the single ePUB file to a folder synchronized by
+    /// Syncthing, Dropbox... (renamed into place only when complete)
+    #[arg(long, env = "CLIMA_SYNC_DIR")]
+    sync_dir: Option<PathBuf>,
+
+    /// Add a hash of the content to the names of the files copied to
+    /// --sync-dir, to avoid sync conflicts between machines
+    #[arg(long, env = "CLIMA_SYNC_HASH", default_value_t = false)]
+    sync_hash: bool,
+
+    /// calibre library (directory or content server URL) the e-books are
+    /// added to with calibredb, one book for each edition
+    #[arg(long, env = "CLIMA_CALIBRE_LIBRARY")]
+    calibre_library: Option<String>,
+
+    /// Command used to run calibredb (default: calibredb in the PATH)
+    #[arg(long, env = "CLIMA_CALIBREDB", requires = "calibre_library")]
+    calibredb: Option<String>,
+
+    /// Token of the Telegram bot sending the PDF and ePUB files to --telegram-chat
+    #[arg(long, env = "CLIMA_TELEGRAM_TOKEN", hide_env_values = true, requires = "telegram_chat")]
+    telegram_token: Option<String>,
+
+    /// Telegram chat id the files are sent to
+    #[arg(long, env = "CLIMA_TELEGRAM_CHAT", requires = "telegram_token")]
+    telegram_chat: Option<String>,
+
+    /// Matrix homeserver of the account posting to --matrix-room, e.g.
+    /// https://matrix.org
+    #[arg(long, env = "CLIMA_MATRIX_HOMESERVER", requires_all = ["matrix_token", "matrix_room"])]
+    matrix_homeserver: Option<String>,
+
+    /// Access token of the Matrix account
+    #[arg(long, env = "CLIMA_MATRIX_TOKEN", hide_env_values = true, requires = "matrix_homeserver")]
+    matrix_token: Option<String>,
+
+    /// Matrix room (id or alias) where a message is posted and the PDF and
+    /// ePUB files are uploaded when the edition is ready
+    #[arg(long, env = "CLIMA_MATRIX_ROOM", requires = "matrix_homeserver")]
+    matrix_room: Option<String>,
+
+    /// SMTP server (host or host:port) sending the PDF and ePUB files to
+    /// --smtp-to, one message for each file, and to --send-to-kindle
+    #[arg(long, env = "CLIMA_SMTP_SERVER")]
+    smtp_server: Option<String>,
+
+    /// Encryption of the SMTP connection: starttls (port 587), tls (465) or
+    /// none (25, for local relays)
+    #[arg(long, env = "CLIMA_SMTP_SECURITY", value_enum, default_value_t = mail::Security::Starttls)]
+    smtp_security: mail::Security,
+
+    /// SMTP user name
+    #[arg(long, env = "CLIMA_SMTP_USER")]
+    smtp_user: Option<String>,
+
+    /// SMTP password
+    #[arg(long, env = "CLIMA_SMTP_PASSWORD", hide_env_values = true)]
+    smtp_password: Option<String>,
+
+    /// Sender of the messages (default: the SMTP user)
+    #[arg(long, env = "CLIMA_SMTP_FROM")]
+    smtp_from: Option<String>,
+
+    /// Recipients of the files, separated by commas
+    #[arg(long, env = "CLIMA_SMTP_TO", value_delimiter = ',', requires = "smtp_server")]
+    smtp_to: Vec<String>,
+
+    /// Send to Kindle address (name@kindle.com) the edition is mailed to, as
+    /// ePUB (or PDF without ePUB). The sender must be approved in the Amazon
+    /// account
+    #[arg(long, env = "CLIMA_SEND_TO_KINDLE", requires = "smtp_server")]
+    send_to_kindle: Option<String>,
+
+    /// Files larger than this (in MB) are not mailed
+    #[arg(long, env = "CLIMA_SMTP_MAX_SIZE", default_value_t = 25)]
+    smtp_max_size: u64,
+
+    /// Upload the produced files with sftp to [user@]host:directory, e.g. a
+    /// NAS or a web server (key authentication only, the host must be known)
+    #[arg(long, env = "CLIMA_SFTP")]
+    sftp: Option<String>,
+
+    /// Private key for --sftp (default: the SSH agent and ~/.ssh)
+    #[arg(long, env = "CLIMA_SFTP_KEY", requires = "sftp")]
+    sftp_key: Option<PathBuf>,
+
+    /// SSH port of the --sftp host
+    #[arg(long, env = "CLIMA_SFTP_PORT", requires = "sftp")]
+    sftp_port: Option<u16>,
+
+    /// Upload the PDF and ePUB files to this Dropbox folder, e.g.
+    /// "/Apps/Rakuten Kobo" (after `authorize dropbox`)
+    #[arg(long, env = "CLIMA_DROPBOX", requires = "dropbox_app")]
+    dropbox: Option<String>,
+
+    /// App key of the Dropbox app the files are uploaded with
+    #[arg(long, env = "CLIMA_DROPBOX_APP")]
+    dropbox_app: Option<String>,
+
+    /// Upload the PDF and ePUB files to this Google Drive folder, created if
+    /// missing (after `authorize drive`)
+    #[arg(long, env = "CLIMA_DRIVE", requires_all = ["drive_client_id", "drive_client_secret"])]
+    drive: Option<String>,
+
+    /// Client id of the Google OAuth client (Desktop app) the files are
+    /// uploaded with
+    #[arg(long, env = "CLIMA_DRIVE_CLIENT_ID")]
+    drive_client_id: Option<String>,
+
+    /// Client secret of the Google OAuth client
+    #[arg(long, env = "CLIMA_DRIVE_CLIENT_SECRET", hide_env_values = true)]
+    drive_client_secret: Option<String>,
+
+    /// Wallabag server the articles are saved to, e.g. https://app.wallabag.it
+    #[arg(long, env = "CLIMA_WALLABAG", requires_all = ["wallabag_client_id", "wallabag_client_secret", "wallabag_user", "wallabag_password"])]
+    wallabag: Option<String>,
+
+    /// Client id of the Wallabag API client
+    #[arg(long, env = "CLIMA_WALLABAG_CLIENT_ID", requires = "wallabag")]
+    wallabag_client_id: Option<String>,
+
+    /// Client secret of the Wallabag API client
+    #[arg(long, env = "CLIMA_WALLABAG_CLIENT_SECRET", hide_env_values = true, requires = "wallabag")]
+    wallabag_client_secret: Option<String>,
+
+    /// Wallabag user name
+    #[arg(long, env = "CLIMA_WALLABAG_USER", requires = "wallabag")]
+    wallabag_user: Option<String>,
+
+    /// Wallabag password
+    #[arg(long, env = "CLIMA_WALLABAG_PASSWORD", hide_env_values = true, requires = "wallabag")]
+    wallabag_password: Option<String>,
+
+    /// Readwise Reader access token (https://readwise.io/access_token), the
+    /// articles are saved to Reader
+    #[arg(long, env = "CLIMA_READWISE_TOKEN", hide_env_values = true)]
+    readwise_token: Option<String>,
+
+    /// Readeck server the articles are bookmarked in, e.g.
+    /// https://readeck.example.org
+    #[arg(long, env = "CLIMA_READECK", requires = "readeck_token")]
+    readeck: Option<String>,
+
+    /// Readeck API token
+    #[arg(long, env = "CLIMA_READECK_TOKEN", hide_env_values = true, requires = "readeck")]
+    readeck_token: Option<String>,
+
+    /// Only the articles of these sections (names or slugs, separated by
+    /// commas) are sent to Wallabag, Readwise and Readeck
+    #[arg(long, env = "CLIMA_READ_LATER_SECTIONS", value_delimiter = ',')]
+    read_later_sections: Vec<String>,
+
+    /// Only the articles with one of these words (separated by commas) in the
+    /// title, kicker or summary are sent to Wallabag, Readwise and Readeck
+    #[arg(long, env = "CLIMA_READ_LATER_KEYWORDS", value_delimiter = ',')]
+    read_later_keywords: Vec<String>,
+
+    /// Shell command run when an edition is ready, with the produced files as
+    /// arguments and the edition in CLIMA_EDITION, CLIMA_TITLE, CLIMA_DATE...
+    #[arg(long, env = "CLIMA_ON_SUCCESS")]
+    on_success: Option<String>,
+
+    /// Shell command run when an edition cannot be downloaded, with the error
+    /// in CLIMA_ERROR
+    #[arg(long, env = "CLIMA_ON_FAILURE")]
+    on_failure: Option<String>,
+
+    /// Shell command run for each produced file, with its path as argument
+    #[arg(long, env = "CLIMA_ON_FILE")]
+    on_file: Option<String>,
+
+    /// ntfy topic notified when the edition is ready or cannot be
+    /// downloaded, e.g. https://ntfy.sh/my-paper
+    #[arg(long, env = "CLIMA_NTFY")]
+    ntfy: Option<String>,
+
+    /// URL to which a JSON document with the edition, the produced files or
+    /// the error is posted at the end of the run
+    #[arg(long, env = "CLIMA_WEBHOOK")]
+    webhook: Option<String>,
+
+    /// Number of articles downloaded at the same time
+    #[arg(short, long, env = "CLIMA_JOBS", default_value_t = 4)]
+    jobs: usize,
+
+    /// Retries of failed requests
+    #[arg(long, env = "CLIMA_RETRIES", default_value_t = 3)]
+    retries: u32,
+
+    /// Wait before the first retry in milliseconds, doubled at each retry
+    #[arg(long, env = "CLIMA_RETRY_DELAY", default_value_t = 500)]
+    retry_delay: u64,
+
+    /// Requests per second to the API, 0 for no limit
+    #[arg(long, env = "CLIMA_RATE_LIMIT", default_value_t = 5.0)]
+    rate_limit: f64,
+
+    /// Directory of the produced files, created if missing (default: current directory)
+    #[arg(short, long, env = "CLIMA_OUTPUT_DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Also download the editions of the last days missing from the history
+    #[arg(long, env = "CLIMA_BACKFILL", value_name = "DAYS")]
+    backfill: Option<i64>,
+
+    /// Name of the PDF and ePUB files, with {date}, {slug}, {title} and {ext}
+    /// (default: {slug}.{ext})
+    #[arg(long, env = "CLIMA_NAME_TEMPLATE")]
+    name_template: Option<String>,
+
+    /// Replace existing output files (default)
+    #[arg(long, default_value_t = false, group = "existing")]
+    overwrite: bool,
+
+    /// Keep existing output files, the PDF or ePUB is not downloaded again
+    #[arg(long, default_value_t = false, group = "existing")]
+    skip_existing: bool,
+
+    /// Rename existing output files to <name>.bak before writing them
+    #[arg(long, default_value_t = false, group = "existing")]
+    backup: bool,
+
+    /// Configuration file (default: config.toml in the platform config directory)
+    #[arg(long, env = "CLIMA_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Device profile of config.toml, with the filters applied to the images
+    #[arg(long, env = "CLIMA_PROFILE")]
+    profile: Option<String>,
+
+    /// More messages (-v information, -vv debug, -vvv everything)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print errors
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of the messages: text, or json for log collectors
+    #[arg(long, env = "CLIMA_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// API to use: prod, staging or the base URL of another server.
+    /// Each one has its own login.json
+    #[arg(long, env = "CLIMA_API_ENV", default_value = "prod", value_parser = ApiEnv::parse)]
+    api_env: ApiEnv,
+
+    /// Base URL of the API, e.g. of a staging mirror or a local mock server.
+    /// Same as --api-env with a URL
+    #[arg(long, env = "CLIMA_BASE_URL", value_parser = ApiEnv::parse_url, conflicts_with = "api_env")]
+    base_url: Option<ApiEnv>,
+
+    /// Accept invalid TLS certificates. Anybody on the network can then read
+    /// the password and the tokens: prefer --ca-cert
+    #[arg(long, default_value_t = false)]
+    insecure: bool,
+
+    /// Also trust the certificates in this PEM file, e.g. the one of a TLS
+    /// intercepting proxy of the company network
+    #[arg(long, env = "CLIMA_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// Seconds to wait for the connection to the server (default 30)
+    #[arg(long, env = "CLIMA_CONNECT_TIMEOUT")]
+    connect_timeout: Option<u64>,
+
+    /// Seconds without receiving data after which a request fails and is
+    /// retried (default 60)
+    #[arg(long, env = "CLIMA_READ_TIMEOUT")]
+    read_timeout: Option<u64>,
+
+    /// User-Agent header of the requests (default: clima/<version>)
+    #[arg(long, env = "CLIMA_USER_AGENT")]
+    user_agent: Option<String>,
+
+    /// Proxy for all requests: http://, https:// or socks5:// URL, with
+    /// user:password@ for authenticated proxies. Without it the HTTPS_PROXY,
+    /// HTTP_PROXY and NO_PROXY environment variables are used
+    #[arg(long, env = "CLIMA_PROXY")]
+    proxy: Option<String>,
+
+    /// Save every API response (URL, status, headers and body) in this
+    /// directory, to report changes of the API. Tokens are redacted
+    #[arg(long, env = "CLIMA_DUMP_API")]
+    dump_api: Option<PathBuf>,
+
+    /// Draw tables wider than this many pixels (the screen width of the
+    /// e-reader, e.g. 758) as images in the single ePUB file
+    #[arg(long, env = "CLIMA_TABLE_IMAGES")]
+    table_images: Option<u32>,
+
+    /// Report printed at the end: text, or json with the produced files and
+    /// the status of each article on stdout
+    #[arg(long, env = "CLIMA_OUTPUT", value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    /// Cover of the single ePUB file: the photo of the edition (image), one
+    /// generated with title and date (generated), the photo if available
+    /// and a generated one otherwise (auto), none or the path of an image
+    #[arg(long, env = "CLIMA_COVER", default_value_t = CoverMode::Auto)]
+    cover: CoverMode,
+
+    /// Embed the images of the single ePUB file as downloaded, without
+    /// resizing or filters. Only formats ePUB readers cannot show are converted
+    #[arg(long, env = "CLIMA_NO_RESIZE", default_value_t = false)]
+    no_resize: bool,
+
+    /// Convert the images of the single ePUB file to grayscale, lighter for
+    /// e-ink screens (Kindle, Kobo)
+    #[arg(long, env = "CLIMA_EINK", default_value_t = false, conflicts_with = "no_resize")]
+    eink: bool,
+
+    /// Draw the SVG figures as PNG images this many pixels wide, for readers
+    /// that cannot show SVG (embedded as they are otherwise)
+    #[arg(long, env = "CLIMA_SVG_RASTER")]
+    svg_raster: Option<u32>,
+
+    /// ePUB version of the single ePUB file: 3, or 2 for older readers (table
+    /// of contents in toc.ncx only, articles without HTML5 markup)
+    #[arg(long, env = "CLIMA_EPUB_VERSION", default_value_t = 3, value_parser = clap::value_parser!(u8).range(2..=3))]
+    epub_version: u8,
+
+    /// Write a Kobo ePUB of the single ePUB file too (<name>.kepub.epub), opened by
+    /// the faster reader of Kobo devices with reading statistics
+    #[arg(long, env = "CLIMA_KEPUB", default_value_t = false)]
+    kepub: bool,
+
+    /// Convert the single ePUB file for Kindle devices too: azw3, or mobi for
+    /// the older ones. Needs calibre (ebook-convert) or kindlegen
+    #[arg(long, env = "CLIMA_KINDLE", value_enum)]
+    kindle: Option<kindle::Format>,
+
+    /// Command converting the ePUB file with --kindle, given the input and the
+    /// output file (default: ebook-convert, or kindlegen for mobi, from the PATH)
+    #[arg(long, env = "CLIMA_CONVERTER", requires = "kindle")]
+    converter: Option<String>,
+
+    /// Write a copy of the PDF for e-readers too (<name>.ereader.pdf), with
+    /// the page margins cut: auto to find them on each page with Ghostscript,
+    /// or the margins in points (all, vertical,horizontal or top,right,bottom,left)
+    #[arg(long, env = "CLIMA_PDF_CROP")]
+    pdf_crop: Option<pdf::Crop>,
+
+    /// Write the downloaded PDF again with Ghostscript, with the images at a
+    /// lower resolution and linearized, to make it smaller
+    #[arg(long, env = "CLIMA_PDF_COMPRESS", default_value_t = false)]
+    pdf_compress: bool,
+
+    /// Resolution of the images of the PDF with --pdf-compress, in dots per inch
+    #[arg(long, env = "CLIMA_PDF_DPI", default_value_t = 150, value_parser = clap::value_parser!(u32).range(36..=600))]
+    pdf_dpi: u32,
+
+    /// Split the PDF in smaller files too (<name>-<part>.pdf): sections, found
+    /// by the titles of the articles on the pages, or page ranges such as
+    /// 1-3,7,cultura=12-14
+    #[arg(long, env = "CLIMA_PDF_SPLIT")]
+    pdf_split: Option<pdf::Split>,
+
+    /// Add an outline to the PDF, with the sections and the articles found on
+    /// the pages by their titles
+    #[arg(long, env = "CLIMA_PDF_OUTLINE", default_value_t = false)]
+    pdf_outline: bool,
+
+    /// Write the PDF as a comic book archive too (<name>.cbz), each page as an
+    /// image, for e-ink readers and comic apps. Needs Ghostscript
+    #[arg(long, env = "CLIMA_CBZ", default_value_t = false)]
+    cbz: bool,
+
+    /// Resolution of the pages of the CBZ file, in dots per inch
+    #[arg(long, env = "CLIMA_CBZ_DPI", default_value_t = 150, value_parser = clap::value_parser!(u32).range(36..=600))]
+    cbz_dpi: u32,
+
+    /// Draw the pages of the CBZ file in shades of gray
+    #[arg(long, env = "CLIMA_CBZ_GRAY", default_value_t = false)]
+    cbz_gray: bool,
+
+    /// Ghostscript command (default: gs, or gswin64c on Windows, from the PATH)
+    #[arg(long, env = "CLIMA_GHOSTSCRIPT")]
+    ghostscript: Option<String>,
+
+    /// Write the whole edition as one HTML file too (<name>.html), with the
+    /// stylesheet and the images inside, for reading in a browser
+    #[arg(long, env = "CLIMA_HTML", default_value_t = false)]
+    html: bool,
+
+    /// Write the whole edition as one Markdown file too (<name>.md), with the
+    /// text and the links of the articles, without images
+    #[arg(long, env = "CLIMA_MARKDOWN", default_value_t = false)]
+    markdown: bool,
+
+    /// Write the text of each article too, as UTF-8 files in a directory named
+    /// after the edition (<name>/001-<slug>.txt)
+    #[arg(long, env = "CLIMA_TEXT", default_value_t = false)]
+    text: bool,
+
+    /// With --text, write the text of the whole edition in one file too
+    /// (<name>.txt)
+    #[arg(long, env = "CLIMA_TEXT_ALL", default_value_t = false, requires = "text")]
+    text_all: bool,
+
+    /// Write the edition as a structured document too (<name>.json): metadata,
+    /// and title, kicker, summary, authors, photos and text of every article
+    #[arg(long, env = "CLIMA_DUMP", value_enum)]
+    dump: Option<corpus::Format>,
+
+    /// Write an index of the articles too (<name>.index.csv or .index.json):
+    /// slug, title, section, author, number of words and page
+    #[arg(long, env = "CLIMA_INDEX", value_enum)]
+    index: Option<index::Format>,
+
+    /// Read the articles aloud too, in an audiobook with a chapter for each
+    /// article (<name>.m4b or .mp3). Needs a text-to-speech engine and ffmpeg
+    #[arg(long, env = "CLIMA_AUDIO", value_enum)]
+    audio: Option<audio::Format>,
+
+    /// Text-to-speech command for --audio, given the text of an article on its
+    /// input and writing the WAV file {output}
+    #[arg(long, env = "CLIMA_TTS_COMMAND", default_value = audio::PIPER)]
+    tts_command: String,
+
+    /// Address the output directory is served at, for the links of the
+    /// podcast feed of the audiobooks (podcast.xml)
+    #[arg(long, env = "CLIMA_PODCAST_URL", requires = "audio")]
+    podcast_url: Option<String>,
+
+    /// Look of the single ePUB file: none (fonts and margins of the reader),
+    /// serif, sans or large
+    #[arg(long, env = "CLIMA_THEME", value_enum, default_value_t = Theme::None)]
+    theme: Theme,
+
+    /// CSS file added to the stylesheet of the single ePUB file, after the theme
+    #[arg(long, env = "CLIMA_STYLESHEET")]
+    stylesheet: Option<PathBuf>,
+
+    /// Directory with templates of the generated pages (cover.xhtml,
+    /// front.xhtml, section.xhtml, omitted.xhtml, credits.xhtml) replacing
+    /// the bundled ones
+    #[arg(long, env = "CLIMA_TEMPLATE_DIR")]
+    template_dir: Option<PathBuf>,
+
+    /// Leave the images out of the single ePUB file
+    #[arg(long, default_value_t = false)]
+    skip_images: bool,
+
+    /// Text-only single ePUB file: no images are downloaded, the cover and
+    /// the front pages are left out (same as all of --skip-images,
+    /// --skip-cover and --skip-front-pages)
+    #[arg(long, env = "CLIMA_NO_IMAGES", default_value_t = false, conflicts_with = "table_images")]
+    no_images: bool,
+
+    /// Leave the cover out of the single ePUB file
+    #[arg(long, default_value_t = false)]
+    skip_cover: bool,
+
+    /// Leave the table of contents page out of the single ePUB file
+    #[arg(long, default_value_t = false)]
+    skip_toc: bool,
+
+    /// Leave the front pages (photo, title and summary before the articles)
+    /// out of the single ePUB file
+    #[arg(long, default_value_t = false)]
+    skip_front_pages: bool,
+
+    /// Print progress events as JSON lines on stdout, for graphical front-ends
+    #[arg(long, default_value_t = false)]
+    progress_json: bool,
+
+    /// Check that login and API are working and quit (exit code 0 if healthy)
+    #[arg(long, default_value_t = false)]
+    healthcheck: bool,
+
+    #[command(flatten)]
+    posts_query: PostsQuery,
+}
+
+impl Args {
+    fn existing(&self) -> Existing {
+        if self.skip_existing {
+            Existing::Skip
+        } else if self.backup {
+            Existing::Backup
+        } else {
+            Existing::Overwrite
+        }
+    }
+
+    fn epub_version(&self) -> EpubVersion {
+        match self.epub_version {
+            2 => EpubVersion::V20,
+            _ => EpubVersion::V30,
+        }
+    }
+
+    fn skip(&self) -> Skip {
+        Skip {
+            images: self.skip_images || self.no_images,
+            cover: self.skip_cover || self.no_images || self.cover == CoverMode::None,
+            toc: self.skip_toc,
+            front_pages: self.skip_front_pages || self.no_images,
+        }
+    }
+}
+
+// Stages of the single ePUB file left out with the --skip-* flags
+#[derive(Clone, Copy, Debug, Default)]
+struct Skip {
+    images: bool,
+    cover: bool,
+    toc: bool,
+    front_pages: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the logged in user, membership and token expiry
+    Whoami,
+
+    /// Open the most recent PDF or ePUB file
+    Open {
+        /// Open the PDF version
+        #[arg(long, default_value_t = false, conflicts_with = "epub")]
+        pdf: bool,
+
+        /// Open the ePUB version
+        #[arg(long, default_value_t = false)]
+        epub: bool,
+
+        /// Command used to open the file (default: the platform default application)
+        #[arg(long, env = "CLIMA_READER")]
+        reader: Option<String>,
+    },
+
+    /// Summarize the editions and articles downloaded over a period
+    Usage {
+        /// Number of days to summarize
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+
+    /// Mail a summary of the last days (editions, failures, disk usage)
+    /// through --smtp-server, e.g. from cron
+    Weekly {
+        /// Number of days to summarize
+        #[arg(long, default_value_t = 7)]
+        days: i64,
+
+        /// Recipients of the summary, separated by commas (default: --smtp-to)
+        #[arg(long, env = "CLIMA_MAIL_TO", value_delimiter = ',')]
+        to: Vec<String>,
+    },
+
+    /// Search the text of the downloaded articles, e.g. search "terza guerra"
+    /// (words in double quotes are searched as a phrase)
+    Search {
+        /// Words to search
+        #[arg(required_unless_present = "reindex")]
+        query: Option<String>,
+
+        /// Number of articles listed
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// Build a single ePUB file of the articles found
+        #[arg(long, default_value_t = false)]
+        epub: bool,
+
+        /// Open the ePUB file of the articles found (implies --epub)
+        #[arg(long, default_value_t = false)]
+        open: bool,
+
+        /// Command used to open the file (default: the platform default application)
+        #[arg(long, env = "CLIMA_READER")]
+        reader: Option<String>,
+
+        /// Index again all the archived articles first
+        #[arg(long, default_value_t = false)]
+        reindex: bool,
+    },
+
+    /// Serve a web page listing the downloaded editions, to read them in the
+    /// browser and download their files
+    Serve {
+        /// Address to listen on, e.g. 0.0.0.0:8080 to be reachable from the
+        /// other devices of the network
+        #[arg(long, env = "CLIMA_LISTEN", default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+
+    /// Allow the uploads to Dropbox (with --dropbox-app) or Google Drive (with
+    /// --drive-client-id and --drive-client-secret), once: the token is kept
+    /// in the state directory
+    Authorize {
+        #[arg(value_enum)]
+        service: oauth::Service,
+    },
+
+    /// Build the single ePUB file again, without network access, from the
+    /// files kept with --keep-files
+    #[command(alias = "combine")]
+    Build {
+        /// Directory of the kept files (the cache directory of the download)
+        #[arg(long)]
+        from_dir: PathBuf,
+
+        /// Print the reading order, table of contents and resources of the
+        /// ePUB file instead of writing it
+        #[arg(long, default_value_t = false)]
+        preview: bool,
+    },
 }
 
 #[derive(Serialize, Debug, Deserialize)]
@@ -107,19 +1037,15 @@ pub struct Credentials {
     password: String,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Editions {
-    data: Edition,
-}
-
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct Edition {
     id: i32,
     slug: String,
     pdf: String,
     title: String,
+    #[serde(default)]
+    date: String,
     featured_image: Option<Image>,
 }
 
@@ -148,7 +1074,7 @@ struct Login {
     token: Token,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct Post {
     // id: i32, Not currently used
@@ -157,21 +1083,79 @@ struct Post {
     kicker: String,
     summary: String,
     excerpt: String,
-    // link: String, Not currently used
+    #[serde(default)]
+    link: String,
     #[serde(deserialize_with = "de_format_cover_position")]
     cover_position: i32, // Used to be cover_position: Option<i32>,
     cover_summary: String,
     cover_title: String,
     cover_image: Option<Image>,
     featured_image: Option<Image>,
+    #[serde(default, deserialize_with = "de_format_section")]
+    section: Option<Section>,
+    #[serde(default, deserialize_with = "de_format_authors")]
+    authors: Vec<String>,
 }
 
-#[derive(Deserialize, Debug)]
+impl Edition {
+    // The API does not always report the date, the slug contains it
+    fn date_or_slug(&self) -> &str {
+        if self.date.is_empty() {
+            &self.slug
+        } else {
+            &self.date
+        }
+    }
+
+    // Date of the edition, from its date or its slug (e.g. 2023-09-01 or
+    // il-manifesto-del-2023-09-01)
+    fn day(&self) -> Option<NaiveDate> {
+        [&self.date, &self.slug].into_iter().find_map(|text| {
+            (0..text.len().saturating_sub(9))
+                // a dash before the year is not a sign (-2023-09-0)
+                .filter(|&i| text.as_bytes()[i].is_ascii_digit() && text.is_char_boundary(i + 10))
+                .find_map(|i| NaiveDate::parse_from_str(&text[i..i + 10], "%Y-%m-%d").ok())
+        })
+    }
+}
+
+impl Post {
+    fn is_supplement(&self) -> bool {
+        match &self.section {
+            Some(section) => SUPPLEMENT_SECTIONS
+                .iter()
+                .any(|prefix| section.slug.starts_with(prefix)),
+            None => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Section {
+    name: String,
+    slug: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 struct Image {
     #[allow(unused_variables)]
     src: String,
     // alt: String Not currently used
+    #[serde(default)]
+    caption: Option<String>,
+    #[serde(default)]
+    credits: Option<String>,
+}
+
+impl Image {
+    // Credits of the photo, from the caption if missing
+    fn credits(&self) -> Option<&str> {
+        self.credits
+            .as_deref()
+            .or(self.caption.as_deref())
+            .filter(|credits| !credits.trim().is_empty())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -179,424 +1163,2090 @@ struct Data {
     data: Vec<Post>,
 }
 
-fn write_file(filename: String, content: Bytes, is_tmp: bool) -> std::io::Result<()> {
-    let path;
-    let tmp_file = std::env::temp_dir().join("clima-rs").join(&filename);
+// Write to tmp_dir if given, to the current directory otherwise
+fn write_file(filename: String, content: Bytes, tmp_dir: Option<&Path>) -> Result<()> {
+    let tmp_file;
 
-    if is_tmp {
+    let path = if let Some(tmp_dir) = tmp_dir {
         // create if missing
-        if let Err(e) = create_dir(tmp_file.parent().unwrap()) {
-            if e.kind() != ErrorKind::AlreadyExists {
-                return Err(e);
-            }
-        }
+        create_dir_all(tmp_dir).with_path(tmp_dir)?;
 
-        path = tmp_file.as_path();
+        tmp_file = tmp_dir.join(&filename);
+        tmp_file.as_path()
     } else {
-        path = Path::new(&filename);
-    }
-
-    let mut file = match File::create(&path) {
-        Err(why) => panic!("Couldn't create {}", why),
-        Ok(file) => file,
+        Path::new(&filename)
     };
-    file.write_all(&content)?;
+
+    let mut file = File::create(path).with_path(path)?;
+    file.write_all(&content).with_path(path)?;
     Ok(())
 }
 
-fn extract_file_from_url(url_str: &String) -> Result<String, Box<dyn std::error::Error>> {
-    let url = Url::parse(&url_str)?;
-    let path_segments = url.path_segments().ok_or_else(|| "cannot be base")?;
-    Ok(String::from(path_segments.last().unwrap()))
+fn extract_file_from_url(url_str: &str) -> Result<String> {
+    let url = Url::parse(url_str)?;
+    let mut path_segments = url.path_segments().ok_or("cannot be base")?;
+    Ok(String::from(path_segments.next_back().unwrap_or_default()))
 }
 
-fn resize_image(image_path: PathBuf) -> Result<Cursor<Vec<u8>>, Box<dyn std::error::Error>> {
-    let reader = ImageReader::open(image_path).expect("Failed reading image");
+// The format is guessed from the content: the API sometimes serves WebP
+// images with a .jpg name. Returns the extension, the media type and the
+// content of the processed image: JPEG for photos, PNG for line art
+fn resize_image(image_path: PathBuf, filters: &[Filter]) -> Result<(&'static str, &'static str, Vec<u8>)> {
+    let reader = ImageReader::open(&image_path).with_path(&image_path)?;
     let img = reader.with_guessed_format()?.decode()?;
 
-    let mut buff = Cursor::new(Vec::new());
-
-    img.thumbnail(600, 600).write_to(&mut buff, ImageFormat::Jpeg)?;
-    buff.rewind().unwrap();
-    Ok(buff)
+    // decided on the original, resizing blends the edges into new colors
+    let line_art = is_line_art(&img);
+    let img = filters::apply(filters, img);
+    if line_art {
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageFormat::Png)?;
+        return Ok(("png", "image/png", png.into_inner()));
+    }
+    Ok(("jpg", "image/jpeg", to_jpeg(img)?))
 }
 
-fn combine_articles(
-    edition: Edition,
-    posts: Data,
-    keep_files: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create a new EpubBuilder using the zip library
-    let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
-
-    // Set some metadata
-    builder.add_author("il Manifesto");
-    builder.set_title(&edition.title);
-    builder.set_lang("it");
-    builder.set_toc_name(&edition.title);
-    builder.epub_version(EpubVersion::V30);
-    //builder.set_publication_date Maybe in the future
-
-    let tmp_dir = std::env::temp_dir().join("clima-rs");
-
-    // Add cover
-    let cover_file = &format!("{}.jpg", edition.slug);
-    let cover_path = tmp_dir.join(cover_file);
-    if cover_path.is_file() {
-        builder.add_cover_image("cover.jpg", File::open(cover_path).unwrap(), "image/jpeg")?;
-    }
+// Cartoons, charts and maps have few flat colors: JPEG blurs their edges
+// and text, while PNG keeps them sharp in a smaller file
+fn is_line_art(img: &DynamicImage) -> bool {
+    const MAX_COLORS: usize = 256;
+    let mut colors = HashSet::new();
+    img.to_rgba8().pixels().all(|pixel| {
+        colors.insert(pixel.0);
+        colors.len() <= MAX_COLORS
+    })
+}
 
-    builder.inline_toc();
+// JPEG image of the cover given with --cover <file>
+fn read_cover(path: &Path) -> Result<Vec<u8>> {
+    let content = std::fs::read(path).with_path(path)?;
+    let img = if svg::is_svg(&content) {
+        svg::rasterize(&content, cover::WIDTH).and_then(|png| Ok(image::load_from_memory(&png)?))
+    } else {
+        image::load_from_memory(&content).map_err(Error::from)
+    };
+    let img = img.map_err(|e| format!("cover {}: {}", path.display(), e))?;
+    to_jpeg(cover::fit(img))
+}
 
-    let mut posts_data = posts.data;
+// The JPEG encoder only takes 8 bit gray and RGB images: transparent pixels
+// (WebP, PNG) are put on white
+fn to_jpeg(img: DynamicImage) -> Result<Vec<u8>> {
+    let img = match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => img,
+        img if img.color().has_alpha() => {
+            let mut rgb = RgbImage::from_pixel(img.width(), img.height(), Rgb([255, 255, 255]));
+            for (x, y, pixel) in img.to_rgba8().enumerate_pixels() {
+                let [r, g, b, a] = pixel.0;
+                let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+                rgb.put_pixel(x, y, Rgb([blend(r), blend(g), blend(b)]));
+            }
+            DynamicImage::ImageRgb8(rgb)
+        }
+        img if img.color().has_color() => DynamicImage::ImageRgb8(img.to_rgb8()),
+        img => DynamicImage::ImageLuma8(img.to_luma8()),
+    };
 
-    // sort by cover position
-    posts_data.sort_by_key(|element| element.cover_position );
+    let mut jpeg = Cursor::new(Vec::new());
+    img.write_to(&mut jpeg, ImageFormat::Jpeg)?;
+    Ok(jpeg.into_inner())
+}
 
-    // add cover page
-    for post in &posts_data {
-        if post.cover_image.is_some() {
-            let image_url = &post.cover_image.as_ref().unwrap().src;
-            let image_name = extract_file_from_url(image_url)?;
-            let image_path = tmp_dir.join(&image_name);
+// Titles of the front page articles (sorted by cover position) as the
+// description of the edition
+fn headlines(posts: &[Post]) -> Option<String> {
+    let titles: Vec<String> = posts
+        .iter()
+        .filter(|post| post.cover_position != 99 && !post.title.is_empty())
+        .map(|post| xhtml::plain(&post.title))
+        .collect();
+    (!titles.is_empty()).then(|| titles.join(" · "))
+}
 
-            // convert and resize to small jpegs
-            if Path::new(&image_path).exists() {
-                let unique_image_name = format!("{}.jpg", generate(12, CHARSET));
-
-                // file name is used in the id of xml file and cannot start with number
-                if let Ok(data) = resize_image(image_path) {
-                    builder.add_resource(&unique_image_name,
-                                            data,
-                                            "image/jpeg",
-                                            )?;
-                } else {
-                    info!("Failed to resize image!");
-                }
+// Sections and kickers of the articles, each once
+fn subjects<'a>(posts: impl Iterator<Item = &'a Post>) -> Vec<String> {
+    let mut subjects: Vec<String> = Vec::new();
+    for post in posts {
+        let section = post.section.as_ref().map(|section| section.name.as_str());
+        for subject in [section, Some(post.kicker.as_str())].into_iter().flatten() {
+            let subject = xhtml::plain(subject);
+            if !subject.is_empty() && !subjects.iter().any(|known| known.to_lowercase() == subject.to_lowercase()) {
+                subjects.push(subject);
+            }
+        }
+    }
+    subjects
+}
 
+// Keep the articles of the authors (all when none is given) that match the
+// --match patterns and not the --skip ones, at most max_articles of them, in
+// order of importance in the front page (cover position), supplements last.
+// Returns the selected and the omitted posts.
+fn select_posts(mut posts: Vec<Post>, args: &Args) -> (Vec<Post>, Vec<Post>) {
+    posts.sort_by_key(|post| (post.is_supplement(), post.cover_position));
+
+    // the names as given by the API, in any case
+    let words = |name: &str| xhtml::plain(name).to_lowercase().split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    let authors: Vec<_> = args.authors.iter().map(|name| words(name)).collect();
+    let selected = |post: &Post| {
+        let text = [&post.title, &post.kicker, &post.summary].map(|field| xhtml::plain(field)).join("\n");
+        (authors.is_empty() || post.authors.iter().any(|author| authors.contains(&words(author))))
+            && (args.match_patterns.is_empty() || args.match_patterns.iter().any(|pattern| pattern.is_match(&text)))
+            && !args.skip_patterns.iter().any(|pattern| pattern.is_match(&text))
+    };
+    let (mut posts, mut omitted): (Vec<Post>, Vec<Post>) = posts
+        .into_iter()
+        .partition(|post| selected(post));
+
+    if let Some(max) = args.max_articles.filter(|max| *max < posts.len()) {
+        let mut rest = posts.split_off(max);
+        rest.append(&mut omitted);
+        omitted = rest;
+    }
+    (posts, omitted)
+}
 
+// Add the front page (if the article has an image) and the content of an article
+fn add_article(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    locators: &mut Locators,
+    post: &Post,
+    tmp_dir: &Path,
+    options: &CombineOptions,
+    level: i32,
+) -> Result<()> {
+    // add image to archive
+    if let Some(featured_image) = post.featured_image.as_ref().filter(|_| !options.skip.front_pages) {
+        let image_url = &featured_image.src;
+        let image_name = extract_file_from_url(image_url)?;
+        let image_path = tmp_dir.join(&image_name);
+
+        // convert and resize to small jpegs
+
+        if options.skip.images || Path::new(&image_path).exists() {
+            let image = add_image(builder, &image_path, options)?;
+
+            let title_file = format!("{}-front.xhtml", post.slug);
+            let title = if post.kicker.is_empty() {
+                &post.title
+            } else {
+                &post.kicker
+            };
+            let title_content = pages::render(
+                &options.layout.front,
+                options.lang,
+                json!({ "title": title, "post": post }),
+                &[("image", &image), ("summary", &xhtml::sanitize(&post.excerpt))],
+            );
+
+            add_page(
+                builder,
+                locators,
+                EpubContent::new(title_file, title_content.as_bytes())
+                    .reftype(ReferenceType::Text),
+            )?;
+        }
+    }
 
-                let title_file = format!("{}-cover.xhtml", post.slug);
-                let title_content = format!(
-                    IMAGE_HTML!(),
-                    tsize = "h1",
-                    title = post.cover_title,
-                    imgurl = unique_image_name,
-                    summary = post.cover_summary
-                );
+    // Files are stored in temp directory in this case
+    let filename = tmp_dir.join(format!("{}.epub", post.slug));
 
-                builder.add_content(
-                    EpubContent::new(title_file, title_content.as_bytes())
-                        .reftype(ReferenceType::Text),
-                )?;
+    let mut doc = match EpubDoc::new(&filename) {
+        Ok(doc) => doc,
+        Err(e) => {
+            if filename.exists() {
+                warn!("{} left out, cannot open it: {}", filename.display(), e);
             }
+            return Ok(());
         }
-    }
+    };
 
-    for post in &posts_data {
-        // add image to archive
-        if post.featured_image.is_some() {
-            let image_url = &post.featured_image.as_ref().unwrap().src;
-            let image_name = extract_file_from_url(image_url)?;
-            let image_path = tmp_dir.join(&image_name);
+    let mut chapters = Vec::new();
+    for (path, chapter) in article_chapters(&mut doc)? {
+        chapters.push(embed_images(builder, &mut doc, &post.slug, &path, &chapter, tmp_dir, options)?);
+    }
+    let mut content = xhtml::strip_external(&join_chapters(chapters));
+    if options.version == EpubVersion::V20 {
+        content = xhtml::epub2(&content);
+    }
 
-            // convert and resize to small jpegs
+    let content_file = format!("{}.xhtml", post.slug);
 
-            if Path::new(&image_path).exists() {
-                let unique_image_name = format!("{}.jpg", generate(12, CHARSET));
-
-                // file name is used in the id of xml file and cannot start with number
-                if let Ok(data) = resize_image(image_path) {
-                    builder.add_resource(&unique_image_name,
-                                            data,
-                                            "image/jpeg",
-                                            )?;
-                } else {
-                    info!("Failed to resize image!");
-                }
+    let mut content = content
+        .replace("h0", "h1")
+        .replace("quote", "blockquote");
+    if let Some(head_end) = content.find("</head>") {
+        content.insert_str(head_end, STYLESHEET_LINK);
+    }
+    if let Some(screen_width) = options.table_width {
+        let (converted, images) = tables::convert(&content, &post.slug, screen_width);
+        for (name, svg) in images {
+            builder.add_resource(&name, svg.as_bytes(), "image/svg+xml")?;
+        }
+        content = converted;
+    }
+    let mut title = xhtml::plain(&post.title);
 
-                let title_file = format!("{}-front.xhtml", post.slug);
-                let title_content = format!(
-                    IMAGE_HTML!(),
-                    tsize = "h4",
-                    title = if post.kicker.is_empty() {
-                        &post.title
-                    } else {
-                        &post.kicker
-                    },
-                    imgurl = unique_image_name,
-                    summary = post.excerpt
-                );
+    // Byline under the title, also shown in the table of contents
+    let authors = if post.authors.is_empty() {
+        article_authors(&doc)
+    } else {
+        post.authors.clone()
+    };
+    if !authors.is_empty() {
+        let authors = xhtml::plain(&authors.join(", "));
+        let byline = format!(BYLINE_HTML!(), authors = xhtml::escape(&authors));
+        let at = match content.find("</h1>") {
+            Some(end) => Some(end + "</h1>".len()),
+            None => content.find("<body").and_then(|start| Some(start + content[start..].find('>')? + 1)),
+        };
+        if let Some(at) = at {
+            content.insert_str(at, &byline);
+        }
+        title = format!("{} — {}", title, authors);
+    }
 
-                builder.add_content(
-                    EpubContent::new(title_file, title_content.as_bytes())
-                        .reftype(ReferenceType::Text),
-                )?;
+    // Notice at the top of articles corrected since the previous download
+    if options.updated.contains(&post.slug) {
+        if let Some(start) = content.find("<body") {
+            if let Some(end) = content[start..].find('>') {
+                let notice = format!(UPDATED_HTML!(), notice = options.strings.updated_notice);
+                content.insert_str(start + end + 1, &notice);
             }
         }
+        title = format!("{} ({})", title, options.strings.updated_suffix);
+    }
 
-        // Files are stored in temp directory in this case
-        let filename = tmp_dir.join(&format!("{}.epub", post.slug));
+    // Add a chapter, mark it as beginning of the "real content"
+    let position = add_page(
+        builder,
+        locators,
+        EpubContent::new(&content_file, content.as_bytes())
+            .title(&title)
+            .reftype(ReferenceType::Text)
+            .level(level),
+    )?;
+    locators.article(post, &content_file, position, &content);
 
-        //let filename = format!("{}.epub", post.slug);
-        let doc = EpubDoc::new(&filename);
-        if !doc.is_ok() {
+    Ok(())
+}
+
+// Posts grouped by section, in the order of the first post of each section.
+// Posts without section are kept on their own.
+fn by_section(posts: &[Post]) -> Vec<(Option<&Section>, Vec<&Post>)> {
+    let mut groups: Vec<(Option<&Section>, Vec<&Post>)> = Vec::new();
+    for post in posts {
+        let Some(section) = &post.section else {
+            groups.push((None, vec![post]));
             continue;
         };
+        match groups.iter_mut().find(|(s, _)| s.is_some_and(|s| s.slug == section.slug)) {
+            Some((_, posts)) => posts.push(post),
+            None => groups.push((Some(section), vec![post])),
+        }
+    }
+    groups
+}
 
-        let mut doc = doc.unwrap();
-
-        assert_eq!("application/xhtml+xml", doc.get_current_mime().unwrap());
+// Title page of a section, with the articles under it in the TOC
+fn add_section(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    locators: &mut Locators,
+    prefix: &str,
+    section: &Section,
+    posts: &[&Post],
+    tmp_dir: &Path,
+    options: &CombineOptions,
+) -> Result<()> {
+    let titles: String = posts
+        .iter()
+        .map(|post| format!("<li>{}</li>", xhtml::text(&post.title)))
+        .collect();
+    let section_content = pages::render(
+        &options.layout.section,
+        options.lang,
+        json!({ "title": section.name, "section": section }),
+        &[("titles", &titles)],
+    );
+
+    add_page(
+        builder,
+        locators,
+        EpubContent::new(format!("{}-{}.xhtml", prefix, section.slug), section_content.as_bytes())
+            .title(xhtml::plain(&section.name))
+            .reftype(ReferenceType::Text),
+    )?;
+
+    for post in posts {
+        add_article(builder, locators, post, tmp_dir, options, 2)?;
+    }
+    Ok(())
+}
 
-        let content = doc
-            .get_resource_str_by_path("OEBPS/Chapter001.xhtml")
-            .unwrap();
+// Documents of an article ePUB file in reading order, with their path in
+// the file: long articles are split in more chapters
+fn article_chapters<R: Read + std::io::Seek>(doc: &mut EpubDoc<R>) -> Result<Vec<(PathBuf, String)>> {
+    let paths: Vec<PathBuf> = doc
+        .spine
+        .iter()
+        .filter_map(|id| doc.resources.get(id))
+        .filter(|(_, mime)| mime == "application/xhtml+xml")
+        .map(|(path, _)| path.clone())
+        .collect();
+    if paths.is_empty() {
+        let path = PathBuf::from("OEBPS/Chapter001.xhtml");
+        let content = doc.get_resource_str_by_path(&path)?;
+        return Ok(vec![(path, content)]);
+    }
 
-        let content_file = format!("{}.xhtml", post.slug);
+    let mut chapters = Vec::new();
+    for path in paths {
+        let content = doc.get_resource_str_by_path(&path)?;
+        chapters.push((path, content));
+    }
+    Ok(chapters)
+}
 
-        // Add a chapter, mark it as beginning of the "real content"
-        builder.add_content(
-            EpubContent::new(
-                content_file,
-                content
-                    .replace("h0", "h1")
-                    .replace("quote", "blockquote")
-                    .as_bytes(),
-            )
-            .title(&post.title)
-            .reftype(ReferenceType::Text), //.level(2)
-        )?;
+// The chapters as one document, their bodies are added to the first one
+fn join_chapters(chapters: Vec<String>) -> String {
+    let mut chapters = chapters.into_iter();
+    let mut content = chapters.next().unwrap_or_default();
+    for chapter in chapters {
+        if let (Some(body), Some(end)) = (xhtml::body(&chapter), content.rfind("</body>")) {
+            content.insert_str(end, body);
+        }
     }
+    content
+}
 
-    // Use standard file writer?
-    let filename = format!("{}.epub", &edition.slug);
-    let f = File::create(&filename).expect("Unable to create file");
-    builder.generate(f)?;
+// Images packaged in the article file are added to the single ePUB file (and
+// go through the same conversions as the others), their <img> elements point
+// to the new names. Images that are left out are removed.
+fn embed_images<R: Read + std::io::Seek>(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    doc: &mut EpubDoc<R>,
+    slug: &str,
+    chapter_path: &Path,
+    chapter: &str,
+    tmp_dir: &Path,
+    options: &CombineOptions,
+) -> Result<String> {
+    let mut output = String::with_capacity(chapter.len());
+    let mut rest = chapter;
+    while let Some(start) = rest.find("<img") {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        let tag = &rest[start..end];
+        rest = &rest[end..];
 
-    // Keep epub files if requested
-    if !keep_files {
-        remove_dir_all(std::env::temp_dir().join("clima-rs"))?;
+        let Some(src) = xhtml::attribute(tag, "src").filter(|src| !src.contains(':')) else {
+            output.push_str(tag);
+            continue;
+        };
+        let path = xhtml::resolve(chapter_path, src);
+        let content = match doc.get_resource_by_path(&path) {
+            Ok(content) => content,
+            Err(_) => {
+                output.push_str(tag);
+                continue;
+            }
+        };
+        // written next to the downloaded images, add_image reads them from there
+        let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let image_path = tmp_dir.join(format!("{}-{}", slug, file_name));
+        std::fs::write(&image_path, content).with_path(&image_path)?;
+        if let Some(name) = add_image_resource(builder, &image_path, options)? {
+            output.push_str(&tag.replacen(src, &name, 1));
+        }
     }
-
-    Ok(())
+    output.push_str(rest);
+    Ok(output)
 }
 
-fn show_editions() {
+// Authors of an article ePUB file, without the newspaper itself
+fn article_authors<R: Read + std::io::Seek>(doc: &EpubDoc<R>) -> Vec<String> {
+    doc.metadata
+        .get("creator")
+        .into_iter()
+        .flatten()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("il manifesto"))
+        .map(String::from)
+        .collect()
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+// Add a resized copy of the image (or the image itself with --no-resize),
+// returns the <img> element showing it
+fn add_image(builder: &mut EpubBuilder<ZipLibrary>, image_path: &Path, options: &CombineOptions) -> Result<String> {
+    let name = add_image_resource(builder, image_path, options)?;
+    Ok(name.map(|name| format!(r#"<img src="{}" />"#, name)).unwrap_or_default())
+}
 
-    // Create the client
-    let client = reqwest::Client::builder().cookie_store(true).build()?;
+// Name of the image in the ePUB file, none if it is left out
+fn add_image_resource(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    image_path: &Path,
+    options: &CombineOptions,
+) -> Result<Option<String>> {
+    if options.skip.images {
+        return Ok(None);
+    }
+    // file name is used in the id of xml file and cannot start with number
+    let unique_name = generate(12, CHARSET);
+
+    // figures are not resized, nor go through the filters
+    if let Some(content) = std::fs::read(image_path).ok().filter(|content| svg::is_svg(content)) {
+        let (image_name, data, mime) = match options.svg_raster {
+            Some(width) => (format!("{}.png", unique_name), svg::rasterize(&content, width), "image/png"),
+            None => (format!("{}.svg", unique_name), Ok(content), "image/svg+xml"),
+        };
+        return match data {
+            Ok(data) => {
+                builder.add_resource(&image_name, data.as_slice(), mime)?;
+                Ok(Some(image_name))
+            }
+            Err(e) => {
+                warn!("{} left out: {}", image_path.display(), e);
+                Ok(None)
+            }
+        };
+    }
 
-    // Get last edition (currently the only supported operation)
-    let res = client
-        .get(&format!("{}/wp/editions/latest", BASE_URL))
-        //.headers(headers)
-        .send()
-        .await?;
+    if options.no_resize {
+        match original_image(image_path) {
+            Some((extension, mime, data)) => {
+                let image_name = format!("{}.{}", unique_name, extension);
+                builder.add_resource(&image_name, data.as_slice(), mime)?;
+                return Ok(Some(image_name));
+            }
+            None => debug!("{} converted, ePUB readers cannot show its format", image_path.display()),
+        }
+    }
 
-    // Parse the response body
-    let edition = res.json::<Edition>().await?;
+    match resize_image(image_path.to_path_buf(), options.filters) {
+        Ok((extension, mime, data)) => {
+            let image_name = format!("{}.{}", unique_name, extension);
+            builder.add_resource(&image_name, data.as_slice(), mime)?;
+            Ok(Some(image_name))
+        }
+        Err(e) => {
+            warn!("{} left out: {}", image_path.display(), e);
+            Ok(None)
+        }
+    }
+}
 
-    info!("{:?}", edition.slug);
+// Content of the image if ePUB readers can show it as it is (JPEG, PNG
+// and GIF), with its extension and media type
+fn original_image(path: &Path) -> Option<(&'static str, &'static str, Vec<u8>)> {
+    let content = std::fs::read(path).ok()?;
+    let (extension, mime) = match image::guess_format(&content).ok()? {
+        ImageFormat::Jpeg => ("jpg", "image/jpeg"),
+        ImageFormat::Png => ("png", "image/png"),
+        ImageFormat::Gif => ("gif", "image/gif"),
+        _ => return None,
+    };
+    Some((extension, mime, content))
+}
 
-    // Check if token is already available, or ask it to server
-    let mut login: Login;
-    if Path::new("login.json").is_file() {
-        let file = File::open("login.json")?;
-        let reader = BufReader::new(file);
+// Add a page at the end of the reading order, returns its position
+fn add_page<R: Read>(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    locators: &mut Locators,
+    content: EpubContent<R>,
+) -> Result<usize> {
+    let position = locators.page(&content.toc.url);
+    builder.add_content(content)?;
+    Ok(position)
+}
 
-        // Read token saved with first login
-        login = serde_json::from_reader(reader)?;
+// Options of the single ePUB file
+struct CombineOptions<'a> {
+    keep_files: bool,
+    // articles changed since the previous download
+    updated: &'a [String],
+    strings: &'static Strings,
+    lang: &'a str,
+    // screen width in pixels, wider tables are converted to images
+    table_width: Option<u32>,
+    // the ePUB file
+    output: &'a Path,
+    cover: &'a CoverMode,
+    skip: Skip,
+    // applied to the images
+    filters: &'a [Filter],
+    // images embedded as downloaded
+    no_resize: bool,
+    // grayscale cover, the images get the filters
+    eink: bool,
+    // SVG figures drawn as PNG images this wide
+    svg_raster: Option<u32>,
+    // ePUB 2 or 3
+    version: EpubVersion,
+    // Kobo ePUB file
+    kepub: Option<&'a Path>,
+    // HTML file of the edition
+    html: Option<&'a Path>,
+    // Markdown file of the edition
+    markdown: Option<&'a Path>,
+    // directory of the text files of the articles
+    text: Option<&'a Path>,
+    // text file of the edition
+    text_all: Option<&'a Path>,
+    // JSON document of the edition
+    dump: Option<&'a Path>,
+    // index of the articles
+    index: Option<(index::Format, &'a Path)>,
+    // audiobook of the articles
+    audio: Option<(audio::Format, &'a Path)>,
+    tts_command: &'a str,
+    // address of the podcast feed
+    podcast_url: Option<&'a str>,
+    // stylesheet and templates of the generated pages
+    layout: &'a Layout,
+    // print the structure instead of writing the file
+    preview: bool,
+}
 
-        // refresh token (if required, but we do it every time now)
-        let req_str = format!(r#"{{"refreshToken":"{}"}}"#, login.token.refresh_token);
-        let json_req: serde_json::Value = serde_json::from_str(&req_str).unwrap();
+// Whether the single ePUB file can be written. Its locators go with it, kept
+// or moved to a backup together.
+fn prepare_single(existing: Existing, path: &Path) -> Result<bool> {
+    if !existing.prepare(path)? {
+        return Ok(false);
+    }
+    // without the ePUB file they are stale, written again under Skip
+    if existing != Existing::Skip {
+        existing.prepare(&path.with_extension(locators::FILE_NAME))?;
+    }
+    Ok(true)
+}
 
-        let res = client
-            .post(&format!("{}/auth/token", BASE_URL))
-            .json(&json_req)
-            .send()
-            .await?;
+// Files made with the single ePUB file, when requested, with whether they are
+// written under the policy for existing files
+#[derive(Default)]
+struct SingleOutputs {
+    kepub: Option<(PathBuf, bool)>,
+    html: Option<(PathBuf, bool)>,
+    markdown: Option<(PathBuf, bool)>,
+    // directory of the text files of the articles
+    text: Option<(PathBuf, bool)>,
+    text_all: Option<(PathBuf, bool)>,
+    dump: Option<(PathBuf, bool)>,
+    index: Option<(index::Format, PathBuf, bool)>,
+    audio: Option<(audio::Format, PathBuf, bool)>,
+}
 
-        // update token part
-        login.token = res.json::<Token>().await?;
+impl SingleOutputs {
+    fn prepare(args: &Args, output_dir: &Path, name_template: &str, edition: &Edition, single_path: &Path) -> Result<SingleOutputs> {
+        let existing = args.existing();
+        let path = |extension: &str| output_dir.join(template::file_name(name_template, edition, extension));
+        let prepare = |requested: bool, path: PathBuf| -> Result<Option<(PathBuf, bool)>> {
+            if !requested {
+                return Ok(None);
+            }
+            let write = existing.prepare(&path)?;
+            Ok(Some((path, write)))
+        };
 
-        // save it again
-        let file = match File::create("login.json") {
-            Err(why) => panic!("couldn't create {}", why),
-            Ok(file) => file,
+        let text_path = path("txt");
+        let text = prepare(args.text, text_path.with_extension(""))?;
+        // part of the text output, not written without the directory
+        let text_all = match &text {
+            Some((_, true)) => prepare(args.text_all, text_path)?,
+            _ => Some((text_path, false)).filter(|_| args.text_all),
         };
-        serde_json::to_writer(&file, &login)?;
+        Ok(SingleOutputs {
+            kepub: prepare(args.kepub, single_path.with_extension("kepub.epub"))?,
+            html: prepare(args.html, path("html"))?,
+            markdown: prepare(args.markdown, path("md"))?,
+            text,
+            text_all,
+            dump: prepare(args.dump.is_some(), path("json"))?,
+            index: args
+                .index
+                .map(|format| -> Result<_> {
+                    let path = path(format.extension());
+                    let write = existing.prepare(&path)?;
+                    Ok((format, path, write))
+                })
+                .transpose()?,
+            audio: args
+                .audio
+                .map(|format| -> Result<_> {
+                    let path = path(format.extension());
+                    let write = existing.prepare(&path)?;
+                    Ok((format, path, write))
+                })
+                .transpose()?,
+        })
+    }
+
+    // Logs the files written, adds those found to `produced`
+    fn finish(self, produced: &mut Vec<PathBuf>) {
+        let outputs = [
+            self.kepub.map(|(path, write)| ("kepub", path, write)),
+            self.html.map(|(path, write)| ("html", path, write)),
+            self.markdown.map(|(path, write)| ("md", path, write)),
+            self.text.map(|(path, write)| ("txt", path, write)),
+            self.text_all.map(|(path, write)| ("txt", path, write)),
+            self.dump.map(|(path, write)| ("json", path, write)),
+            self.index.map(|(format, path, write)| (format.extension(), path, write)),
+            self.audio.map(|(format, path, write)| (format.extension(), path, write)),
+        ];
+        // the audiobook is not written when the speech synthesis fails
+        for (format, path, write) in outputs.into_iter().flatten().filter(|(_, path, _)| path.exists()) {
+            if write {
+                info!(event = "file", format = format, path = path.display().to_string().as_str(); "Wrote {}", path.display());
+            }
+            // files only, the text directory is not delivered
+            if path.is_file() {
+                produced.push(path);
+            }
+        }
+    }
+}
+
+// Path of an output, if it is written
+fn written(output: &Option<(PathBuf, bool)>) -> Option<&Path> {
+    output.as_ref().filter(|(_, write)| *write).map(|(path, _)| path.as_path())
+}
+
+// Kindle copy of the single ePUB file, which is kept when the conversion fails
+fn kindle_copy(args: &Args, single_path: &Path, summary: &mut Summary, produced: &mut Vec<PathBuf>) -> Result<()> {
+    let Some(format) = args.kindle else {
+        return Ok(());
+    };
+    let path = single_path.with_extension(format.extension());
+    if args.existing().prepare(&path)? {
+        match kindle::convert(single_path, format, args.converter.as_deref()) {
+            Ok(path) => {
+                info!(event = "file", format = format.extension(), path = path.display().to_string().as_str(); "Wrote {}", path.display());
+                produced.push(path);
+            }
+            Err(e) => summary.failed("kindle", &path.display().to_string(), &e),
+        }
+    } else {
+        produced.push(path);
+    }
+    Ok(())
+}
+
+fn combine_articles(
+    edition: &Edition,
+    posts: Data,
+    omitted: &[Post],
+    tmp_dir: &Path,
+    options: &CombineOptions,
+) -> Result<()> {
+    // Create a new EpubBuilder using the zip library
+    let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
+
+    // Set some metadata
+    builder.add_author("il Manifesto");
+    // epub-builder escapes the metadata and the TOC, they are given as plain text
+    builder.set_title(xhtml::plain(&edition.title));
+    builder.set_lang("it");
+    builder.set_toc_name(xhtml::plain(&edition.title));
+    builder.epub_version(options.version);
+    builder.set_uuid(metadata::uuid(edition.id));
+    // dc:date, library software sorts the editions by it
+    if let Some(day) = edition.day() {
+        builder.set_publication_date(day.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    // Add cover, the photo of the edition if it can be read, converted to
+    // JPEG when it is in another format
+    let cover_file = &format!("{}.jpg", edition.slug);
+    let cover_path = tmp_dir.join(cover_file);
+    let photo = match std::fs::read(&cover_path) {
+        Ok(content) if image::guess_format(&content).is_ok_and(|format| format == ImageFormat::Jpeg) => Some(content),
+        Ok(content) => image::load_from_memory(&content).ok().map(to_jpeg).transpose()?,
+        Err(_) => None,
+    };
+    let cover = match (options.cover, photo) {
+        _ if options.skip.cover => None,
+        (CoverMode::Auto | CoverMode::Image, Some(photo)) => Some(photo),
+        (CoverMode::Auto, None) | (CoverMode::Generated, _) => {
+            Some(cover::generate(&edition.title, edition.date_or_slug())?)
+        }
+        (CoverMode::File(path), _) => Some(read_cover(path)?),
+        (CoverMode::Image, None) | (CoverMode::None, _) => None,
+    };
+    if let Some(mut cover) = cover {
+        // the cover is not resized, only made gray
+        if options.eink {
+            cover = to_jpeg(filters::apply(filters::EINK, image::load_from_memory(&cover)?))?;
+        }
+        builder.add_cover_image("cover.jpg", cover.as_slice(), "image/jpeg")?;
+    }
+
+    builder.stylesheet(options.layout.stylesheet.as_bytes())?;
+    let mut locators = Locators::new(&edition.slug);
+    if !options.skip.toc {
+        builder.inline_toc();
+        locators.page("toc.xhtml");
+    }
+
+    let mut posts_data = posts.data;
+
+    // sort by cover position, then by title
+    posts_data.sort_by(|a, b| {
+        a.cover_position
+            .cmp(&b.cover_position)
+            .then_with(|| collate::cmp(&a.title, &b.title))
+    });
+
+    let context = serde_json::to_value(template::Context {
+        edition,
+        date: edition.date_or_slug(),
+        lang: options.lang,
+        posts: &posts_data,
+        omitted,
+        updated: options.updated,
+    })?;
+
+    // supplements ("inserti") are kept apart from the main edition
+    let (supplements, posts_data): (Vec<Post>, Vec<Post>) =
+        posts_data.into_iter().partition(|post| post.is_supplement());
+
+    // searchable in Calibre and OPDS catalogs
+    if let Some(description) = headlines(&posts_data) {
+        builder.add_description(description);
+    }
+    builder.set_subjects(subjects(posts_data.iter().chain(&supplements)));
+
+    // add cover page
+    for post in posts_data.iter().filter(|_| !options.skip.front_pages) {
+        if let Some(cover_image) = &post.cover_image {
+            let image_url = &cover_image.src;
+            let image_name = extract_file_from_url(image_url)?;
+            let image_path = tmp_dir.join(&image_name);
+
+            // convert and resize to small jpegs
+            if options.skip.images || Path::new(&image_path).exists() {
+                let image = add_image(&mut builder, &image_path, options)?;
+
+                let title_file = format!("{}-cover.xhtml", post.slug);
+                let title_content = pages::render(
+                    &options.layout.cover,
+                    options.lang,
+                    json!({ "title": post.cover_title, "post": post }),
+                    &[("image", &image), ("summary", &xhtml::sanitize(&post.cover_summary))],
+                );
+
+                add_page(
+                    &mut builder,
+                    &mut locators,
+                    EpubContent::new(title_file, title_content.as_bytes())
+                        .reftype(ReferenceType::Text),
+                )?;
+            }
+        }
+    }
+
+    // Articles are grouped in the TOC by section, sections in the order of
+    // their most important article
+    for (section, posts) in by_section(&posts_data) {
+        match section {
+            Some(section) => add_section(&mut builder, &mut locators, "sezione", section, &posts, tmp_dir, options)?,
+            None => {
+                for post in posts {
+                    add_article(&mut builder, &mut locators, post, tmp_dir, options, 1)?;
+                }
+            }
+        }
+    }
+
+    // Supplements get their own part of the TOC, one for each section,
+    // after the edition (they always have a section)
+    let mut sections: Vec<(&Section, Vec<&Post>)> = by_section(&supplements)
+        .into_iter()
+        .filter_map(|(section, posts)| Some((section?, posts)))
+        .collect();
+    sections.sort_by(|(a, _), (b, _)| collate::cmp(&a.name, &b.name));
+
+    for (section, posts) in sections {
+        add_section(&mut builder, &mut locators, "inserto", section, &posts, tmp_dir, options)?;
+    }
+
+    if !omitted.is_empty() {
+        let links: String = omitted
+            .iter()
+            .map(|post| format!(r#"<li><a href="{}">{}</a></li>"#, xhtml::escape(&post.link), xhtml::text(&post.title)))
+            .collect();
+        let omitted_content = pages::render(
+            &options.layout.omitted,
+            options.lang,
+            json!({ "title": options.strings.omitted_title }),
+            &[("links", &links)],
+        );
+
+        add_page(
+            &mut builder,
+            &mut locators,
+            EpubContent::new("omitted.xhtml", omitted_content.as_bytes())
+                .title(options.strings.omitted_title)
+                .reftype(ReferenceType::Text),
+        )?;
+    }
+
+    // Closing page with photo credits and copyright
+    let credits: String = posts_data
+        .iter()
+        .chain(&supplements)
+        .filter_map(|post| {
+            let credits: Vec<&str> = [&post.cover_image, &post.featured_image]
+                .into_iter()
+                .flatten()
+                .filter_map(|image| image.credits())
+                .collect();
+            if credits.is_empty() {
+                None
+            } else {
+                Some(format!("<li><b>{}</b>: {}</li>", xhtml::text(&post.title), xhtml::text(&credits.join(", "))))
+            }
+        })
+        .collect();
+    let credits = if credits.is_empty() {
+        String::new()
+    } else {
+        format!("<h2>{}</h2><ul>{}</ul>", xhtml::escape(options.strings.photo_credits), credits)
+    };
+    let credits_content = pages::render(
+        &options.layout.credits,
+        options.lang,
+        json!({
+            "title": options.strings.credits_title,
+            "copyright": template::render(options.strings.copyright, &context),
+            "edition": edition,
+            "date": edition.date_or_slug(),
+        }),
+        &[("credits", &credits)],
+    );
+    add_page(
+        &mut builder,
+        &mut locators,
+        EpubContent::new("credits.xhtml", credits_content.as_bytes())
+            .title(options.strings.credits_title)
+            .reftype(ReferenceType::Copyright),
+    )?;
+
+    // Locator index, inside the ePUB file and next to it
+    let index = locators.to_json()?;
+    builder.add_resource(locators::FILE_NAME, index.as_slice(), "application/json")?;
+
+    let mut epub = Vec::new();
+    builder.generate(&mut epub)?;
+    if let Some(day) = edition.day() {
+        epub = metadata::add_series(epub, day, options.version)?;
+    }
+    if let Some(path) = options.html.filter(|_| !options.preview) {
+        std::fs::write(path, html::render(&epub)?).with_path(path)?;
+    }
+    if let Some(path) = options.markdown.filter(|_| !options.preview) {
+        let title = if edition.title.is_empty() { &edition.slug } else { &edition.title };
+        markdown::write(&epub, title, path)?;
+    }
+    if let Some(dir) = options.text.filter(|_| !options.preview) {
+        text::write(&epub, dir, options.text_all)?;
+    }
+    if let Some(path) = options.dump.filter(|_| !options.preview) {
+        corpus::write(edition, posts_data.iter().chain(&supplements), &epub, path)?;
+    }
+    if let Some((format, path)) = options.index.filter(|_| !options.preview) {
+        index::write(posts_data.iter().chain(&supplements), &epub, format, path)?;
+    }
+    if let Some((format, path)) = options.audio.filter(|_| !options.preview) {
+        // the other files are written when reading fails
+        let posts = posts_data.iter().chain(&supplements);
+        match audio::write(edition, posts, &epub, format, options.tts_command, &tmp_dir.join("audio"), path) {
+            Ok(duration) => {
+                let feed = podcast::Feed {
+                    url: options.podcast_url,
+                    lang: options.lang,
+                    strings: options.strings,
+                };
+                podcast::add(&feed, edition, &epub, path, format, duration)?;
+            }
+            Err(e) => warn!("{} not written: {}", path.display(), e),
+        }
+    }
+    if options.preview {
+        print!("{}", preview::render(epub)?);
+        return Ok(());
+    }
+
+    let index_filename = options.output.with_extension(locators::FILE_NAME);
+    std::fs::write(&index_filename, &index).with_path(&index_filename)?;
+    std::fs::write(options.output, &epub).with_path(options.output)?;
+    if let Some(path) = options.kepub {
+        std::fs::write(path, kepub::convert(epub)?).with_path(path)?;
+    }
+
+    // Keep epub files if requested, the HTTP cache always
+    if !options.keep_files {
+        for entry in std::fs::read_dir(tmp_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_name() == http_cache::DIR_NAME {
+                continue;
+            } else if path.is_dir() {
+                remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Article ePUB and images of a post
+struct PostDownload<'a> {
+    post: &'a Post,
+    epub: Result<Bytes>,
+    // file name (or URL if it has none) and content
+    images: Vec<(String, Result<Bytes>)>,
+}
+
+async fn download_post<'a>(api: &impl ApiClient, post: &'a Post, skip: Skip) -> PostDownload<'a> {
+    let epub = api.download_epub(&post.slug).await;
+
+    // Download cover image for main article and cover image for each article
+    let mut images = Vec::new();
+    if epub.is_ok() && !skip.images && !skip.front_pages {
+        for image in [&post.cover_image, &post.featured_image].into_iter().flatten() {
+            match extract_file_from_url(&image.src) {
+                Ok(image_name) => images.push((image_name, api.download_image(&image.src).await)),
+                Err(e) => images.push((image.src.clone(), Err(e))),
+            }
+        }
+    }
+
+    PostDownload { post, epub, images }
+}
+
+// Expiry time of a JWT token, read from its "exp" claim
+fn token_expiry(token: &str) -> Option<DateTime<Utc>> {
+    let payload = token.split('.').nth(1)?;
+    let claims: serde_json::Value =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?).ok()?;
+    DateTime::from_timestamp(claims["exp"].as_i64()?, 0)
+}
+
+fn whoami(login_path: &Path, passphrase: Option<&str>) -> Result<()> {
+    if !login_path.is_file() {
+        return Err(Error::NotLoggedIn);
+    }
+    let login: Login = serde_json::from_slice(&secret::read(login_path, passphrase)?)?;
+
+    println!("User:            {} (id {})", login.user.email, login.user.user_id);
+    println!("Membership code: {}", login.user.membership_code);
+
+    // The access token is refreshed on every run, only the refresh token matters
+    let now = Utc::now();
+    match token_expiry(&login.token.access_token) {
+        Some(expiry) => println!("Access token:    expires {}", expiry.to_rfc2822()),
+        None => println!("Access token:    unknown expiry"),
+    }
+    match token_expiry(&login.token.refresh_token) {
+        Some(expiry) => {
+            println!("Refresh token:   expires {}", expiry.to_rfc2822());
+            if expiry < now {
+                warn!("Refresh token expired, login again with --email and --password");
+            }
+        }
+        None => println!("Refresh token:   unknown expiry"),
+    }
+
+    if login.user.membership_code.is_empty() {
+        warn!("No membership code, the subscription appears to be expired");
+    }
+
+    Ok(())
+}
+
+// Build the single ePUB file from the article files and the manifest kept by
+// a previous download. The files are left in place.
+fn build(
+    args: &Args,
+    from_dir: &Path,
+    preview: bool,
+    output_dir: &Path,
+    name_template: &str,
+    filters: &[Filter],
+    layout: &Layout,
+) -> Result<PathBuf> {
+    let Manifest {
+        edition,
+        posts,
+        omitted,
+        updated,
+    } = Manifest::load(from_dir)?;
+    let output = output_dir.join(template::file_name(name_template, &edition, "epub"));
+
+    // The same policy for existing files as a download, not applied by a preview
+    let mut summary = Summary::new(Progress::new(args.progress_json));
+    if !preview && !prepare_single(args.existing(), &output)? {
+        summary.skipped("epub", &output.display().to_string());
+        summary.print();
+        return Ok(output);
+    }
+    let outputs = if preview {
+        SingleOutputs::default()
+    } else {
+        SingleOutputs::prepare(args, output_dir, name_template, &edition, &output)?
+    };
+
+    let options = CombineOptions {
+        keep_files: true,
+        updated: &updated,
+        strings: locale::strings(&args.lang),
+        lang: &args.lang,
+        table_width: args.table_images,
+        output: &output,
+        cover: &args.cover,
+        skip: args.skip(),
+        filters,
+        no_resize: args.no_resize,
+        eink: args.eink,
+        svg_raster: args.svg_raster,
+        version: args.epub_version(),
+        kepub: written(&outputs.kepub),
+        html: written(&outputs.html),
+        markdown: written(&outputs.markdown),
+        text: written(&outputs.text),
+        text_all: written(&outputs.text_all),
+        dump: written(&outputs.dump),
+        index: outputs.index.as_ref().filter(|(_, _, write)| *write).map(|(format, path, _)| (*format, path.as_path())),
+        audio: outputs.audio.as_ref().filter(|(_, _, write)| *write).map(|(format, path, _)| (*format, path.as_path())),
+        tts_command: &args.tts_command,
+        podcast_url: args.podcast_url.as_deref(),
+        layout,
+        preview,
+    };
+    combine_articles(&edition, Data { data: posts }, &omitted, from_dir, &options)?;
+    if preview {
+        return Ok(output);
+    }
+    info!(event = "file", format = "epub", path = output.display().to_string().as_str(); "Wrote {}", output.display());
+    let mut produced = Vec::new();
+    outputs.finish(&mut produced);
+    kindle_copy(args, &output, &mut summary, &mut produced)?;
+    summary.print();
+
+    Ok(output)
+}
+
+// Article files and manifest of an edition made of the articles found, in
+// `dir` (replaced), for the build command. The articles are grouped by the
+// date of their edition.
+fn search_manifest(query: &str, hits: &[search::Hit], dir: &Path) -> Result<()> {
+    if dir.exists() {
+        remove_dir_all(dir).with_path(dir)?;
+    }
+    create_dir_all(dir).with_path(dir)?;
+
+    let mut posts: Vec<Post> = Vec::new();
+    for hit in hits {
+        if posts.iter().any(|post| post.slug == hit.slug) {
+            continue;
+        }
+        let path = dir.join(format!("{}.epub", hit.slug));
+        std::fs::copy(&hit.path, &path).with_path(&hit.path)?;
+        posts.push(Post {
+            slug: hit.slug.clone(),
+            title: xhtml::escape(&hit.title),
+            kicker: String::new(),
+            summary: String::new(),
+            excerpt: String::new(),
+            link: String::new(),
+            cover_position: 99,
+            cover_summary: String::new(),
+            cover_title: String::new(),
+            cover_image: None,
+            featured_image: None,
+            section: Some(Section {
+                name: if hit.date.is_empty() { hit.edition.clone() } else { hit.date.clone() },
+                slug: hit.edition.clone(),
+            }),
+            authors: Vec::new(),
+        });
+    }
+
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect();
+    let edition = Edition {
+        id: 0,
+        slug: format!("search-{}", words.join("-")),
+        pdf: String::new(),
+        title: format!("il manifesto: {}", xhtml::escape(query)),
+        date: Utc::now().date_naive().to_string(),
+        featured_image: None,
+    };
+    Manifest {
+        edition,
+        posts,
+        omitted: Vec::new(),
+        updated: Vec::new(),
+    }
+    .save(dir)
+}
+
+// Check if token is already available, or ask it to server
+async fn authenticate(
+    api: Api,
+    args: &Args,
+    login_path: &Path,
+    credentials_path: &Path,
+    passphrase: Option<&str>,
+) -> Result<Api> {
+    let api = if login_path.is_file() {
+        // Read token saved with first login
+        let login = serde_json::from_slice(&secret::read(login_path, passphrase)?)?;
+        let api = api.with_login(login);
+
+        // refresh token (if required, but we do it every time now)
+        api.refresh().await?;
+        api
     } else {
         // obtain credentials from CLI or stored locally in credentials.json
         let credentials;
 
         if args.email.is_empty() || args.password.is_empty() {
-            if Path::new("credentials.json").is_file() {
-                let file = File::open("credentials.json")?;
+            if credentials_path.is_file() {
+                let file = File::open(credentials_path)?;
                 let reader = BufReader::new(file);
 
                 // Read credentials from file
                 credentials = serde_json::from_reader(reader)?;
             } else {
-                panic!("Credentials required!");
+                return Err(Error::CredentialsRequired);
             }
         } else {
             credentials = Credentials {
-                email: args.email,
-                password: args.password,
+                email: args.email.clone(),
+                password: args.password.clone(),
             };
         }
 
-        let res = client
-            .post(&format!("{}/auth/login", BASE_URL))
-            .json(&credentials)
-            .send()
-            .await?;
-        login = res.json::<Login>().await?;
+        api.login(&credentials).await?;
+        api
+    };
+    Ok(api)
+}
+
+// Open the most recent file produced with the reader command or the platform
+// default application.
+fn open_latest(
+    history: &History,
+    format: Option<&str>,
+    reader: Option<&str>,
+) -> Result<()> {
+    let record = history
+        .latest(format)?
+        .ok_or("No downloaded edition found, run a download first")?;
+    open_file(&record.path, reader)
+}
+
+fn open_file(path: &Path, reader: Option<&str>) -> Result<()> {
+    let mut command = match reader {
+        Some(reader) => {
+            let mut words = reader.split_whitespace();
+            let mut command = std::process::Command::new(words.next().ok_or("empty reader command")?);
+            command.args(words);
+            command
+        }
+        None if cfg!(target_os = "macos") => std::process::Command::new("open"),
+        None if cfg!(windows) => {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        None => std::process::Command::new("xdg-open"),
+    };
 
-        let file = match File::create("login.json") {
-            Err(why) => panic!("couldn't create {}", why),
-            Ok(file) => file,
+    info!("Opening {}", path.display());
+    command.arg(path).spawn()?;
+    Ok(())
+}
+
+// Checks used as container health probe: state directory writable,
+// login data readable and API reachable.
+async fn healthcheck(
+    state_dir: &StateDir,
+    api_env: &ApiEnv,
+    passphrase: Option<&str>,
+    client: &reqwest::Client,
+) -> Result<()> {
+    let probe = state_dir.file(".healthcheck");
+    File::create(&probe)?;
+    std::fs::remove_file(&probe)?;
+
+    let login_path = state_dir.file(&api_env.file_name("login.json"));
+    if !login_path.is_file() {
+        return Err(Error::NotLoggedIn);
+    }
+    serde_json::from_slice::<Login>(&secret::read(&login_path, passphrase)?)?;
+
+    client
+        .get(format!("{}/wp/editions/latest", api_env.base_url()))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+// Resolves on SIGINT or SIGTERM (sent by docker stop, or by tini when running
+// as init process) with the signal number.
+async fn shutdown_signal() -> i32 {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(_) => std::future::pending().await,
         };
-        serde_json::to_writer(&file, &login)?;
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => 2,
+            _ = sigterm.recv() => 15,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        2
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    logging::init(args.verbose, args.quiet, args.log_format);
+
+    let result = tokio::select! {
+        result = run(args) => result,
+        signal = shutdown_signal() => {
+            eprintln!("Interrupted");
+            std::process::exit(128 + signal);
+        }
+    };
 
-        info!("{:?}", login);
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
+}
 
-    let auth_code = format!("Bearer {}", login.token.access_token);
+// What is needed to download an edition
+struct Run<'a> {
+    args: &'a Args,
+    api: &'a Api,
+    state_dir: &'a StateDir,
+    history: &'a History,
+    cache_dir: &'a Path,
+    output_dir: &'a Path,
+    name_template: &'a str,
+    filters: &'a [Filter],
+    layout: &'a Layout,
+    // where article files and images are saved
+    download_dir: Option<&'a Path>,
+    telegram: Option<Telegram<'a>>,
+    matrix: Option<Matrix<'a>>,
+    mailer: Option<Mailer>,
+    sftp: Option<Sftp<'a>>,
+    dropbox: Option<Dropbox<'a>>,
+    drive: Option<Drive<'a>>,
+    wallabag: Option<Wallabag<'a>>,
+    readwise: Option<Readwise<'a>>,
+    readeck: Option<Readeck<'a>>,
+    hooks: &'a Hooks,
+}
 
-    // Download PDF
-    if args.pdf {
-        info!(
-            "{:?}",
-            &format!("{}/wp/pdfs/slug/{}/download", BASE_URL, edition.pdf)
-        );
-        let res = client
-            .get(&format!(
-                "{}/wp/pdfs/slug/{}/download",
-                BASE_URL, edition.pdf
-            ))
-            .header(
-                AUTHORIZATION,
-                reqwest::header::HeaderValue::from_str(&auth_code).unwrap(),
-            )
-            .send()
-            .await?;
+impl Run<'_> {
+    // Whether a downloaded article or image can be written. They are outputs
+    // only without a single ePUB file, otherwise they are always replaced.
+    fn prepare(&self, filename: &str) -> Result<bool> {
+        match (self.args.single_epub, self.download_dir) {
+            (false, Some(dir)) => self.args.existing().prepare(&dir.join(filename)),
+            _ => Ok(true),
+        }
+    }
+}
 
-        let filename = format!("{}.pdf", edition.slug);
-        let content = res.bytes().await?;
-        write_file(filename, content, false)?;
+// Download an edition in the requested formats, returns the produced files.
+// Failures are recorded in the history.
+async fn download_edition(run: &Run<'_>, edition: &Edition) -> Result<Vec<PathBuf>> {
+    let result = fetch_edition(run, edition).await;
+    run.hooks.run(Some(edition), result.as_ref().map(Vec::as_slice));
+    if let Err(e) = &result {
+        run.history.record_failure(&edition.slug, "edition", &edition.slug, &e.to_string())?;
     }
+    result
+}
 
-    // Download EPUB and images
-    if args.epub {
-        // Download cover image for this edition
-        if edition.featured_image.is_some() {
-            let res = client
-                .get(&edition.featured_image.as_ref().unwrap().src)
-                .send()
-                .await?;
+async fn fetch_edition(run: &Run<'_>, edition: &Edition) -> Result<Vec<PathBuf>> {
+    // Output files, for delivery
+    let mut produced = Vec::new();
+    // slug and title of the articles downloaded in this run
+    let mut saved: Vec<(String, String)> = Vec::new();
+    // articles of the edition, for the read-it-later services
+    let mut read_later: Vec<Post> = Vec::new();
+
+    // Downloaded files are checked in background
+    let mut archive = Archive::open(&run.state_dir.file("archive"), &edition.slug)?;
+    let verifier = Verifier::new();
+    let progress = Progress::new(run.args.progress_json);
+    progress.emit(Event::RunStarted {
+        edition: &edition.slug,
+        title: &edition.title,
+    });
+    let mut summary = Summary::new(progress);
 
-            let content = res.bytes().await?;
-            let filename = format!("{}.jpg", edition.slug);
-            write_file(filename, content, args.single_epub)?;
+    // Download PDF
+    if run.args.pdf {
+        summary.expect("pdf", 1);
+        let filename = template::file_name(run.name_template, edition, "pdf");
+        let path = run.output_dir.join(&filename);
+
+        if run.args.existing().prepare(&path)? {
+            create_dir_all(run.output_dir).with_path(run.output_dir)?;
+            let content = run.api.download_pdf(&edition.pdf, &path).await?;
+
+            verifier.submit(&filename, content.clone());
+            summary.downloaded("pdf", &filename);
+            info!(event = "file", format = "pdf", path = path.display().to_string().as_str(); "Wrote {}", path.display());
+            if run.args.pdf_compress {
+                if let Err(e) = pdf::compress(&path, run.args.pdf_dpi, run.args.ghostscript.as_deref()) {
+                    summary.failed("pdf", &format!("compression of {}", filename), &e);
+                }
+            }
+        } else {
+            summary.skipped("pdf", &filename);
+        }
+        produced.push(path.clone());
+
+        // the pages of the articles are found by their titles
+        let posts = if run.args.pdf_outline || run.args.pdf_split == Some(pdf::Split::Sections) {
+            match run.api.posts(edition.id, &run.args.posts_query).await {
+                Ok(posts) => posts.data,
+                Err(e) => {
+                    summary.failed("pdf", &format!("articles of {}", edition.slug), &e);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        if run.args.pdf_outline {
+            match pdf::outline(&path, &posts) {
+                Ok(true) => info!("Added the outline to {}", path.display()),
+                Ok(false) => debug!("{} has an outline already", path.display()),
+                Err(e) => summary.failed("pdf", &format!("outline of {}", filename), &e),
+            }
+        }
+
+        // E-reader copy, the PDF is kept when it cannot be cropped
+        if let Some(crop) = &run.args.pdf_crop {
+            let ereader = pdf::ereader_path(&path);
+            if run.args.existing().prepare(&ereader)? {
+                match pdf::crop(&path, crop, run.args.ghostscript.as_deref()) {
+                    Ok(ereader) => {
+                        info!(event = "file", format = "pdf", path = ereader.display().to_string().as_str(); "Wrote {}", ereader.display());
+                        produced.push(ereader);
+                    }
+                    Err(e) => summary.failed("pdf", &ereader.display().to_string(), &e),
+                }
+            } else {
+                produced.push(ereader);
+            }
+        }
+
+        // from the copy without margins when there is one
+        if run.args.cbz {
+            let cbz = run.output_dir.join(template::file_name(run.name_template, edition, "cbz"));
+            if run.args.existing().prepare(&cbz)? {
+                let ereader = pdf::ereader_path(&path);
+                let source = if run.args.pdf_crop.is_some() && ereader.exists() { &ereader } else { &path };
+                let options = cbz::Options {
+                    dpi: run.args.cbz_dpi,
+                    gray: run.args.cbz_gray,
+                    ghostscript: run.args.ghostscript.as_deref(),
+                };
+                let work_dir = run.cache_dir.join(format!("{}-cbz", edition.slug));
+                match cbz::convert(source, &cbz, edition, &options, &work_dir) {
+                    Ok(()) => {
+                        info!(event = "file", format = "cbz", path = cbz.display().to_string().as_str(); "Wrote {}", cbz.display());
+                        produced.push(cbz);
+                    }
+                    Err(e) => summary.failed("pdf", &cbz.display().to_string(), &e),
+                }
+            } else {
+                produced.push(cbz);
+            }
         }
-        // Download cover image for this edition
-        if edition.featured_image.is_some() {
-            let res = client
-                .get(&edition.featured_image.as_ref().unwrap().src)
-                .send()
-                .await?;
 
-            let content = res.bytes().await?;
+        if let Some(split) = &run.args.pdf_split {
+            match pdf::split(&path, split, &posts, run.args.existing()) {
+                Ok(parts) => {
+                    for (part, written) in parts {
+                        if written {
+                            info!(event = "file", format = "pdf", path = part.display().to_string().as_str(); "Wrote {}", part.display());
+                        }
+                        produced.push(part);
+                    }
+                }
+                Err(e) => summary.failed("pdf", &format!("split of {}", filename), &e),
+            }
+        }
+    }
+
+    // The single ePUB file is not built again if it exists and must be kept
+    let single_path = run
+        .output_dir
+        .join(template::file_name(run.name_template, edition, "epub"));
+    let mut epub = run.args.epub;
+    if run.args.single_epub && !prepare_single(run.args.existing(), &single_path)? {
+        summary.skipped("epub", &single_path.display().to_string());
+        produced.push(single_path.clone());
+        epub = false;
+    }
+
+    // Download EPUB and images
+    if epub {
+        // Download cover image for this edition
+        let photo_cover = matches!(run.args.cover, CoverMode::Auto | CoverMode::Image) && !run.args.skip().cover;
+        if let Some(featured_image) = edition.featured_image.as_ref().filter(|_| photo_cover) {
             let filename = format!("{}.jpg", edition.slug);
-            write_file(filename, content, args.single_epub)?;
+            match run.api.download_image(&featured_image.src).await {
+                Ok(content) => {
+                    verifier.submit(&filename, content.clone());
+                    write_file(filename.clone(), content, run.download_dir)?;
+                    summary.downloaded("images", &filename);
+                }
+                Err(e) => summary.failed("images", &filename, &e),
+            }
         }
 
-        let res = client
-            .get(&format!("{}/wp/editions/{}/posts", BASE_URL, edition.id))
-            .header(
-                AUTHORIZATION,
-                reqwest::header::HeaderValue::from_str(&auth_code).unwrap(),
-            )
-            .send()
+        // parse posts
+        let posts = run
+            .api
+            .posts(edition.id, &run.args.posts_query)
             .await?;
+        let (selected, omitted) = select_posts(posts.data, run.args);
+        let posts = Data { data: selected };
+        if run.wallabag.is_some() || run.readwise.is_some() || run.readeck.is_some() {
+            let selection = read_later::Selection {
+                sections: &run.args.read_later_sections,
+                keywords: &run.args.read_later_keywords,
+            };
+            read_later = posts.data.iter().filter(|post| selection.matches(post)).cloned().collect();
+        }
+        if !omitted.is_empty() {
+            info!("{} articles omitted", omitted.len());
+        }
 
-        // parse posts
-        let posts = res.json::<Data>().await?;
+        let mut updated = Vec::new();
+        summary.expect("articles", posts.data.len());
 
+        // Articles are downloaded jobs at a time, then saved in the order they complete
+        let mut pending = Vec::new();
         for post in &posts.data {
             let filename = format!("{}.epub", post.slug);
+            let cached = run.cache_dir.join(&filename);
+            let mut exists = if run.args.single_epub {
+                !run.args.check_updates && cached.exists()
+            } else {
+                run.args.existing() == Existing::Skip && run.output_dir.join(&filename).exists()
+            };
 
-            if std::env::temp_dir()
-                .join("clima-rs")
-                .join(&filename)
-                .exists()
-            {
-                continue;
+            // Cached articles that cannot be opened are downloaded again
+            if exists && run.args.single_epub {
+                if let Err(e) = EpubDoc::new(&cached) {
+                    warn!("{} is corrupted ({}), downloading it again", cached.display(), e);
+                    std::fs::remove_file(&cached).with_path(&cached)?;
+                    summary.evicted("articles", &post.slug, &e.to_string());
+                    exists = false;
+                }
+            }
+            if exists {
+                summary.skipped("articles", &post.slug);
+            } else {
+                pending.push(post);
+            }
+        }
+
+        let mut downloads = stream::iter(pending)
+            .map(|post| download_post(run.api, post, run.args.skip()))
+            .buffer_unordered(run.args.jobs.max(1));
+
+        while let Some(download) = downloads.next().await {
+            let slug = &download.post.slug;
+            let filename = format!("{}.epub", slug);
+
+            // A failed article is reported at the end, the others are still downloaded
+            let content = match download.epub {
+                Ok(content) => content,
+                Err(e) => {
+                    summary.failed("articles", slug, &e);
+                    continue;
+                }
             };
 
-            let res = client
-                .get(&format!(
-                    "{}/wp/posts/{}/download/epub",
-                    BASE_URL, post.slug
-                ))
-                .header(
-                    AUTHORIZATION,
-                    reqwest::header::HeaderValue::from_str(&auth_code).unwrap(),
-                )
-                .send()
-                .await?;
-
-            let content = res.bytes().await?;
-            write_file(filename, content, args.single_epub)?;
-
-            // Download cover image for main article
-            if post.cover_image.is_some() {
-                let image_url = &post.cover_image.as_ref().unwrap().src;
-                let image_name = extract_file_from_url(&image_url)?;
-                let res = client
-                    .get(image_url.as_str())
-                    .header(
-                        AUTHORIZATION,
-                        reqwest::header::HeaderValue::from_str(&auth_code).unwrap(),
-                    )
-                    .send()
-                    .await?;
-
-                let content = res.bytes().await?;
-                write_file(image_name, content, args.single_epub)?;
-            }
-
-            // Download cover image for each article
-            if post.featured_image.is_some() {
-                let image_url = &post.featured_image.as_ref().unwrap().src;
-                let image_name = extract_file_from_url(&image_url)?;
-
-                let res = client
-                    .get(image_url.as_str())
-                    .header(
-                        AUTHORIZATION,
-                        reqwest::header::HeaderValue::from_str(&auth_code).unwrap(),
-                    )
-                    .send()
-                    .await?;
-
-                let content = res.bytes().await?;
-                write_file(image_name, content, args.single_epub)?;
+            summary.downloaded("articles", slug);
+            saved.push((slug.clone(), download.post.title.clone()));
+            info!(event = "article", slug = slug.as_str(), size = content.len(); "Downloaded {}", slug);
+            if archive.store(slug, &content)? {
+                info!("{} has been updated", slug);
+                updated.push(slug.clone());
+            }
+            verifier.submit(&filename, content.clone());
+            if run.prepare(&filename)? {
+                write_file(filename, content, run.download_dir)?;
+            }
+
+            for (image_name, content) in download.images {
+                match content {
+                    Ok(content) => {
+                        verifier.submit(&image_name, content.clone());
+                        if run.prepare(&image_name)? {
+                            write_file(image_name.clone(), content, run.download_dir)?;
+                        }
+                        summary.downloaded("images", &image_name);
+                        info!(event = "image", name = image_name.as_str(); "Downloaded {}", image_name);
+                    }
+                    Err(e) => summary.failed("images", &image_name, &e),
+                }
             }
         }
+        drop(downloads);
+
+        // Nothing to do without articles
+        let (downloaded, skipped, _) = summary.count("articles");
+        if !posts.data.is_empty() && downloaded + skipped == 0 {
+            summary.finish(&produced);
+            summary.print();
+            if run.args.output == Output::Json {
+                report::print(edition, &produced, &summary)?;
+            }
+            return Err("no article could be downloaded".into());
+        }
+
+        if !run.args.mark_updates {
+            updated.clear();
+        }
 
         // Creates a single output file
-        if args.single_epub {
-            combine_articles(edition, posts, args.keep_files)?;
+        if run.args.single_epub {
+            let outputs = SingleOutputs::prepare(run.args, run.output_dir, run.name_template, edition, &single_path)?;
+            let options = CombineOptions {
+                keep_files: run.args.keep_files,
+                updated: &updated,
+                strings: locale::strings(&run.args.lang),
+                lang: &run.args.lang,
+                table_width: run.args.table_images,
+                output: &single_path,
+                cover: &run.args.cover,
+                skip: run.args.skip(),
+                filters: run.filters,
+                no_resize: run.args.no_resize,
+                eink: run.args.eink,
+                svg_raster: run.args.svg_raster,
+                version: run.args.epub_version(),
+                kepub: written(&outputs.kepub),
+                html: written(&outputs.html),
+                markdown: written(&outputs.markdown),
+                text: written(&outputs.text),
+                text_all: written(&outputs.text_all),
+                dump: written(&outputs.dump),
+                index: outputs.index.as_ref().filter(|(_, _, write)| *write).map(|(format, path, _)| (*format, path.as_path())),
+                audio: outputs.audio.as_ref().filter(|(_, _, write)| *write).map(|(format, path, _)| (*format, path.as_path())),
+                tts_command: &run.args.tts_command,
+                podcast_url: run.args.podcast_url.as_deref(),
+                layout: run.layout,
+                preview: false,
+            };
+            if run.args.keep_files {
+                Manifest {
+                    edition: edition.clone(),
+                    posts: posts.data.clone(),
+                    omitted: omitted.clone(),
+                    updated: updated.clone(),
+                }
+                .save(run.cache_dir)?;
+            }
+            combine_articles(edition, posts, &omitted, run.cache_dir, &options)?;
+            info!(event = "file", format = "epub", path = single_path.display().to_string().as_str(); "Wrote {}", single_path.display());
+            outputs.finish(&mut produced);
+            kindle_copy(run.args, &single_path, &mut summary, &mut produced)?;
+            produced.push(single_path);
+        }
+    }
+
+    let verified = verifier.finish();
+    for file in &verified {
+        if let Some(error) = &file.error {
+            warn!("{}: {}", file.name, error);
+        }
+    }
+
+    let articles: Vec<history::Article> = saved
+        .iter()
+        .map(|(slug, title)| {
+            let filename = format!("{}.epub", slug);
+            history::Article {
+                slug,
+                title,
+                sha256: verified
+                    .iter()
+                    .find(|file| file.name == filename)
+                    .map(|file| file.sha256.as_str()),
+            }
+        })
+        .collect();
+    run.history.record(edition, &produced, &articles)?;
+    for item in &summary.items {
+        if let Status::Failed(error) = &item.status {
+            run.history.record_failure(&edition.slug, item.kind, &item.name, error)?;
+        }
+    }
+    archive.save_checksums(&verified)?;
+    archive.save()?;
+    // a download is not failed because of the search index
+    let indexed = Search::open(&run.state_dir.file(search::FILE_NAME)).and_then(|search| search.index(edition, &archive, run.history));
+    if let Err(e) = indexed {
+        warn!("Cannot index the articles of {}: {}", edition.slug, e);
+    }
+
+    if let Some(mount) = &run.args.device {
+        device::deliver(mount, &produced)?;
+    }
+    if let Some(dir) = &run.args.sync_dir {
+        sync::deliver(dir, &produced, run.args.sync_hash)?;
+    }
+    if let Some(library) = &run.args.calibre_library {
+        let calibre = Calibre {
+            library,
+            calibredb: run.args.calibredb.as_deref(),
+        };
+        if let Err(e) = calibre.add(edition, &produced) {
+            summary.failed("calibre", library, &e);
         }
     }
+    if let Some(telegram) = &run.telegram {
+        for (path, e) in telegram.deliver(&produced, &xhtml::plain(&edition.title)).await {
+            summary.failed("telegram", &path.display().to_string(), &e);
+        }
+    }
+    if let Some(matrix) = &run.matrix {
+        for (name, e) in matrix.deliver(&xhtml::plain(&edition.title), &produced).await {
+            summary.failed("matrix", &name, &e);
+        }
+    }
+    if let Some(mailer) = &run.mailer {
+        for (path, e) in mailer.deliver(&xhtml::plain(&edition.title), &produced).await {
+            summary.failed("mail", &path.display().to_string(), &e);
+        }
+    }
+    if let Some(wallabag) = &run.wallabag {
+        for (slug, e) in wallabag.deliver(edition, &read_later, &archive).await {
+            summary.failed("wallabag", &slug, &e);
+        }
+    }
+    if let Some(readwise) = &run.readwise {
+        for (slug, e) in read_later::export(readwise, edition, &read_later, &archive, run.history).await {
+            summary.failed("readwise", &slug, &e);
+        }
+    }
+    if let Some(readeck) = &run.readeck {
+        for (slug, e) in read_later::export(readeck, edition, &read_later, &archive, run.history).await {
+            summary.failed("readeck", &slug, &e);
+        }
+    }
+    if let Some(sftp) = &run.sftp {
+        if let Err(e) = sftp.deliver(&produced) {
+            summary.failed("sftp", sftp.destination, &e);
+        }
+    }
+    if let Some(dropbox) = &run.dropbox {
+        for (name, e) in dropbox.deliver(&produced).await {
+            summary.failed("dropbox", &name, &e);
+        }
+    }
+    if let Some(drive) = &run.drive {
+        for (name, e) in drive.deliver(&produced).await {
+            summary.failed("drive", &name, &e);
+        }
+    }
+
+    summary.finish(&produced);
+    summary.print();
+    if run.args.output == Output::Json {
+        report::print(edition, &produced, &summary)?;
+    }
+
+    Ok(produced)
+}
+
+// SMTP settings of the command line, for the given recipients
+fn mail_settings<'a>(args: &'a Args, server: &'a str, to: &'a [String], kindle: Option<&'a str>) -> mail::Settings<'a> {
+    mail::Settings {
+        server,
+        security: args.smtp_security,
+        user: args.smtp_user.as_deref(),
+        password: args.smtp_password.as_deref(),
+        from: args.smtp_from.as_deref(),
+        to,
+        kindle,
+        max_size: args.smtp_max_size,
+    }
+}
+
+async fn run(mut args: Args) -> Result<()> {
+    if let Some(base_url) = args.base_url.take() {
+        args.api_env = base_url;
+    }
+
+    // Formats given on the command line take precedence over the configuration
+    let config = Config::load(args.config.as_deref())?;
+    if !args.pdf && !args.epub {
+        args.pdf = config.formats.contains(&Format::Pdf) || config.formats.contains(&Format::Cbz);
+        args.single_epub |= config.formats.iter().any(|format| format.needs_single_epub());
+        args.epub = args.single_epub || config.formats.contains(&Format::Articles);
+        args.kepub |= config.formats.contains(&Format::Kepub);
+        if config.formats.contains(&Format::Kindle) {
+            args.kindle.get_or_insert(kindle::Format::Azw3);
+        }
+        args.html |= config.formats.contains(&Format::Html);
+        args.markdown |= config.formats.contains(&Format::Md);
+        args.text |= config.formats.contains(&Format::Text);
+        // the other variants are chosen on the command line
+        if config.formats.contains(&Format::Dump) {
+            args.dump.get_or_insert(corpus::Format::Json);
+        }
+        if config.formats.contains(&Format::Index) {
+            args.index.get_or_insert(index::Format::Csv);
+        }
+        if config.formats.contains(&Format::Audio) {
+            args.audio.get_or_insert(audio::Format::M4b);
+        }
+        args.cbz |= config.formats.contains(&Format::Cbz);
+        // with both, the article files are kept in the cache directory
+        args.keep_files |= args.single_epub && config.formats.contains(&Format::Articles);
+    }
+
+    // Find where login.json and credentials.json are stored
+    let state_dir = StateDir::new(args.state_dir.clone())?;
+    state_dir.migrate()?;
+    let login_path = state_dir.file(&args.api_env.file_name("login.json"));
+    let credentials_path = state_dir.file(&args.api_env.file_name("credentials.json"));
+    let base_url = args.api_env.base_url();
+    let passphrase = args.passphrase.as_deref();
+
+    // Downloaded articles and images
+    let cache_dir = args
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("clima-rs"));
+    let mut filters = config.filters(args.profile.as_deref())?;
+    if args.eink {
+        filters.extend_from_slice(filters::EINK);
+    }
+    debug!("Image filters: {}", filters.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", "));
+    let stylesheet = themes::stylesheet(PAGINATION_CSS, args.theme, args.stylesheet.as_deref())?;
+    let layout = Layout::load(stylesheet, args.template_dir.as_deref())?;
+    // Produced files, the current directory by default
+    let output_dir = args
+        .output_dir
+        .clone()
+        .or(config.output_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    create_dir_all(&output_dir).with_path(&output_dir)?;
+    let name_template = args
+        .name_template
+        .clone()
+        .or(config.name_template)
+        .unwrap_or_else(|| template::DEFAULT_NAME.to_string());
+    let download_dir = if args.single_epub {
+        Some(cache_dir.as_path())
+    } else {
+        Some(output_dir.as_path())
+    };
+
+    if args.insecure && args.api_env == ApiEnv::Prod {
+        warn!("TLS certificates are not checked (--insecure): password and tokens can be intercepted, use --ca-cert if a proxy replaces the certificates");
+    } else if args.insecure {
+        warn!("Accepting invalid certificates from {}", base_url);
+    }
+
+    // Create the client, with the cookies of the previous runs
+    let cookies = CookieJar::load(state_dir.file(&args.api_env.file_name("cookies.json")), passphrase);
+    let connect_timeout = args.connect_timeout.or(config.connect_timeout).unwrap_or(30);
+    let read_timeout = args.read_timeout.or(config.read_timeout).unwrap_or(60);
+    let user_agent = args
+        .user_agent
+        .clone()
+        .or(config.user_agent)
+        .unwrap_or_else(|| format!("clima/{}", env!("CARGO_PKG_VERSION")));
+    let mut client = reqwest::Client::builder()
+        .cookie_provider(cookies.provider())
+        .connect_timeout(Duration::from_secs(connect_timeout))
+        .read_timeout(Duration::from_secs(read_timeout))
+        .user_agent(user_agent)
+        .danger_accept_invalid_certs(args.insecure);
+    if let Some(proxy) = &args.proxy {
+        client = client.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path).with_path(path)?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)?;
+        if certificates.is_empty() {
+            return Err(format!("no PEM certificate in {}", path.display()).into());
+        }
+        for certificate in certificates {
+            client = client.add_root_certificate(certificate);
+        }
+    }
+    let client = client.build()?;
+
+    if args.healthcheck {
+        if let Err(e) = healthcheck(&state_dir, &args.api_env, passphrase, &client).await {
+            eprintln!("Unhealthy: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let history = History::open(&state_dir.file("state.db"), &state_dir.file("history.jsonl"))?;
+
+    match &args.command {
+        Some(Command::Whoami) => return whoami(&login_path, passphrase),
+        Some(Command::Open { pdf, epub, reader }) => {
+            let format = match (pdf, epub) {
+                (true, _) => Some("pdf"),
+                (_, true) => Some("epub"),
+                _ => None,
+            };
+            return open_latest(&history, format, reader.as_deref());
+        }
+        Some(Command::Usage { days }) => return usage::print(&history, *days),
+        Some(Command::Weekly { days, to }) => {
+            let server = args.smtp_server.as_deref().ok_or("--smtp-server is needed to mail the summary")?;
+            let to = if to.is_empty() { &args.smtp_to } else { to };
+            if to.is_empty() {
+                return Err("no recipient of the summary given with --to or --smtp-to".into());
+            }
+            let mailer = Mailer::new(&mail_settings(&args, server, to, None))?;
+            let digest = weekly::digest(&history, *days)?;
+            return mailer.send_text(&digest.subject, digest.body).await;
+        }
+        Some(Command::Search {
+            query,
+            limit,
+            epub,
+            open,
+            reader,
+            reindex,
+        }) => {
+            let search = Search::open(&state_dir.file(search::FILE_NAME))?;
+            if *reindex {
+                search.rebuild(&state_dir.file("archive"), &history)?;
+            }
+            let Some(query) = query else {
+                return Ok(());
+            };
+            let hits = search.search(query, *limit)?;
+            for hit in &hits {
+                println!("{}  {}  {}", hit.date, hit.edition, hit.title);
+                println!("    {}", hit.snippet.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+            if hits.is_empty() {
+                println!("No article found");
+            } else if *epub || *open {
+                let work_dir = cache_dir.join("search");
+                search_manifest(query, &hits, &work_dir)?;
+                let path = build(&args, &work_dir, false, &output_dir, &name_template, &filters, &layout)?;
+                if *open {
+                    open_file(&path, reader.as_deref())?;
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Serve { listen }) => return serve::run(&history, listen).await,
+        Some(Command::Authorize { service }) => {
+            let (provider, file) = match service {
+                oauth::Service::Dropbox => {
+                    let app_key = args.dropbox_app.as_deref().ok_or("--dropbox-app is needed to authorize Dropbox")?;
+                    (dropbox::provider(app_key), dropbox::TOKEN_FILE)
+                }
+                oauth::Service::Drive => match (&args.drive_client_id, &args.drive_client_secret) {
+                    (Some(id), Some(secret)) => (drive::provider(id, secret), drive::TOKEN_FILE),
+                    _ => return Err("--drive-client-id and --drive-client-secret are needed to authorize Drive".into()),
+                },
+            };
+            return provider.authorize(&client, &state_dir.file(file), passphrase).await;
+        }
+        Some(Command::Build { from_dir, preview }) => {
+            return build(&args, from_dir, *preview, &output_dir, &name_template, &filters, &layout).map(|_| ())
+        }
+        None => {}
+    }
+
+    // Network errors are retried
+    let retry = Retry {
+        attempts: args.retries,
+        delay: Duration::from_millis(args.retry_delay),
+    };
+
+    let dump = args.dump_api.as_deref().map(Dump::new).transpose()?;
+
+    let api = Api::new(client.clone(), base_url, retry, login_path.clone(), args.passphrase.clone(), dump)
+        .with_rate_limit(args.rate_limit);
+    let api = if args.no_http_cache {
+        api
+    } else {
+        api.with_cache(HttpCache::new(args.cache_dir.as_deref()))
+    };
+
+    let notifier = Notifier {
+        client: &client,
+        ntfy: args.ntfy.as_deref(),
+        webhook: args.webhook.as_deref(),
+    };
+    let hooks = Hooks {
+        on_success: args.on_success.clone().or(config.hooks.on_success),
+        on_failure: args.on_failure.clone().or(config.hooks.on_failure),
+        on_file: args.on_file.clone().or(config.hooks.on_file),
+    };
+
+    // Get last edition (currently the only supported operation), failures
+    // are notified too
+    let edition = match api.latest_edition().await {
+        Ok(edition) => edition,
+        Err(e) => {
+            notifier.send(None, Err(&e)).await;
+            hooks.run(None, Err(&e));
+            return Err(e);
+        }
+    };
+
+    info!(event = "edition", slug = edition.slug.as_str(), id = edition.id; "Edition {}", edition.slug);
+
+    let api = match authenticate(api, &args, &login_path, &credentials_path, passphrase).await {
+        Ok(api) => api,
+        Err(e) => {
+            notifier.send(Some(&edition), Err(&e)).await;
+            hooks.run(Some(&edition), Err(&e));
+            return Err(e);
+        }
+    };
+
+    let run = Run {
+        args: &args,
+        api: &api,
+        state_dir: &state_dir,
+        history: &history,
+        cache_dir: &cache_dir,
+        output_dir: &output_dir,
+        name_template: &name_template,
+        filters: &filters,
+        layout: &layout,
+        download_dir,
+        telegram: args.telegram_token.as_deref().zip(args.telegram_chat.as_deref()).map(|(token, chat)| Telegram {
+            client: &client,
+            token,
+            chat,
+        }),
+        matrix: match (&args.matrix_homeserver, &args.matrix_token, &args.matrix_room) {
+            (Some(homeserver), Some(token), Some(room)) => Some(Matrix {
+                client: &client,
+                homeserver,
+                token,
+                room,
+            }),
+            _ => None,
+        },
+        mailer: match &args.smtp_server {
+            Some(server) => Some(Mailer::new(&mail_settings(
+                &args,
+                server,
+                &args.smtp_to,
+                args.send_to_kindle.as_deref(),
+            ))?),
+            None => None,
+        },
+        sftp: args.sftp.as_deref().map(|destination| Sftp {
+            destination,
+            key: args.sftp_key.as_deref(),
+            port: args.sftp_port,
+        }),
+        dropbox: match (&args.dropbox, &args.dropbox_app) {
+            (Some(folder), Some(app_key)) => Some(Dropbox {
+                client: &client,
+                app_key,
+                folder,
+                token_path: state_dir.file(dropbox::TOKEN_FILE),
+                passphrase,
+            }),
+            _ => None,
+        },
+        drive: match (&args.drive, &args.drive_client_id, &args.drive_client_secret) {
+            (Some(folder), Some(client_id), Some(client_secret)) => Some(Drive {
+                client: &client,
+                client_id,
+                client_secret,
+                folder,
+                token_path: state_dir.file(drive::TOKEN_FILE),
+                passphrase,
+            }),
+            _ => None,
+        },
+        wallabag: match (
+            &args.wallabag,
+            &args.wallabag_client_id,
+            &args.wallabag_client_secret,
+            &args.wallabag_user,
+            &args.wallabag_password,
+        ) {
+            (Some(url), Some(client_id), Some(client_secret), Some(user), Some(password)) => Some(Wallabag {
+                client: &client,
+                url,
+                client_id,
+                client_secret,
+                user,
+                password,
+            }),
+            _ => None,
+        },
+        readwise: args.readwise_token.as_deref().map(|token| Readwise { client: &client, token }),
+        readeck: args.readeck.as_deref().zip(args.readeck_token.as_deref()).map(|(url, token)| Readeck {
+            client: &client,
+            url,
+            token,
+        }),
+        hooks: &hooks,
+    };
+
+    // Editions missed by the previous runs first
+    let backfill_days = args.backfill.or(config.backfill_days).unwrap_or(0);
+    for missed in backfill::missed(&api, &history, &edition, backfill_days).await? {
+        info!(event = "backfill", slug = missed.slug.as_str(); "Downloading missed edition {}", missed.slug);
+        if let Err(e) = download_edition(&run, &missed).await {
+            warn!("Cannot download missed edition {}: {}", missed.slug, e);
+        }
+    }
+
+    let result = download_edition(&run, &edition).await;
+    cookies.save()?;
+    notifier.send(Some(&edition), result.as_ref().map(Vec::as_slice)).await;
+    result?;
 
     info!("All done!");
     Ok(())
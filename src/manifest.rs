@@ -0,0 +1,37 @@
+// Description of the edition saved next to the article files kept with
+// --keep-files, so that the build command can make the single ePUB file again
+// without network access, e.g. to try changes of the layout.
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+use crate::{Edition, Post};
+
+pub const FILE_NAME: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub edition: Edition,
+    pub posts: Vec<Post>,
+    // articles left out by --max-articles
+    pub omitted: Vec<Post>,
+    // articles changed since the previous download
+    pub updated: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(FILE_NAME);
+        if !path.is_file() {
+            return Err(format!("{} not found, download with --keep-files first", path.display()).into());
+        }
+        Ok(serde_json::from_slice(&std::fs::read(&path).with_path(&path)?)?)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir).with_path(dir)?;
+        let path = dir.join(FILE_NAME);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?).with_path(&path)
+    }
+}
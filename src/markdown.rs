@@ -0,0 +1,120 @@
+// The whole edition in one Markdown file, made from the single ePUB file, for
+// note-taking apps and static site generators: the articles in reading order,
+// separated by a rule, with their headings (one level below the title of the
+// edition), emphasis, lists, quotes and links to other sites. Images are left
+// out, captions are kept as paragraphs.
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+use crate::text;
+use crate::xhtml;
+
+// Elements ending a block of the text
+const BLOCKS: &[&str] = &[
+    "p", "div", "h1", "h2", "h3", "h4", "h5", "h6", "li", "ul", "ol", "dt", "dd", "blockquote", "pre", "table", "tr",
+    "figure", "figcaption", "section", "article", "header", "footer",
+];
+
+// Between the articles
+const SEPARATOR: &str = "\n* * *\n\n";
+
+pub fn write(epub: &[u8], title: &str, path: &Path) -> Result<()> {
+    let mut articles = Vec::new();
+    for page in text::pages(epub)? {
+        articles.push(markdown(xhtml::body(&page.xhtml).unwrap_or_default()));
+    }
+
+    let content = format!("# {}\n\n{}", escape(title), articles.join(SEPARATOR));
+    std::fs::write(path, content).with_path(path)?;
+    Ok(())
+}
+
+// Markdown of the body of a page, a paragraph for each block element
+fn markdown(body: &str) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut block = String::new();
+    // heading or list marker of the block
+    let mut marker = String::new();
+    let mut quotes = 0;
+    // href of the open links, none for those left as text
+    let mut links: Vec<Option<String>> = Vec::new();
+    let mut rest = body;
+
+    let mut flush = |block: &mut String, marker: &mut String, quotes: usize| {
+        let lines: Vec<String> = block
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect();
+        block.clear();
+        let marker = std::mem::take(marker);
+        if !lines.is_empty() {
+            let quote = "> ".repeat(quotes);
+            blocks.push(format!("{}{}{}", quote, marker, lines.join(&format!("\\\n{}", quote))));
+        }
+    };
+
+    while let Some((start, end)) = xhtml::next_tag(rest) {
+        block.push_str(&escape(&xhtml::unescape(&rest[..start]).replace('\n', " ")));
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with("</");
+        let name = xhtml::tag_name(tag).to_ascii_lowercase();
+        match name.as_str() {
+            "script" | "style" => {
+                let close = format!("</{}", name);
+                rest = match rest.to_ascii_lowercase().find(&close) {
+                    Some(i) => rest[i..].find('>').map_or("", |end| &rest[i + end + 1..]),
+                    None => "",
+                };
+            }
+            "br" => block.push('\n'),
+            "em" | "i" => block.push('*'),
+            "strong" | "b" => block.push_str("**"),
+            "a" if closing => {
+                if let Some(Some(href)) = links.pop() {
+                    block.push_str(&format!("]({})", href));
+                }
+            }
+            "a" => {
+                let href = xhtml::attribute(tag, "href")
+                    .map(xhtml::unescape)
+                    .filter(|href| href.starts_with("http:") || href.starts_with("https:"));
+                if href.is_some() {
+                    block.push('[');
+                }
+                links.push(href);
+            }
+            name if BLOCKS.contains(&name) => {
+                flush(&mut block, &mut marker, quotes);
+                if name == "blockquote" {
+                    quotes = if closing { quotes.saturating_sub(1) } else { quotes + 1 };
+                } else if !closing {
+                    marker = match name.as_bytes() {
+                        [b'h', level @ b'1'..=b'6'] => format!("{} ", "#".repeat((level - b'0' + 1).min(6) as usize)),
+                        b"li" => "- ".to_string(),
+                        _ => String::new(),
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+    block.push_str(&escape(&xhtml::unescape(rest)));
+    flush(&mut block, &mut marker, quotes);
+
+    blocks.join("\n\n") + "\n"
+}
+
+// The characters with a meaning in Markdown, escaped with a backslash
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,134 @@
+// Delivery to a Matrix room: a message telling that the edition is ready,
+// followed by the PDF, ePUB, Kindle and comic book (CBZ) files uploaded to
+// the homeserver. The access token is that of the account posting, e.g. from
+// the settings of Element (Help & About), and the account must have joined
+// the room.
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use crate::error::{Error, FileContext, Result};
+use crate::output;
+
+#[derive(Deserialize)]
+struct Upload {
+    content_uri: String,
+}
+
+pub struct Matrix<'a> {
+    pub client: &'a reqwest::Client,
+    // e.g. https://matrix.org
+    pub homeserver: &'a str,
+    pub token: &'a str,
+    // room id (!abc:matrix.org) or alias (#room:matrix.org)
+    pub room: &'a str,
+}
+
+impl Matrix<'_> {
+    // Items that could not be sent (the message or the files), with the
+    // reason. When the message cannot be posted the files are not uploaded.
+    pub async fn deliver(&self, title: &str, files: &[PathBuf]) -> Vec<(String, Error)> {
+        let posted = match self.room_id().await {
+            Ok(room) => {
+                let message = json!({ "msgtype": "m.text", "body": format!("{} is ready", title) });
+                self.send(&room, &message, 0).await.map(|()| room)
+            }
+            Err(e) => Err(e),
+        };
+        let room = match posted {
+            Ok(room) => room,
+            Err(e) => return vec![(self.room.to_string(), e)],
+        };
+
+        let mut failed = Vec::new();
+        for (number, file) in files.iter().filter(|file| output::is_ebook(file)).enumerate() {
+            match self.upload(&room, file, number + 1).await {
+                Ok(()) => info!("Sent {} to {}", file.display(), self.room),
+                Err(e) => failed.push((file.display().to_string(), e)),
+            }
+        }
+        failed
+    }
+
+    async fn upload(&self, room: &str, file: &Path, number: usize) -> Result<()> {
+        let name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = file.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let mime = output::content_type(&extension);
+        let content = std::fs::read(file).with_path(file)?;
+        let size = content.len();
+
+        let mut url = self.url(&["_matrix", "media", "v3", "upload"])?;
+        url.query_pairs_mut().append_pair("filename", &name);
+        let upload: Upload = self
+            .client
+            .post(url)
+            .bearer_auth(self.token)
+            .header(reqwest::header::CONTENT_TYPE, mime)
+            .body(content)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let message = json!({
+            "msgtype": "m.file",
+            "body": name,
+            "filename": name,
+            "url": upload.content_uri,
+            "info": { "mimetype": mime, "size": size },
+        });
+        self.send(room, &message, number).await
+    }
+
+    // The transaction id makes a retried request post the message only once
+    async fn send(&self, room: &str, message: &Value, number: usize) -> Result<()> {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let transaction = format!("clima-{}-{}", time, number);
+        let url = self.url(&["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", &transaction])?;
+        self.client
+            .put(url)
+            .bearer_auth(self.token)
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // Aliases are resolved to the room id
+    async fn room_id(&self) -> Result<String> {
+        if !self.room.starts_with('#') {
+            return Ok(self.room.to_string());
+        }
+        let url = self.url(&["_matrix", "client", "v3", "directory", "room", self.room])?;
+        let room: Value = self
+            .client
+            .get(url)
+            .bearer_auth(self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        room["room_id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| format!("room {} not found", self.room).into())
+    }
+
+    // Segments are escaped (room ids have ! and :)
+    fn url(&self, segments: &[&str]) -> Result<Url> {
+        let mut url = Url::parse(self.homeserver)?;
+        url.path_segments_mut()
+            .map_err(|_| format!("invalid homeserver {}", self.homeserver))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+}
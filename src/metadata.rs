@@ -0,0 +1,73 @@
+// Metadata of the single ePUB file that epub-builder cannot write: the
+// editions as a series, for calibre (calibre:series) and for ePUB 3 readers
+// (belongs-to-collection, not in ePUB 2 files). The package document is
+// changed after the file is generated, the other entries are copied as they
+// are.
+use chrono::NaiveDate;
+use epub_builder::EpubVersion;
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use std::io::{Cursor, Read, Write};
+
+use crate::error::{Error, Result};
+
+const SERIES: &str = "il manifesto";
+const PACKAGE: &str = "OEBPS/content.opf";
+
+// Same identifier for every file of an edition, so that importing it again
+// replaces the book instead of adding a copy
+pub fn uuid(edition_id: i32) -> Uuid {
+    let namespace = Uuid::new_v5(&Uuid::NAMESPACE_DNS, b"ilmanifesto.it");
+    Uuid::new_v5(&namespace, format!("edition/{}", edition_id).as_bytes())
+}
+
+// Editions are numbered by date, e.g. 20240315
+pub fn add_series(epub: Vec<u8>, day: NaiveDate, version: EpubVersion) -> Result<Vec<u8>> {
+    let index = day.format("%Y%m%d").to_string();
+    let mut meta = format!(
+        r#"    <meta name="calibre:series" content="{series}"/>
+    <meta name="calibre:series_index" content="{index}"/>
+"#,
+        series = SERIES,
+        index = index
+    );
+    if version == EpubVersion::V30 {
+        meta.push_str(&format!(
+            r##"    <meta property="belongs-to-collection" id="series">{series}</meta>
+    <meta refines="#series" property="collection-type">series</meta>
+    <meta refines="#series" property="group-position">{index}</meta>
+"##,
+            series = SERIES,
+            index = index
+        ));
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(epub)).map_err(zip_error)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(zip_error)?;
+        if file.name() != PACKAGE {
+            // the mimetype entry stays first and uncompressed
+            writer.raw_copy_file(file).map_err(zip_error)?;
+            continue;
+        }
+
+        let mut package = String::new();
+        file.read_to_string(&mut package)?;
+        let end = package.find("</metadata>").ok_or("ePUB package without metadata")?;
+        // at the start of the line, before the indentation
+        let end = package[..end].rfind('\n').map_or(end, |i| i + 1);
+        package.insert_str(end, &meta);
+
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file(PACKAGE, options).map_err(zip_error)?;
+        writer.write_all(package.as_bytes())?;
+    }
+    Ok(writer.finish().map_err(zip_error)?.into_inner())
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::EpubWrite(e.into())
+}
@@ -0,0 +1,98 @@
+// Notification at the end of a download run, so that a phone can tell when
+// the paper is ready (or could not be downloaded): a message posted to an
+// ntfy topic (--ntfy) and/or a JSON document posted to a webhook (--webhook).
+// A notification that cannot be sent is only logged.
+use serde::Serialize;
+use url::Url;
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::error::{Error, Result};
+use crate::xhtml::plain;
+use crate::Edition;
+
+// Produced files, or why the run failed
+pub type Outcome<'a> = std::result::Result<&'a [PathBuf], &'a Error>;
+
+#[derive(Serialize)]
+struct Message<'a> {
+    // ok, failed
+    status: &'a str,
+    edition: Option<EditionInfo<'a>>,
+    files: Vec<&'a Path>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EditionInfo<'a> {
+    slug: &'a str,
+    title: String,
+    date: Option<String>,
+}
+
+pub struct Notifier<'a> {
+    pub client: &'a reqwest::Client,
+    pub ntfy: Option<&'a str>,
+    pub webhook: Option<&'a str>,
+}
+
+impl Notifier<'_> {
+    // The edition is not known when the run fails before getting it
+    pub async fn send(&self, edition: Option<&Edition>, result: Outcome<'_>) {
+        if let Some(topic) = self.ntfy {
+            if let Err(e) = self.ntfy(topic, edition, result).await {
+                warn!("Cannot send the notification to {}: {}", topic, e);
+            }
+        }
+        if let Some(url) = self.webhook {
+            if let Err(e) = self.webhook(url, edition, result).await {
+                warn!("Cannot send the notification to {}: {}", url, e);
+            }
+        }
+    }
+
+    // Title and options in the query, so that they can be UTF-8
+    async fn ntfy(&self, topic: &str, edition: Option<&Edition>, result: Outcome<'_>) -> Result<()> {
+        let title = edition.map_or("il manifesto".to_string(), |edition| plain(&edition.title));
+        let mut url = Url::parse(topic)?;
+        let body = match result {
+            Ok(files) => {
+                let names: Vec<String> = files
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().to_string())
+                    .collect();
+                url.query_pairs_mut().append_pair("title", &title).append_pair("tags", "newspaper");
+                format!("Ready: {}", names.join(", "))
+            }
+            Err(e) => {
+                url.query_pairs_mut()
+                    .append_pair("title", &format!("{}: download failed", title))
+                    .append_pair("tags", "warning")
+                    .append_pair("priority", "high");
+                e.to_string()
+            }
+        };
+        debug!("Notifying {}", url);
+        self.client.post(url).body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn webhook(&self, url: &str, edition: Option<&Edition>, result: Outcome<'_>) -> Result<()> {
+        let message = Message {
+            status: if result.is_ok() { "ok" } else { "failed" },
+            edition: edition.map(|edition| EditionInfo {
+                slug: &edition.slug,
+                title: plain(&edition.title),
+                date: edition.day().map(|day| day.to_string()),
+            }),
+            files: result.map(|files| files.iter().map(PathBuf::as_path).collect()).unwrap_or_default(),
+            error: result.err().map(|e| e.to_string()),
+        };
+        debug!("Notifying {}", url);
+        self.client.post(url).json(&message).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
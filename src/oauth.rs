@@ -0,0 +1,205 @@
+// OAuth 2 authorization of the uploads to cloud storage (Dropbox, Google
+// Drive), for an app registered by the user. `authorize <service>` is run once
+// in a terminal: the page it prints is opened in a browser, the code given
+// back by the service is exchanged for a refresh token, kept in the state
+// directory (encrypted with --passphrase, like login.json). Each run exchanges
+// it for a short-lived access token. PKCE protects the code, so that Dropbox
+// apps do not need their secret.
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use random_string::generate;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::secret;
+
+// Characters allowed in the PKCE verifier
+const VERIFIER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Service {
+    Dropbox,
+    Drive,
+}
+
+// How the code gets back to the program
+pub enum Redirect {
+    // shown by the service and pasted in the terminal
+    Paste,
+    // sent by the browser to a local port
+    Loopback,
+}
+
+pub struct Provider<'a> {
+    pub name: &'static str,
+    pub authorize_url: &'static str,
+    pub token_url: &'static str,
+    pub redirect: Redirect,
+    pub scope: Option<&'static str>,
+    // asking for a refresh token
+    pub offline: &'static [(&'static str, &'static str)],
+    pub client_id: &'a str,
+    pub client_secret: Option<&'a str>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    refresh_token: String,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl Provider<'_> {
+    // Interactive, saves the refresh token
+    pub async fn authorize(&self, client: &reqwest::Client, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        let verifier = generate(64, VERIFIER);
+        let challenge = BASE64.encode(Sha256::digest(verifier.as_bytes()));
+        let state = generate(16, VERIFIER);
+
+        let listener = match self.redirect {
+            Redirect::Loopback => Some(TcpListener::bind("127.0.0.1:0").await?),
+            Redirect::Paste => None,
+        };
+        let redirect_uri = match &listener {
+            Some(listener) => Some(format!("http://127.0.0.1:{}", listener.local_addr()?.port())),
+            None => None,
+        };
+
+        let mut url = Url::parse(self.authorize_url)?;
+        url.query_pairs_mut()
+            .append_pair("client_id", self.client_id)
+            .append_pair("response_type", "code")
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256")
+            .append_pair("state", &state)
+            .extend_pairs(self.offline.iter().copied());
+        if let Some(scope) = self.scope {
+            url.query_pairs_mut().append_pair("scope", scope);
+        }
+        if let Some(redirect_uri) = &redirect_uri {
+            url.query_pairs_mut().append_pair("redirect_uri", redirect_uri);
+        }
+        println!("Open this page to allow the uploads to {}:\n\n{}\n", self.name, url);
+
+        let code = match &listener {
+            Some(listener) => receive_code(listener, &state).await?,
+            None => {
+                print!("Code: ");
+                std::io::stdout().flush()?;
+                let mut code = String::new();
+                std::io::stdin().read_line(&mut code)?;
+                code.trim().to_string()
+            }
+        };
+        if code.is_empty() {
+            return Err(format!("no code from {}", self.name).into());
+        }
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("code_verifier", verifier.as_str()),
+        ];
+        if let Some(redirect_uri) = &redirect_uri {
+            form.push(("redirect_uri", redirect_uri));
+        }
+        let token = self.token(client, form).await?;
+        let refresh_token = token
+            .refresh_token
+            .ok_or_else(|| format!("{} did not give a refresh token", self.name))?;
+        secret::write(path, &serde_json::to_vec(&Stored { refresh_token })?, passphrase)?;
+        println!("Uploads to {} allowed, the token is saved in {}", self.name, path.display());
+        Ok(())
+    }
+
+    // Access token for this run
+    pub async fn access_token(&self, client: &reqwest::Client, path: &Path, passphrase: Option<&str>) -> Result<String> {
+        if !path.is_file() {
+            return Err(format!("not authorized, run `il_manifesto authorize {}` first", self.name.to_lowercase()).into());
+        }
+        let stored: Stored = serde_json::from_slice(&secret::read(path, passphrase)?)?;
+        let form = vec![("grant_type", "refresh_token"), ("refresh_token", stored.refresh_token.as_str())];
+        let token = self.token(client, form).await?;
+        // some services replace the refresh token
+        if let Some(refresh_token) = token.refresh_token {
+            secret::write(path, &serde_json::to_vec(&Stored { refresh_token })?, passphrase)?;
+        }
+        Ok(token.access_token)
+    }
+
+    async fn token(&self, client: &reqwest::Client, mut form: Vec<(&str, &str)>) -> Result<Token> {
+        form.push(("client_id", self.client_id));
+        if let Some(secret) = self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = client.post(self.token_url).form(&form).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = match response.json::<TokenError>().await {
+                Ok(e) => format!("{} ({})", e.error, e.error_description.unwrap_or_default()),
+                Err(_) => status.to_string(),
+            };
+            return Err(format!("{} refused the token: {}", self.name, error).into());
+        }
+        Ok(response.json().await?)
+    }
+}
+
+// The browser is sent back to the local port with the code in the query
+async fn receive_code(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+            match stream.read(&mut buffer).await? {
+                0 => break,
+                n => request.extend_from_slice(&buffer[..n]),
+            }
+        }
+        let request = String::from_utf8_lossy(&request);
+        let target = request.split_whitespace().nth(1).unwrap_or("/");
+        let url = Url::parse("http://127.0.0.1")?.join(target)?;
+        let query = |name: &str| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.to_string());
+
+        // e.g. the favicon
+        if query("code").is_none() && query("error").is_none() {
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await?;
+            continue;
+        }
+        let result = match query("error") {
+            Some(error) => Err(format!("authorization refused: {}", error)),
+            None if query("state").as_deref() != Some(state) => Err("authorization with a wrong state".to_string()),
+            None => Ok(query("code").unwrap_or_default()),
+        };
+        let page = match &result {
+            Ok(_) => "Authorized, this page can be closed.".to_string(),
+            Err(e) => e.clone(),
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            page.len(),
+            page
+        );
+        stream.write_all(response.as_bytes()).await?;
+        return Ok(result?);
+    }
+}
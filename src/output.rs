@@ -0,0 +1,79 @@
+// What to do with output files that already exist, and what they are
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{FileContext, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Existing {
+    #[default]
+    Overwrite,
+    Skip,
+    // the old file is renamed to <name>.bak
+    Backup,
+}
+
+impl Existing {
+    // Whether `path` can be written. With Backup an existing file is moved
+    // out of the way, replacing the previous backup.
+    pub fn prepare(self, path: &Path) -> Result<bool> {
+        if !path.exists() {
+            return Ok(true);
+        }
+
+        match self {
+            Existing::Overwrite => Ok(true),
+            Existing::Skip => {
+                info!("{} exists, skipped", path.display());
+                Ok(false)
+            }
+            Existing::Backup => {
+                let backup = backup_path(path);
+                // a directory is not replaced by rename
+                match fs::symlink_metadata(&backup) {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&backup).with_path(&backup)?,
+                    Ok(_) => fs::remove_file(&backup).with_path(&backup)?,
+                    Err(_) => {}
+                }
+                fs::rename(path, &backup).with_path(path)?;
+                info!("Moved {} to {}", path.display(), backup.display());
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+// Files readers open, among the produced ones (kepub.epub files are ePUB
+// files too)
+const EBOOKS: &[&str] = &["pdf", "epub", "azw3", "mobi", "cbz"];
+
+pub fn is_ebook(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| EBOOKS.contains(&extension.to_string_lossy().to_lowercase().as_str()))
+}
+
+// MIME type of a file with the extension
+pub fn content_type(format: &str) -> &'static str {
+    match format {
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "html" => "text/html; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "json" => "application/json",
+        "csv" => "text/csv; charset=utf-8",
+        "mp3" => "audio/mpeg",
+        "m4b" => "audio/mp4",
+        "azw3" | "mobi" => "application/x-mobipocket-ebook",
+        "cbz" => "application/vnd.comicbook+zip",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,69 @@
+// Generated pages of the single ePUB file, written with the placeholders of
+// the text templates ({title}, {post.kicker}). The templates in
+// assets/templates are replaced by the files of the same name in the
+// directory given with --template-dir, so the layout can be changed without
+// recompiling.
+use serde_json::Value;
+
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+use crate::template;
+use crate::xhtml;
+
+pub struct Layout {
+    // content of stylesheet.css
+    pub stylesheet: String,
+    // photo and title of an article on the front page of the newspaper
+    pub cover: String,
+    // photo and kicker before an article
+    pub front: String,
+    // title page of a section
+    pub section: String,
+    // articles left out with --max-articles
+    pub omitted: String,
+    // photo credits and copyright notice
+    pub credits: String,
+}
+
+impl Layout {
+    pub fn load(stylesheet: String, dir: Option<&Path>) -> Result<Layout> {
+        let template = |name: &str, default: &str| -> Result<String> {
+            match dir.map(|dir| dir.join(name)).filter(|path| path.exists()) {
+                Some(path) => std::fs::read_to_string(&path).with_path(&path),
+                None => Ok(default.to_string()),
+            }
+        };
+
+        Ok(Layout {
+            stylesheet,
+            cover: template("cover.xhtml", include_str!("../assets/templates/cover.xhtml"))?,
+            front: template("front.xhtml", include_str!("../assets/templates/front.xhtml"))?,
+            section: template("section.xhtml", include_str!("../assets/templates/section.xhtml"))?,
+            omitted: template("omitted.xhtml", include_str!("../assets/templates/omitted.xhtml"))?,
+            credits: template("credits.xhtml", include_str!("../assets/templates/credits.xhtml"))?,
+        })
+    }
+}
+
+// Page from a template. The strings of `data` (a JSON object) are written as
+// plain text, `fragments` are markup written as they are.
+pub fn render(template: &str, lang: &str, mut data: Value, fragments: &[(&str, &str)]) -> String {
+    escape(&mut data);
+    if let Value::Object(map) = &mut data {
+        map.insert("lang".to_string(), Value::from(lang));
+        for (name, fragment) in fragments {
+            map.insert(name.to_string(), Value::from(*fragment));
+        }
+    }
+    template::render(template, &data)
+}
+
+fn escape(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = xhtml::text(s),
+        Value::Array(values) => values.iter_mut().for_each(escape),
+        Value::Object(map) => map.values_mut().for_each(escape),
+        _ => {}
+    }
+}
@@ -0,0 +1,423 @@
+// Post-processing of the PDF of the edition. The pages of the broadsheet have
+// wide white margins, unreadable on a 6" screen: the copy for e-readers
+// (<name>.ereader.pdf) has the visible area of each page reduced to its
+// content, found by Ghostscript (bbox device) on each page or given in points.
+// The PDF can be written again by Ghostscript, with the images at a lower
+// resolution and linearized (pages shown while the file is still loading), to
+// make it smaller for devices with little storage, or split in smaller files
+// by section or page range. The PDF has no outline, one is added with the
+// sections and the articles. The API does not give the pages of the articles:
+// they are found by their titles in the text of the pages.
+use lopdf::{Bookmark, Dictionary, Document, Object, ObjectId};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use log::{debug, info};
+
+use crate::error::{FileContext, Result};
+use crate::output::Existing;
+use crate::xhtml::plain;
+use crate::Post;
+
+// space kept around the content found on the pages, in points
+const PADDING: f32 = 6.0;
+// smaller pages are left as they are, the margins would be wrong
+const MIN_SIZE: f32 = 72.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Crop {
+    // margins found on each page, blank pages are left as they are
+    Auto,
+    // top, right, bottom, left in points (1/72 inch)
+    Margins([f32; 4]),
+}
+
+// auto, or the margins as in CSS: all, vertical,horizontal or
+// top,right,bottom,left
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Crop::Auto);
+        }
+        let margins = s
+            .split(',')
+            .map(|margin| margin.trim().parse::<f32>().ok().filter(|margin| *margin >= 0.0))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("expected auto or the margins in points, e.g. 36 or 36,24 or 36,24,36,24")?;
+        Ok(Crop::Margins(match margins[..] {
+            [all] => [all; 4],
+            [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+            [top, right, bottom, left] => [top, right, bottom, left],
+            _ => return Err("expected 1, 2 or 4 margins".to_string()),
+        }))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Split {
+    // the pages of each section
+    Sections,
+    // name, first and last page (numbered from 1)
+    Pages(Vec<(String, u32, u32)>),
+}
+
+// sections, or page ranges such as 1-3,7,cultura=12-14 (p1-3 and p7 are the
+// names of the first two)
+impl FromStr for Split {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "sections" {
+            return Ok(Split::Sections);
+        }
+        let error = || "expected sections or page ranges, e.g. 1-3,7,cultura=12-14".to_string();
+        let mut ranges = Vec::new();
+        for range in s.split(',').map(str::trim) {
+            let (name, pages) = match range.split_once('=') {
+                Some((name, pages)) if !name.trim().is_empty() => (name.trim().to_string(), pages.trim()),
+                Some(_) => return Err(error()),
+                None => (format!("p{}", range), range),
+            };
+            let (first, last) = pages.split_once('-').unwrap_or((pages, pages));
+            let (Ok(first), Ok(last)) = (first.trim().parse::<u32>(), last.trim().parse::<u32>()) else {
+                return Err(error());
+            };
+            if first == 0 || last < first {
+                return Err(format!("invalid page range {}", range));
+            }
+            ranges.push((name, first, last));
+        }
+        Ok(Split::Pages(ranges))
+    }
+}
+
+// <name>.ereader.pdf, next to the PDF
+pub fn ereader_path(pdf: &Path) -> PathBuf {
+    pdf.with_extension("ereader.pdf")
+}
+
+// Path of the cropped copy
+pub fn crop(pdf: &Path, crop: &Crop, ghostscript: Option<&str>) -> Result<PathBuf> {
+    let output = ereader_path(pdf);
+    let mut doc = Document::load(pdf)?;
+    let found = match crop {
+        Crop::Auto => bounding_boxes(pdf, ghostscript)?,
+        Crop::Margins(_) => Vec::new(),
+    };
+
+    for (number, id) in doc.get_pages() {
+        let Some([x0, y0, x1, y1]) = media_box(&doc, id) else {
+            continue;
+        };
+        let area = match crop {
+            Crop::Margins([top, right, bottom, left]) => [x0 + left, y0 + bottom, x1 - right, y1 - top],
+            // Ghostscript puts the corner of the page in 0 0
+            Crop::Auto => match found.get(number as usize - 1) {
+                Some(Some([left, bottom, right, top])) => [
+                    (x0 + left - PADDING).max(x0),
+                    (y0 + bottom - PADDING).max(y0),
+                    (x0 + right + PADDING).min(x1),
+                    (y0 + top + PADDING).min(y1),
+                ],
+                _ => continue,
+            },
+        };
+        if area[2] - area[0] < MIN_SIZE || area[3] - area[1] < MIN_SIZE {
+            debug!("Page {} not cropped, the margins are larger than the page", number);
+            continue;
+        }
+        // some readers ignore the crop box
+        let rectangle = Object::Array(area.into_iter().map(Object::Real).collect());
+        let page = doc.get_object_mut(id)?.as_dict_mut()?;
+        page.set("MediaBox", rectangle.clone());
+        page.set("CropBox", rectangle);
+    }
+    doc.save(&output)?;
+    Ok(output)
+}
+
+// Paths of the parts, <name>-<part>.pdf next to the PDF, and whether they
+// were written. The articles are needed for the sections.
+pub fn split(pdf: &Path, split: &Split, posts: &[Post], existing: Existing) -> Result<Vec<(PathBuf, bool)>> {
+    let doc = Document::load(pdf)?;
+    let count = doc.get_pages().len() as u32;
+    let parts = match split {
+        Split::Pages(ranges) => ranges
+            .iter()
+            .map(|(name, first, last)| (name.clone(), (*first..=(*last).min(count)).collect::<Vec<_>>()))
+            .filter(|(_, pages)| !pages.is_empty())
+            .collect(),
+        Split::Sections => sections(&doc, posts),
+    };
+    if parts.is_empty() {
+        return Err("no pages to split the PDF into".into());
+    }
+
+    let stem = pdf.file_stem().unwrap_or_default().to_string_lossy();
+    let mut paths = Vec::new();
+    for (name, pages) in parts {
+        let path = pdf.with_file_name(format!("{}-{}.pdf", stem, name));
+        let written = existing.prepare(&path)?;
+        if written {
+            debug!("Pages {:?} in {}", pages, path.display());
+            extract(&doc, &pages)?.save(&path)?;
+        }
+        paths.push((path, written));
+    }
+    Ok(paths)
+}
+
+// Outline with a bookmark for each section, on its first page, and the
+// articles inside, added to the PDF. Whether it was added: a PDF with an
+// outline is left as it is.
+pub fn outline(pdf: &Path, posts: &[Post]) -> Result<bool> {
+    let mut doc = Document::load(pdf)?;
+    if doc.catalog()?.has(b"Outlines") {
+        return Ok(false);
+    }
+    let pages = doc.get_pages();
+    // in the order of the pages, and of importance on a page
+    let mut articles: Vec<(u32, &Post)> = article_pages(&doc, posts)
+        .into_iter()
+        .zip(posts)
+        .filter_map(|(page, post)| Some((page?, post)))
+        .collect();
+    articles.sort_by_key(|(page, _)| *page);
+    if articles.is_empty() {
+        return Err("the titles of the articles were not found in the pages".into());
+    }
+
+    // slug and bookmark of the section of the articles before
+    let mut section: Option<(&str, u32)> = None;
+    for (page, post) in articles {
+        let page = pages[&page];
+        let parent = match &post.section {
+            Some(current) => match section {
+                Some((slug, bookmark)) if slug == current.slug => Some(bookmark),
+                _ => {
+                    // bold
+                    let bookmark = doc.add_bookmark(Bookmark::new(plain(&current.name), [0.0; 3], 2, page), None);
+                    section = Some((&current.slug, bookmark));
+                    Some(bookmark)
+                }
+            },
+            None => {
+                section = None;
+                None
+            }
+        };
+        doc.add_bookmark(Bookmark::new(plain(&post.title), [0.0; 3], 0, page), parent);
+    }
+    let outline = doc.build_outline().ok_or("empty outline")?;
+    let catalog = doc.catalog_mut()?;
+    catalog.set("Outlines", outline);
+    catalog.set("PageMode", Object::Name(b"UseOutlines".to_vec()));
+
+    let part = pdf.with_extension("pdf.outline");
+    doc.save(&part)?;
+    std::fs::rename(&part, pdf).with_path(pdf)?;
+    Ok(true)
+}
+
+// Page of each article (numbered from 1), found by the beginning of its title
+// in the text of the pages. The front page announces the main articles, the
+// page where they are printed is preferred.
+fn article_pages(doc: &Document, posts: &[Post]) -> Vec<Option<u32>> {
+    let texts: Vec<(u32, String)> = doc
+        .get_pages()
+        .into_keys()
+        .map(|page| (page, format!(" {} ", words(&doc.extract_text(&[page]).unwrap_or_default()))))
+        .collect();
+    posts
+        .iter()
+        .map(|post| {
+            let title = words(&plain(&post.title)).split(' ').take(8).collect::<Vec<_>>().join(" ");
+            if title.is_empty() {
+                return None;
+            }
+            let title = format!(" {} ", title);
+            let found: Vec<u32> = texts.iter().filter(|(_, text)| text.contains(&title)).map(|(page, _)| *page).collect();
+            found.iter().find(|page| **page > 1).or(found.first()).copied()
+        })
+        .collect()
+}
+
+// Section (slug) and pages of the parts, in the order of the edition. A page
+// goes with the section of most of its articles, pages without articles
+// found with the section before.
+fn sections(doc: &Document, posts: &[Post]) -> Vec<(String, Vec<u32>)> {
+    let located = article_pages(doc, posts);
+    let count = doc.get_pages().len() as u32;
+    let mut pages: Vec<Option<&str>> = (1..=count)
+        .map(|page| {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for (post, _) in posts.iter().zip(&located).filter(|(_, found)| **found == Some(page)) {
+                if let Some(section) = &post.section {
+                    *counts.entry(section.slug.as_str()).or_default() += 1;
+                }
+            }
+            counts.into_iter().max_by_key(|(_, count)| *count).map(|(slug, _)| slug)
+        })
+        .collect();
+    // the first pages before a section go with it
+    let Some(first) = pages.iter().flatten().next().copied() else {
+        return Vec::new();
+    };
+    let mut section = first;
+    for page in pages.iter_mut() {
+        section = *page.get_or_insert(section);
+    }
+
+    let mut parts: Vec<(String, Vec<u32>)> = Vec::new();
+    for (page, section) in (1..).zip(pages.into_iter().flatten()) {
+        match parts.iter_mut().find(|(name, _)| name == section) {
+            Some((_, pages)) => pages.push(page),
+            None => parts.push((section.to_string(), vec![page])),
+        }
+    }
+    parts
+}
+
+// Lowercase words separated by a space, for comparing titles
+fn words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Attributes a page can take from its parents
+const INHERITED: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// Copy of the document with these pages only (numbered from 1). The page tree
+// is made flat, the outline is left out: it would point to the pages removed.
+fn extract(doc: &Document, pages: &[u32]) -> Result<Document> {
+    let mut part = doc.clone();
+    let all = part.get_pages();
+    let root = part.catalog()?.get(b"Pages")?.as_reference()?;
+    let mut kids = Vec::new();
+    for id in pages.iter().filter_map(|page| all.get(page)).copied() {
+        let attributes: Vec<(&[u8], Object)> = INHERITED
+            .iter()
+            .filter_map(|key| Some((*key, inherited(&part, id, key)?.clone())))
+            .collect();
+        let page = part.get_object_mut(id)?.as_dict_mut()?;
+        for (key, value) in attributes {
+            page.set(key, value);
+        }
+        page.set("Parent", root);
+        kids.push(Object::Reference(id));
+    }
+    let tree = part.get_object_mut(root)?.as_dict_mut()?;
+    tree.set("Count", kids.len() as i64);
+    tree.set("Kids", kids);
+    let catalog = part.catalog_mut()?;
+    catalog.remove(b"Outlines");
+    catalog.remove(b"PageMode");
+    part.prune_objects();
+    Ok(part)
+}
+
+// Write the PDF again with the images at this resolution (dots per inch),
+// kept as it is when it would not be smaller
+pub fn compress(pdf: &Path, dpi: u32, ghostscript_command: Option<&str>) -> Result<()> {
+    // <name>.pdf.part is a download to resume
+    let part = pdf.with_extension("pdf.gs");
+    let mut command = ghostscript(ghostscript_command)?;
+    command
+        .args(["-sDEVICE=pdfwrite", "-dCompatibilityLevel=1.5", "-dPDFSETTINGS=/ebook", "-dFastWebView=true"])
+        .args(["-dDetectDuplicateImages=true", "-dCompressFonts=true"])
+        .arg(format!("-dColorImageResolution={}", dpi))
+        .arg(format!("-dGrayImageResolution={}", dpi))
+        // line art, scanned at a higher resolution
+        .arg(format!("-dMonoImageResolution={}", dpi * 2))
+        .arg(format!("-sOutputFile={}", part.display()))
+        .arg(pdf);
+    if let Err(e) = run(command) {
+        let _ = std::fs::remove_file(&part);
+        return Err(e);
+    }
+
+    let before = std::fs::metadata(pdf).with_path(pdf)?.len();
+    let after = std::fs::metadata(&part).with_path(&part)?.len();
+    if after >= before {
+        info!("{} not compressed, it would not be smaller", pdf.display());
+        return std::fs::remove_file(&part).with_path(&part);
+    }
+    std::fs::rename(&part, pdf).with_path(pdf)?;
+    let megabytes = |size: u64| size as f64 / (1 << 20) as f64;
+    info!("Compressed {} from {:.1} to {:.1} MB", pdf.display(), megabytes(before), megabytes(after));
+    Ok(())
+}
+
+// Ghostscript, given or from the PATH
+pub fn ghostscript(command: Option<&str>) -> Result<Command> {
+    let default = if cfg!(windows) { "gswin64c" } else { "gs" };
+    let mut words = command.unwrap_or(default).split_whitespace();
+    let program = words.next().ok_or("empty Ghostscript command")?;
+    let mut command = Command::new(program);
+    command.args(words).args(["-q", "-dSAFER", "-dBATCH", "-dNOPAUSE"]);
+    Ok(command)
+}
+
+// The messages of Ghostscript (stderr)
+pub fn run(mut command: Command) -> Result<String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let result = command
+        .output()
+        .map_err(|e| format!("cannot run {}: {}, install Ghostscript or use --ghostscript", program, e))?;
+    let output = String::from_utf8_lossy(&result.stderr).to_string();
+    if !result.status.success() {
+        let error = output.trim().lines().last().unwrap_or_default();
+        return Err(format!("{} failed ({}): {}", program, result.status, error).into());
+    }
+    Ok(output)
+}
+
+// Box of the content of each page (left, bottom, right, top), None for blank
+// pages
+fn bounding_boxes(pdf: &Path, command: Option<&str>) -> Result<Vec<Option<[f32; 4]>>> {
+    let mut ghostscript = ghostscript(command)?;
+    ghostscript.arg("-sDEVICE=bbox").arg(pdf);
+    // the boxes are written to stderr
+    let output = run(ghostscript)?;
+    // %%HiResBoundingBox: 31.895999 40.103999 805.265975 1150.281965
+    Ok(output
+        .lines()
+        .filter_map(|line| line.strip_prefix("%%HiResBoundingBox:"))
+        .map(|line| {
+            let numbers: Vec<f32> = line.split_whitespace().filter_map(|number| number.parse().ok()).collect();
+            match numbers[..] {
+                [left, bottom, right, top] if right > left && top > bottom => Some([left, bottom, right, top]),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+// The media box can be given by a parent in the page tree
+fn media_box(doc: &Document, id: ObjectId) -> Option<[f32; 4]> {
+    let (_, rectangle) = doc.dereference(inherited(doc, id, b"MediaBox")?).ok()?;
+    let numbers: Vec<f32> = rectangle.as_array().ok()?.iter().filter_map(|number| number.as_float().ok()).collect();
+    match numbers[..] {
+        [x0, y0, x1, y1] => Some([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]),
+        _ => None,
+    }
+}
+
+// Attribute of a page or of its nearest parent
+fn inherited<'a>(doc: &'a Document, id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node: &Dictionary = doc.get_dictionary(id).ok()?;
+    loop {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        node = doc.get_dictionary(node.get(b"Parent").ok()?.as_reference().ok()?).ok()?;
+    }
+}
@@ -0,0 +1,156 @@
+// Podcast feed of the audio editions (podcast.xml next to them), so that a
+// podcast app can subscribe to the daily paper read aloud. Each audiobook
+// written with --audio is added as an episode, with its length, duration and
+// the cover of the edition as artwork (<name>.jpg). The episodes are kept in
+// podcast.json; those whose file was removed leave the feed.
+// Podcast apps need absolute URLs: --podcast-url is the address the output
+// directory is served at, the file names are used as they are otherwise.
+use chrono::{DateTime, NaiveTime, Utc};
+use epub::doc::EpubDoc;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::audio;
+use crate::error::{FileContext, Result};
+use crate::locale::Strings;
+use crate::xhtml::{escape, plain};
+use crate::Edition;
+
+pub const FEED: &str = "podcast.xml";
+const EPISODES: &str = "podcast.json";
+
+#[derive(Serialize, Deserialize)]
+struct Episode {
+    // the edition slug
+    guid: String,
+    title: String,
+    // RFC 3339
+    date: String,
+    // file names in the directory of the feed
+    file: String,
+    image: Option<String>,
+    length: u64,
+    seconds: u64,
+    mime: String,
+}
+
+// Feed options
+pub struct Feed<'a> {
+    pub url: Option<&'a str>,
+    pub lang: &'a str,
+    pub strings: &'static Strings,
+}
+
+// Adds the audiobook `path` of the edition to the feed in its directory, with
+// the cover of the single ePUB file
+pub fn add(
+    feed: &Feed,
+    edition: &Edition,
+    epub: &[u8],
+    path: &Path,
+    format: audio::Format,
+    duration: Duration,
+) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let image = match EpubDoc::from_reader(Cursor::new(epub))?.get_cover().ok() {
+        Some(cover) => {
+            let image = path.with_extension("jpg");
+            std::fs::write(&image, &cover).with_path(&image)?;
+            Some(name(&image))
+        }
+        None => None,
+    };
+    let date = edition
+        .day()
+        .map_or_else(Utc::now, |day| day.and_time(NaiveTime::MIN).and_utc());
+    let episode = Episode {
+        guid: edition.slug.clone(),
+        title: plain(&edition.title),
+        date: date.to_rfc3339(),
+        file: name(path),
+        image,
+        length: std::fs::metadata(path).with_path(path)?.len(),
+        seconds: duration.as_secs(),
+        mime: match format {
+            audio::Format::M4b => "audio/x-m4b",
+            audio::Format::Mp3 => "audio/mpeg",
+        }
+        .to_string(),
+    };
+
+    let episodes_path = dir.join(EPISODES);
+    let mut episodes: Vec<Episode> = match std::fs::read(&episodes_path) {
+        Ok(content) => serde_json::from_slice(&content)?,
+        Err(_) => Vec::new(),
+    };
+    episodes.retain(|other| other.guid != episode.guid && dir.join(&other.file).exists());
+    episodes.push(episode);
+    // the newest first
+    episodes.sort_by(|a, b| b.date.cmp(&a.date));
+    std::fs::write(&episodes_path, serde_json::to_vec_pretty(&episodes)?).with_path(&episodes_path)?;
+
+    let feed_path = dir.join(FEED);
+    std::fs::write(&feed_path, render(feed, &episodes)?).with_path(&feed_path)
+}
+
+fn render(feed: &Feed, episodes: &[Episode]) -> Result<String> {
+    let base = match feed.url {
+        Some(url) => Some(Url::parse(&format!("{}/", url.trim_end_matches('/')))?),
+        None => None,
+    };
+    let url = |name: &str| -> Result<String> {
+        Ok(match &base {
+            Some(base) => base.join(name)?.to_string(),
+            None => name.to_string(),
+        })
+    };
+
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+  <channel>
+    <title>il manifesto</title>
+"#,
+    );
+    if let Some(base) = &base {
+        xml.push_str(&format!("    <link>{}</link>\n", escape(base.as_str())));
+    }
+    xml.push_str(&format!("    <description>{}</description>\n", escape(feed.strings.podcast_description)));
+    xml.push_str(&format!("    <language>{}</language>\n", escape(feed.lang)));
+    xml.push_str("    <itunes:author>il manifesto</itunes:author>\n");
+    if let Some(image) = episodes.iter().find_map(|episode| episode.image.as_deref()) {
+        xml.push_str(&format!("    <itunes:image href=\"{}\"/>\n", escape(&url(image)?)));
+    }
+
+    for episode in episodes {
+        let date = DateTime::parse_from_rfc3339(&episode.date).map_or(episode.date.clone(), |date| date.to_rfc2822());
+        xml.push_str("    <item>\n");
+        xml.push_str(&format!("      <title>{}</title>\n", escape(&episode.title)));
+        xml.push_str(&format!("      <guid isPermaLink=\"false\">{}</guid>\n", escape(&episode.guid)));
+        xml.push_str(&format!("      <pubDate>{}</pubDate>\n", date));
+        xml.push_str(&format!(
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape(&url(&episode.file)?),
+            episode.length,
+            episode.mime
+        ));
+        xml.push_str(&format!("      <itunes:duration>{}</itunes:duration>\n", duration(episode.seconds)));
+        if let Some(image) = &episode.image {
+            xml.push_str(&format!("      <itunes:image href=\"{}\"/>\n", escape(&url(image)?)));
+        }
+        xml.push_str("    </item>\n");
+    }
+    xml.push_str("  </channel>\n</rss>\n");
+    Ok(xml)
+}
+
+// HH:MM:SS
+fn duration(seconds: u64) -> String {
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
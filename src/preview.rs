@@ -0,0 +1,83 @@
+// Structure of a single ePUB file made in memory, printed by `build --preview`
+// instead of writing it: reading order, table of contents and resources with
+// their size, to check the ordering and the sections before making the file.
+use epub::doc::EpubDoc;
+
+use std::io::Cursor;
+
+use crate::error::Result;
+
+pub fn render(epub: Vec<u8>) -> Result<String> {
+    let size = epub.len();
+    let mut doc = EpubDoc::from_reader(Cursor::new(epub))?;
+    let mut lines = Vec::new();
+
+    lines.push("Reading order".to_string());
+    for (position, id) in doc.spine.iter().enumerate() {
+        let path = doc.resources.get(id).map(|(path, _)| path.display().to_string());
+        lines.push(format!("  {:3}  {}", position + 1, path.as_deref().unwrap_or(id)));
+    }
+
+    lines.push(String::new());
+    lines.push("Table of contents".to_string());
+    if let Ok(ncx) = doc.get_resource_str_by_path("OEBPS/toc.ncx") {
+        toc(&ncx, &mut lines);
+    }
+
+    let mut resources: Vec<(String, String, String)> = doc
+        .resources
+        .iter()
+        .map(|(id, (path, mime))| (path.display().to_string(), mime.clone(), id.clone()))
+        .collect();
+    resources.sort();
+
+    lines.push(String::new());
+    lines.push("Resources".to_string());
+    let mut total = 0;
+    for (path, mime, id) in &resources {
+        let len = doc.get_resource(id).map(|content| content.len()).unwrap_or(0);
+        total += len;
+        lines.push(format!("  {:>9}  {:<24} {}", human_size(len), mime, path));
+    }
+
+    lines.push(String::new());
+    lines.push(format!(
+        "{} resources, {} uncompressed, {} for the ePUB file",
+        resources.len(),
+        human_size(total),
+        human_size(size)
+    ));
+
+    Ok(lines.join("\n") + "\n")
+}
+
+// The navigation points of toc.ncx, indented by level. The epub crate skips
+// them, since epub-builder writes no playOrder.
+fn toc(ncx: &str, lines: &mut Vec<String>) {
+    let mut depth: usize = 0;
+    let mut label = String::new();
+    let mut rest = ncx;
+
+    while let Some(start) = rest.find('<') {
+        let tag = &rest[start..];
+        if tag.starts_with("<navPoint") {
+            depth += 1;
+        } else if tag.starts_with("</navPoint>") {
+            depth = depth.saturating_sub(1);
+        } else if let Some(text) = tag.strip_prefix("<text>") {
+            label = text[..text.find('<').unwrap_or(text.len())].trim().to_string();
+        } else if let Some(src) = tag.strip_prefix("<content src=\"") {
+            let src = &src[..src.find('"').unwrap_or(src.len())];
+            lines.push(format!("{}{}  ({})", "  ".repeat(depth), label, src));
+        }
+        rest = &tag[1..];
+    }
+}
+
+fn human_size(bytes: usize) -> String {
+    if bytes < 1_000_000 {
+        format!("{:.1} kB", bytes as f64 / 1_000.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+    }
+}
@@ -0,0 +1,85 @@
+// Progress events for GUI wrappers, one JSON object per line on stdout
+// with --progress-json. Every event has the schema version, the time and its
+// type; fields are only added within a version, anything else bumps it.
+//
+// {"version":1,"time":"...","type":"run-started","edition":"...","title":"..."}
+// {"version":1,"time":"...","type":"item-done","kind":"articles","name":"...","status":"failed","error":"..."}
+// {"version":1,"time":"...","type":"item-progress","kind":"articles","done":3,"total":20}
+// {"version":1,"time":"...","type":"run-summary","items":{"articles":{...}},"files":["..."]}
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Debug)]
+pub struct Counts {
+    pub downloaded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    RunStarted {
+        edition: &'a str,
+        title: &'a str,
+    },
+    ItemDone {
+        kind: &'a str,
+        name: &'a str,
+        // downloaded, skipped, failed, evicted (corrupted in the cache)
+        status: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<&'a str>,
+    },
+    ItemProgress {
+        kind: &'a str,
+        done: usize,
+        // unknown for images, found while downloading the articles
+        total: Option<usize>,
+    },
+    RunSummary {
+        items: BTreeMap<&'a str, Counts>,
+        files: &'a [PathBuf],
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    version: u32,
+    time: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Progress {
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Progress {
+        Progress { enabled }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if !self.enabled {
+            return;
+        }
+        let line = Line {
+            version: SCHEMA_VERSION,
+            time: Utc::now(),
+            event: &event,
+        };
+        if let Ok(line) = serde_json::to_string(&line) {
+            // a closed pipe must not stop the download
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Client side limit of the requests per second to the API, shared by the
+// articles downloaded at the same time, so that they do not trip the
+// publisher's abuse protection. When the server answers 429 every request
+// waits for the time it asked.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+pub struct RateLimiter {
+    // minimum time between the start of two requests, none without limit
+    interval: Option<Duration>,
+    // when the next request can start
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // Zero requests per second disables the limit
+    pub fn new(per_second: f64) -> RateLimiter {
+        RateLimiter {
+            interval: (per_second > 0.0).then(|| Duration::from_secs_f64(1.0 / per_second)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Wait for the turn of a request
+    pub async fn wait(&self) {
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval.unwrap_or_default();
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+
+    // No request starts before the delay is over
+    pub fn pause(&self, delay: Duration) {
+        let mut next = self.next.lock().unwrap();
+        *next = (*next).max(Instant::now() + delay);
+    }
+}
@@ -0,0 +1,78 @@
+// Export of the articles to read-it-later services (Readwise Reader,
+// Readeck), where they can be highlighted and archived. Articles can be
+// selected by section (name or slug) and by keyword (in the title, kicker,
+// summary and excerpt), the selection applies to Wallabag too. Articles sent
+// to a service are recorded in the history and not sent again.
+use std::path::Path;
+
+use log::info;
+
+use crate::archive::Archive;
+use crate::error::{Error, Result};
+use crate::history::History;
+use crate::xhtml::plain;
+use crate::{Edition, Post};
+
+pub trait Service {
+    // in the history and in the summary
+    fn name(&self) -> &'static str;
+    async fn save(&self, edition: &Edition, post: &Post, path: &Path) -> Result<()>;
+}
+
+// Every article when both are empty
+pub struct Selection<'a> {
+    pub sections: &'a [String],
+    pub keywords: &'a [String],
+}
+
+impl Selection<'_> {
+    pub fn matches(&self, post: &Post) -> bool {
+        let section = self.sections.is_empty()
+            || post.section.as_ref().is_some_and(|section| {
+                self.sections
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&plain(&section.name)) || name.eq_ignore_ascii_case(&section.slug))
+            });
+        if !section || self.keywords.is_empty() {
+            return section;
+        }
+        let text = [&post.title, &post.kicker, &post.summary, &post.excerpt]
+            .iter()
+            .map(|field| plain(field))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        self.keywords.iter().any(|keyword| text.contains(&keyword.to_lowercase()))
+    }
+}
+
+// Articles that could not be sent, with the reason
+pub async fn export(
+    service: &impl Service,
+    edition: &Edition,
+    posts: &[Post],
+    archive: &Archive,
+    history: &History,
+) -> Vec<(String, Error)> {
+    let archived = archive.latest();
+    let mut failed = Vec::new();
+    let mut count = 0;
+    for post in posts {
+        let Some((_, path)) = archived.iter().find(|(slug, _)| *slug == post.slug) else {
+            continue;
+        };
+        let sent = match history.exported(service.name(), &post.slug) {
+            Ok(true) => continue,
+            Ok(false) => service.save(edition, post, path).await,
+            Err(e) => Err(e),
+        };
+        match sent.and_then(|()| history.record_export(service.name(), &edition.slug, &post.slug)) {
+            Ok(()) => count += 1,
+            Err(e) => failed.push((post.slug.clone(), e)),
+        }
+    }
+    if count > 0 {
+        info!("Sent {} articles to {}", count, service.name());
+    }
+    failed
+}
@@ -0,0 +1,46 @@
+// Articles saved to a Readeck server as bookmarks, with the section as label.
+// The Readeck API only takes the link: the server downloads the page itself,
+// so only the free part of the articles behind the paywall is kept. The API
+// token is created in Readeck under Settings, API tokens.
+use serde_json::json;
+
+use std::path::Path;
+
+use crate::error::Result;
+use crate::read_later::Service;
+use crate::xhtml::plain;
+use crate::{Edition, Post};
+
+pub struct Readeck<'a> {
+    pub client: &'a reqwest::Client,
+    // e.g. https://readeck.example.org
+    pub url: &'a str,
+    pub token: &'a str,
+}
+
+impl Service for Readeck<'_> {
+    fn name(&self) -> &'static str {
+        "readeck"
+    }
+
+    async fn save(&self, _edition: &Edition, post: &Post, _path: &Path) -> Result<()> {
+        if post.link.is_empty() {
+            return Err("the article has no link".into());
+        }
+        let mut labels = vec!["il manifesto".to_string()];
+        labels.extend(post.section.as_ref().map(|section| plain(&section.name)));
+        let bookmark = json!({
+            "url": post.link,
+            "title": plain(&post.title),
+            "labels": labels,
+        });
+        self.client
+            .post(format!("{}/api/bookmarks", self.url.trim_end_matches('/')))
+            .bearer_auth(self.token)
+            .json(&bookmark)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
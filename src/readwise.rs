@@ -0,0 +1,75 @@
+// Articles saved to Readwise Reader with their text, so that Reader does not
+// need to get past the paywall. The access token is that of the account, from
+// https://readwise.io/access_token. Reader allows 50 saves a minute: when it
+// asks to wait, the article is sent again once after the delay.
+use serde_json::json;
+
+use std::path::Path;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::error::Result;
+use crate::read_later::Service;
+use crate::text;
+use crate::xhtml::plain;
+use crate::{Edition, Post};
+
+const SAVE: &str = "https://readwise.io/api/v3/save/";
+
+pub struct Readwise<'a> {
+    pub client: &'a reqwest::Client,
+    pub token: &'a str,
+}
+
+impl Service for Readwise<'_> {
+    fn name(&self) -> &'static str {
+        "readwise"
+    }
+
+    async fn save(&self, edition: &Edition, post: &Post, path: &Path) -> Result<()> {
+        if post.link.is_empty() {
+            return Err("the article has no link".into());
+        }
+        let mut tags = vec!["il manifesto".to_string()];
+        tags.extend(post.section.as_ref().map(|section| plain(&section.name)));
+        let mut document = json!({
+            "url": post.link,
+            "html": text::article_html(path)?,
+            "should_clean_html": false,
+            "title": plain(&post.title),
+            "author": post.authors.join(", "),
+            "summary": plain(&post.summary),
+            "tags": tags,
+            "category": "article",
+            "saved_using": "clima",
+        });
+        if let Some(day) = edition.day() {
+            document["published_date"] = json!(day.to_string());
+        }
+
+        for attempt in 0..2 {
+            let response = self
+                .client
+                .post(SAVE)
+                .header(reqwest::header::AUTHORIZATION, format!("Token {}", self.token))
+                .json(&document)
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && attempt == 0 {
+                let delay = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .unwrap_or(60);
+                debug!("Readwise asks to wait {} s", delay);
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                continue;
+            }
+            // 200 when it was already saved
+            response.error_for_status()?;
+            break;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+// Report of a run printed on stdout with --output json, so that scripts
+// can find the produced files without guessing their names. It is a single
+// line, so it can follow the --progress-json events.
+use clap::ValueEnum;
+use serde::Serialize;
+
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::summary::Summary;
+use crate::Edition;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    edition: EditionInfo<'a>,
+    files: Vec<FileInfo>,
+    items: Vec<ItemInfo<'a>>,
+}
+
+#[derive(Serialize)]
+struct EditionInfo<'a> {
+    id: i32,
+    slug: &'a str,
+    title: &'a str,
+    date: &'a str,
+}
+
+#[derive(Serialize)]
+struct FileInfo {
+    path: PathBuf,
+    // pdf, epub
+    format: String,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct ItemInfo<'a> {
+    // articles, images, pdf
+    kind: &'a str,
+    name: &'a str,
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+pub fn print(edition: &Edition, files: &[PathBuf], summary: &Summary) -> Result<()> {
+    let report = Report {
+        edition: EditionInfo {
+            id: edition.id,
+            slug: &edition.slug,
+            title: &edition.title,
+            date: &edition.date,
+        },
+        files: files.iter().map(|path| file_info(path)).collect(),
+        items: summary
+            .items
+            .iter()
+            .map(|item| ItemInfo {
+                kind: item.kind,
+                name: &item.name,
+                status: item.status.label(),
+                error: item.status.error(),
+            })
+            .collect(),
+    };
+
+    println!("{}", serde_json::to_string(&report)?);
+    Ok(())
+}
+
+fn file_info(path: &Path) -> FileInfo {
+    FileInfo {
+        path: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+        format: path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        size: path.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+    }
+}
@@ -0,0 +1,155 @@
+// Full-text search of the downloaded articles (the search command), with a
+// SQLite FTS5 index (search.db) in the state directory. The last version of
+// each article in the archive is indexed after every download; the index can
+// be rebuilt from the whole archive with --reindex.
+use epub::doc::EpubDoc;
+use rusqlite::{params, Connection};
+
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+
+use crate::archive::{self, Archive};
+use crate::error::Result;
+use crate::history::History;
+use crate::text;
+use crate::xhtml;
+use crate::{article_chapters, Edition};
+
+pub const FILE_NAME: &str = "search.db";
+
+// Accents are ignored, "perche" finds "perché"
+const SCHEMA: &str = "
+CREATE VIRTUAL TABLE IF NOT EXISTS articles USING fts5(
+    edition UNINDEXED,
+    date UNINDEXED,
+    slug UNINDEXED,
+    path UNINDEXED,
+    title,
+    text,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+";
+
+// Article found, the words of the query are in brackets in the snippet
+pub struct Hit {
+    pub edition: String,
+    pub date: String,
+    pub slug: String,
+    pub title: String,
+    pub snippet: String,
+    // article ePUB file in the archive
+    pub path: PathBuf,
+}
+
+pub struct Search {
+    db: Connection,
+}
+
+impl Search {
+    pub fn open(path: &Path) -> Result<Search> {
+        let db = Connection::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Search { db })
+    }
+
+    // Index the last version of the archived articles of the edition, in
+    // place of the previous ones. Titles are those recorded in the history.
+    pub fn index(&self, edition: &Edition, archive: &Archive, history: &History) -> Result<usize> {
+        let date = edition.day().map(|day| day.to_string()).unwrap_or_default();
+        let titles = history.article_titles(&edition.slug)?;
+        let tx = self.db.unchecked_transaction()?;
+        tx.execute("DELETE FROM articles WHERE edition = ?1", [&edition.slug])?;
+
+        let mut count = 0;
+        for (slug, path) in archive.latest() {
+            let (title, text) = match article_text(&path) {
+                Ok((title, text)) => (titles.get(slug).map_or(title, |title| xhtml::plain(title)), text),
+                Err(e) => {
+                    warn!("{} not indexed: {}", path.display(), e);
+                    continue;
+                }
+            };
+            tx.execute(
+                "INSERT INTO articles (edition, date, slug, path, title, text) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![edition.slug, date, slug, path.to_string_lossy(), title, text],
+            )?;
+            count += 1;
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    // Index again all the archived editions
+    pub fn rebuild(&self, archive_root: &Path, history: &History) -> Result<()> {
+        self.db.execute("DELETE FROM articles", [])?;
+        for slug in archive::editions(archive_root)? {
+            let edition = Edition {
+                id: 0,
+                title: String::new(),
+                slug,
+                pdf: String::new(),
+                date: String::new(),
+                featured_image: None,
+            };
+            let count = self.index(&edition, &Archive::open(archive_root, &edition.slug)?, history)?;
+            info!("Indexed {} articles of {}", count, edition.slug);
+        }
+        Ok(())
+    }
+
+    // Best matches first, newest first among equal ones
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Hit>> {
+        let query = match_expression(query);
+        if query.is_empty() {
+            return Err("empty search".into());
+        }
+        let mut statement = self.db.prepare(
+            "SELECT edition, date, slug, title, snippet(articles, 5, '[', ']', '…', 16), path
+             FROM articles WHERE articles MATCH ?1 ORDER BY rank, date DESC LIMIT ?2",
+        )?;
+        let rows = statement.query_map(params![query, limit], |row| {
+            let path: String = row.get(5)?;
+            Ok(Hit {
+                edition: row.get(0)?,
+                date: row.get(1)?,
+                slug: row.get(2)?,
+                title: row.get(3)?,
+                snippet: row.get(4)?,
+                path: PathBuf::from(path),
+            })
+        })?;
+        Ok(rows.collect::<std::result::Result<_, _>>()?)
+    }
+}
+
+// Every word must be found, the words in double quotes as a phrase. Words
+// are quoted, so that punctuation (l'Italia, 25-aprile) is not read as FTS5
+// syntax.
+fn match_expression(query: &str) -> String {
+    let mut terms = Vec::new();
+    for (i, part) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            terms.push(part.trim().to_string());
+        } else {
+            terms.extend(part.split_whitespace().map(String::from));
+        }
+    }
+    terms
+        .iter()
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"", term))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Title and plain text of an article ePUB file
+fn article_text(path: &Path) -> Result<(String, String)> {
+    let mut doc = EpubDoc::new(path)?;
+    let title = doc.mdata("title").unwrap_or_default();
+    let text: Vec<String> = article_chapters(&mut doc)?
+        .iter()
+        .map(|(_, content)| text::text(xhtml::body(content).unwrap_or_default()))
+        .collect();
+    Ok((xhtml::plain(&title), text.join("\n")))
+}
@@ -0,0 +1,100 @@
+// Optional encryption of files holding long-lived tokens (login.json).
+// The key is derived from a passphrase with Argon2id and the content is
+// encrypted with XChaCha20-Poly1305. Plain files are still read transparently.
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+
+const CIPHER: &str = "xchacha20poly1305-argon2id";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Encrypted {
+    cipher: String,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("key derivation failed: {}", e))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+// Read a file, decrypting it if it was written with a passphrase
+pub fn read(path: &Path, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    let content = fs::read(path).with_path(path)?;
+
+    let encrypted = match serde_json::from_slice::<Encrypted>(&content) {
+        Ok(encrypted) if encrypted.cipher == CIPHER => encrypted,
+        _ => return Ok(content),
+    };
+
+    let passphrase = passphrase.ok_or_else(|| {
+        format!("{} is encrypted, set CLIMA_PASSPHRASE", path.display())
+    })?;
+
+    let cipher = derive_key(passphrase, &BASE64.decode(encrypted.salt)?)?;
+    let nonce = BASE64.decode(encrypted.nonce)?;
+    let plain = cipher
+        .decrypt(XNonce::from_slice(&nonce), BASE64.decode(encrypted.data)?.as_ref())
+        .map_err(|_| format!("cannot decrypt {}, wrong passphrase?", path.display()))?;
+
+    Ok(plain)
+}
+
+// Write a file, encrypting it when a passphrase is given.
+// The file is only readable by the owner in any case.
+pub fn write(path: &Path, content: &[u8], passphrase: Option<&str>) -> Result<()> {
+    let content = match passphrase {
+        Some(passphrase) => {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+
+            let cipher = derive_key(passphrase, &salt)?;
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let data = cipher
+                .encrypt(&nonce, content)
+                .map_err(|_| "encryption failed")?;
+
+            serde_json::to_vec(&Encrypted {
+                cipher: CIPHER.to_string(),
+                salt: BASE64.encode(salt),
+                nonce: BASE64.encode(nonce),
+                data: BASE64.encode(data),
+            })?
+        }
+        None => content.to_vec(),
+    };
+
+    // readable by the owner only from the start, files of older versions
+    // are restricted before the token is written
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).with_path(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600)).with_path(path)?;
+    }
+    file.write_all(&content).with_path(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+// Web viewer of the downloaded editions (the serve command), for devices
+// without an ePUB reader: a page lists the editions recorded in the history
+// with their files still on disk, an edition is read in the browser as one
+// HTML page made from its ePUB file and its files can be downloaded.
+// Only GET and HEAD requests are answered, one at a time.
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::error::{FileContext, Result};
+use crate::history::History;
+use crate::html;
+use crate::output;
+use crate::xhtml::{escape, plain};
+
+// Requests are not read after this
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST: usize = 8192;
+
+macro_rules! PAGE {
+    () => {
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8" />
+<meta name="viewport" content="width=device-width, initial-scale=1" />
+<title>il manifesto</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 1em auto; padding: 0 1em; }}
+li {{ margin-bottom: 0.8em; }}
+.files a {{ margin-right: 0.6em; }}
+</style>
+</head>
+<body>
+<h1>il manifesto</h1>
+{editions}</body>
+</html>
+"#
+    };
+}
+
+// Edition with its files still on disk
+struct Edition {
+    slug: String,
+    title: String,
+    downloaded: DateTime<Utc>,
+    // format (file extension) -> last file
+    files: BTreeMap<String, PathBuf>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    // file name of a download
+    attachment: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn html(body: String) -> Response {
+        Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            attachment: None,
+            body: body.into_bytes(),
+        }
+    }
+
+    fn error(status: &'static str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            attachment: None,
+            body: format!("{}\n", status).into_bytes(),
+        }
+    }
+}
+
+pub async fn run(history: &History, listen: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    info!("Serving the editions on http://{}/", listener.local_addr()?);
+    loop {
+        let (mut stream, address) = listener.accept().await?;
+        match tokio::time::timeout(TIMEOUT, handle(&mut stream, history)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("{}: {}", address, e),
+            Err(_) => debug!("{}: request not received in time", address),
+        }
+    }
+}
+
+async fn handle(stream: &mut TcpStream, history: &History) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST {
+            return write(stream, Response::error("431 Request Header Fields Too Large"), false).await;
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = (words.next().unwrap_or_default(), words.next().unwrap_or_default());
+    let head = method == "HEAD";
+    if method != "GET" && !head {
+        return write(stream, Response::error("405 Method Not Allowed"), false).await;
+    }
+    debug!("{} {}", method, target);
+
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let response = match respond(history, path) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("{}: {}", path, e);
+            Response::error("500 Internal Server Error")
+        }
+    };
+    write(stream, response, head).await
+}
+
+// /, /read/<edition> and /file/<edition>/<format>
+fn respond(history: &History, path: &str) -> Result<Response> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let editions = editions(history)?;
+    let find = |slug: &str| editions.iter().find(|edition| edition.slug == slug);
+
+    Ok(match segments.as_slice() {
+        [] => Response::html(index(&editions)),
+        ["read", slug] => match find(slug).and_then(|edition| edition.files.get("epub")) {
+            Some(path) => Response::html(html::render(&std::fs::read(path).with_path(path)?)?),
+            None => Response::error("404 Not Found"),
+        },
+        ["file", slug, format] => match find(slug).and_then(|edition| edition.files.get(*format)) {
+            Some(path) => Response {
+                status: "200 OK",
+                content_type: output::content_type(format),
+                attachment: path.file_name().map(|name| name.to_string_lossy().to_string()),
+                body: std::fs::read(path).with_path(path)?,
+            },
+            None => Response::error("404 Not Found"),
+        },
+        _ => Response::error("404 Not Found"),
+    })
+}
+
+async fn write(stream: &mut TcpStream, response: Response, head: bool) -> Result<()> {
+    let mut headers = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if let Some(name) = &response.attachment {
+        // quotes and line breaks cannot end the header
+        let name: String = name.chars().filter(|c| !matches!(c, '"' | '\\' | '\r' | '\n')).collect();
+        headers.push_str(&format!("Content-Disposition: attachment; filename=\"{}\"\r\n", name));
+    }
+    headers.push_str("\r\n");
+
+    stream.write_all(headers.as_bytes()).await?;
+    if !head {
+        stream.write_all(&response.body).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Editions with files on disk, the last downloaded first
+fn editions(history: &History) -> Result<Vec<Edition>> {
+    let titles: BTreeMap<String, String> = history
+        .editions(DateTime::UNIX_EPOCH)?
+        .into_iter()
+        .map(|edition| (edition.slug, edition.title))
+        .collect();
+
+    let mut editions: BTreeMap<String, Edition> = BTreeMap::new();
+    for record in history.records()? {
+        if !record.path.is_file() {
+            continue;
+        }
+        let edition = editions.entry(record.edition.clone()).or_insert_with(|| Edition {
+            title: titles.get(&record.edition).cloned().unwrap_or_default(),
+            slug: record.edition.clone(),
+            downloaded: record.time,
+            files: BTreeMap::new(),
+        });
+        // records are oldest first
+        edition.downloaded = record.time;
+        edition.files.insert(record.format, record.path);
+    }
+
+    let mut editions: Vec<Edition> = editions.into_values().collect();
+    editions.sort_by_key(|edition| std::cmp::Reverse(edition.downloaded));
+    Ok(editions)
+}
+
+fn index(editions: &[Edition]) -> String {
+    let mut list = String::new();
+    if editions.is_empty() {
+        list.push_str("<p>No edition downloaded</p>\n");
+    } else {
+        list.push_str("<ul>\n");
+        for edition in editions {
+            let slug = escape(&edition.slug);
+            let title = if edition.title.is_empty() { slug.clone() } else { escape(&plain(&edition.title)) };
+            list.push_str(&format!("<li><strong>{}</strong><br />\n<span class=\"files\">", title));
+            if edition.files.contains_key("epub") {
+                list.push_str(&format!("<a href=\"/read/{}\">Read</a>", slug));
+            }
+            for format in edition.files.keys() {
+                let format = escape(format);
+                list.push_str(&format!("<a href=\"/file/{}/{}\">{}</a>", slug, format, format.to_uppercase()));
+            }
+            list.push_str(&format!("</span> <small>{}</small></li>\n", edition.downloaded.format("%Y-%m-%d")));
+        }
+        list.push_str("</ul>\n");
+    }
+    format!(PAGE!(), editions = list)
+}
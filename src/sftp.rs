@@ -0,0 +1,105 @@
+// Upload of the produced files to a remote host (a NAS, a web server) with
+// OpenSSH's sftp, found in the PATH. Authentication is by key only (the agent,
+// ~/.ssh or --sftp-key): there is nobody to type a password on a headless
+// server, and an unknown host key fails instead of being accepted. Files are
+// uploaded under a temporary name and renamed when complete, as in the
+// synchronized folders.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::info;
+
+use crate::error::Result;
+
+pub struct Sftp<'a> {
+    // [user@]host:dir, as for scp
+    pub destination: &'a str,
+    pub key: Option<&'a Path>,
+    pub port: Option<u16>,
+}
+
+impl Sftp<'_> {
+    // All the files in one session
+    pub fn deliver(&self, files: &[PathBuf]) -> Result<()> {
+        let (host, dir) = split(self.destination)?;
+        let mut batch = String::new();
+        if !dir.is_empty() {
+            // already there most of the time
+            batch.push_str(&format!("-mkdir {}\n", quote(dir)));
+        }
+        for file in files {
+            let name = file
+                .file_name()
+                .ok_or_else(|| format!("invalid file name {}", file.display()))?
+                .to_string_lossy();
+            let target = remote_path(dir, &name);
+            let tmp = remote_path(dir, &format!(".{}.tmp", name));
+            batch.push_str(&format!("put {} {}\n", quote(&file.to_string_lossy()), quote(&tmp)));
+            batch.push_str(&format!("rename {} {}\n", quote(&tmp), quote(&target)));
+        }
+
+        let mut command = Command::new("sftp");
+        command.args(["-b", "-", "-o", "BatchMode=yes"]);
+        if let Some(key) = self.key {
+            command.arg("-i").arg(key);
+        }
+        if let Some(port) = self.port {
+            command.arg("-P").arg(port.to_string());
+        }
+        let mut child = command
+            .arg(host)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot run sftp: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(batch.as_bytes())
+                .map_err(|e| format!("cannot write to sftp: {}", e))?;
+        }
+        let result = child
+            .wait_with_output()
+            .map_err(|e| format!("cannot run sftp: {}", e))?;
+        if !result.status.success() {
+            let error = String::from_utf8_lossy(&result.stderr);
+            let error = error.trim().lines().last().unwrap_or_default().to_string();
+            return Err(format!("sftp failed ({}): {}", result.status, error).into());
+        }
+        for file in files {
+            info!("Uploaded {} to {}", file.display(), self.destination);
+        }
+        Ok(())
+    }
+}
+
+// Host (with the user) and directory, the host in brackets for IPv6
+fn split(destination: &str) -> Result<(&str, &str)> {
+    let (user, rest) = match destination.split_once('@') {
+        Some((user, rest)) => (user.len() + 1, rest),
+        None => (0, destination),
+    };
+    let colon = match rest.strip_prefix('[') {
+        Some(address) => address.find("]:").map(|end| end + 2),
+        None => rest.find(':'),
+    };
+    match colon {
+        Some(colon) if colon > 0 => Ok((&destination[..user + colon], &rest[colon + 1..])),
+        _ => Err(format!("invalid SFTP destination {}, expected [user@]host:directory", destination).into()),
+    }
+}
+
+// Relative to the home directory when dir is
+fn remote_path(dir: &str, name: &str) -> String {
+    match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => format!("/{}", name),
+        "" => name.to_string(),
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+// Arguments of the batch commands, in double quotes
+fn quote(argument: &str) -> String {
+    format!("\"{}\"", argument.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,68 @@
+// Location of the files that must survive between runs (login token, credentials).
+// They used to be read from the current working directory, which does not work
+// when the program is started from cron or from a different folder.
+use directories::ProjectDirs;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+// Files that older versions kept in the current working directory
+const STATE_FILES: [&str; 2] = ["login.json", "credentials.json"];
+
+pub struct StateDir {
+    path: PathBuf,
+}
+
+impl StateDir {
+    // Use the directory given on the command line or the platform one
+    // (e.g. ~/.local/state/clima on Linux). It is created if missing.
+    pub fn new(path: Option<PathBuf>) -> io::Result<StateDir> {
+        let path = match path {
+            Some(path) => path,
+            None => default_dir()?,
+        };
+        fs::create_dir_all(&path)?;
+
+        Ok(StateDir { path })
+    }
+
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // Move state files left in the current working directory by older versions.
+    // Files already present in the state directory are never overwritten.
+    pub fn migrate(&self) -> io::Result<()> {
+        for name in STATE_FILES {
+            let old_path = Path::new(name);
+            let new_path = self.file(name);
+
+            if !old_path.is_file() || new_path.exists() {
+                continue;
+            }
+
+            // rename does not work across file systems, copy in that case
+            if fs::rename(old_path, &new_path).is_err() {
+                fs::copy(old_path, &new_path)?;
+                fs::remove_file(old_path)?;
+            }
+            info!("Moved {} to {}", name, new_path.display());
+        }
+        Ok(())
+    }
+}
+
+fn default_dir() -> io::Result<PathBuf> {
+    let dirs = ProjectDirs::from("it", "ilmanifesto", "clima").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "cannot find home directory, use --state-dir")
+    })?;
+
+    // state_dir is only defined on Linux
+    Ok(dirs
+        .state_dir()
+        .unwrap_or_else(|| dirs.data_local_dir())
+        .to_path_buf())
+}
@@ -0,0 +1,160 @@
+// What happened to each downloaded item, printed at the end of the run
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::Error;
+use crate::progress::{Counts, Event, Progress};
+
+pub enum Status {
+    Downloaded,
+    Skipped,
+    Failed(String),
+    // corrupted file removed from the cache, to be downloaded again
+    Evicted(String),
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Downloaded => "downloaded",
+            Status::Skipped => "skipped",
+            Status::Failed(_) => "failed",
+            Status::Evicted(_) => "evicted",
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Status::Failed(error) | Status::Evicted(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+pub struct Item {
+    // articles, images, pdf
+    pub kind: &'static str,
+    pub name: String,
+    pub status: Status,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub items: Vec<Item>,
+    // expected number of items of a kind, when known in advance
+    totals: HashMap<&'static str, usize>,
+    progress: Progress,
+}
+
+impl Summary {
+    pub fn new(progress: Progress) -> Summary {
+        Summary {
+            progress,
+            ..Default::default()
+        }
+    }
+
+    pub fn expect(&mut self, kind: &'static str, total: usize) {
+        self.totals.insert(kind, total);
+    }
+
+    fn add(&mut self, kind: &'static str, name: &str, status: Status) {
+        self.progress.emit(Event::ItemDone {
+            kind,
+            name,
+            status: status.label(),
+            error: status.error(),
+        });
+
+        self.items.push(Item {
+            kind,
+            name: name.to_string(),
+            status,
+        });
+
+        let done = self
+            .items
+            .iter()
+            .filter(|item| item.kind == kind && !matches!(item.status, Status::Evicted(_)))
+            .count();
+        self.progress.emit(Event::ItemProgress {
+            kind,
+            done,
+            total: self.totals.get(kind).copied(),
+        });
+    }
+
+    pub fn downloaded(&mut self, kind: &'static str, name: &str) {
+        self.add(kind, name, Status::Downloaded);
+    }
+
+    pub fn skipped(&mut self, kind: &'static str, name: &str) {
+        self.add(kind, name, Status::Skipped);
+    }
+
+    pub fn failed(&mut self, kind: &'static str, name: &str, error: &Error) {
+        self.add(kind, name, Status::Failed(error.to_string()));
+    }
+
+    pub fn evicted(&mut self, kind: &'static str, name: &str, reason: &str) {
+        self.add(kind, name, Status::Evicted(reason.to_string()));
+    }
+
+    // Number of (downloaded, skipped, failed) items of a kind
+    pub fn count(&self, kind: &str) -> (usize, usize, usize) {
+        self.items
+            .iter()
+            .filter(|item| item.kind == kind)
+            .fold((0, 0, 0), |(d, s, f), item| match item.status {
+                Status::Downloaded => (d + 1, s, f),
+                Status::Skipped => (d, s + 1, f),
+                Status::Failed(_) => (d, s, f + 1),
+                Status::Evicted(_) => (d, s, f),
+            })
+    }
+
+    // Kinds in the order they were first seen
+    fn kinds(&self) -> Vec<&'static str> {
+        let mut kinds = Vec::new();
+        for item in &self.items {
+            if !kinds.contains(&item.kind) {
+                kinds.push(item.kind);
+            }
+        }
+        kinds
+    }
+
+    // Final progress event with the counts and the files produced
+    pub fn finish(&self, files: &[PathBuf]) {
+        let items = self
+            .kinds()
+            .into_iter()
+            .map(|kind| {
+                let (downloaded, skipped, failed) = self.count(kind);
+                (kind, Counts { downloaded, skipped, failed })
+            })
+            .collect();
+        self.progress.emit(Event::RunSummary { items, files });
+    }
+
+    pub fn print(&self) {
+        let kinds = self.kinds();
+        if kinds.is_empty() {
+            return;
+        }
+
+        eprintln!("{:<10} {:>10} {:>8} {:>7}", "", "downloaded", "skipped", "failed");
+        for kind in kinds {
+            let (downloaded, skipped, failed) = self.count(kind);
+            eprintln!("{:<10} {:>10} {:>8} {:>7}", kind, downloaded, skipped, failed);
+        }
+
+        for item in &self.items {
+            match &item.status {
+                Status::Failed(error) => eprintln!("Failed {}: {}", item.name, error),
+                Status::Evicted(reason) => eprintln!("Downloaded again {}: {}", item.name, reason),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Figures served as SVG (charts, infographics). They are embedded as they are,
+// or drawn as PNG images with --svg-raster for readers without SVG support
+// (ePUB 2 readers, older Kindles). Text is drawn with the fonts of the
+// generated covers, so that the result does not depend on the system.
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+
+use crate::cover;
+use crate::error::Result;
+
+// SVG files are XML: image::guess_format does not know them
+pub fn is_svg(content: &[u8]) -> bool {
+    let start = &content[..content.len().min(1024)];
+    let start = String::from_utf8_lossy(start);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    (start.starts_with("<?xml") || start.starts_with("<svg") || start.starts_with("<!")) && start.contains("<svg")
+}
+
+// PNG image `width` pixels wide, on white
+pub fn rasterize(content: &[u8], width: u32) -> Result<Vec<u8>> {
+    let mut options = Options {
+        font_family: "DejaVu Serif".to_string(),
+        ..Options::default()
+    };
+    let fontdb = options.fontdb_mut();
+    fontdb.load_font_data(cover::REGULAR.to_vec());
+    fontdb.load_font_data(cover::BOLD.to_vec());
+    // generic families would ask for Times and Arial
+    fontdb.set_serif_family("DejaVu Serif");
+    fontdb.set_sans_serif_family("DejaVu Serif");
+    options.font_family = "DejaVu Serif".to_string();
+
+    let tree = Tree::from_data(content, &options).map_err(|e| format!("invalid SVG: {}", e))?;
+    let size = tree.size();
+    let scale = width as f32 / size.width();
+    let height = (size.height() * scale).ceil().max(1.0) as u32;
+
+    let mut pixmap = Pixmap::new(width.max(1), height).ok_or("SVG image too large")?;
+    pixmap.fill(Color::WHITE);
+    resvg::render(&tree, Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+    Ok(pixmap.encode_png().map_err(|e| e.to_string())?)
+}
@@ -0,0 +1,58 @@
+// Delivery of the produced files to a folder synchronized by Syncthing,
+// Dropbox and the like. Files are copied under a temporary name and renamed
+// when complete, so that a half written file is never synchronized. With the
+// hash suffix, two machines fetching the same edition produce the same name
+// instead of a sync conflict.
+use sha2::{Digest, Sha256};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{FileContext, Result};
+
+pub fn deliver(dir: &Path, files: &[PathBuf], hash_suffix: bool) -> Result<()> {
+    fs::create_dir_all(dir).with_path(dir)?;
+
+    for file in files {
+        let content = fs::read(file).with_path(file)?;
+        let name = file
+            .file_name()
+            .ok_or_else(|| format!("invalid file name {}", file.display()))?
+            .to_string_lossy()
+            .to_string();
+        let name = if hash_suffix {
+            with_hash(&name, &content)
+        } else {
+            name
+        };
+
+        let target = dir.join(&name);
+        if fs::read(&target).is_ok_and(|existing| existing == content) {
+            info!("{} is already in {}", name, dir.display());
+            continue;
+        }
+
+        // hidden while it is written, on the same file system for the rename
+        let tmp = dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, &content).with_path(&tmp)?;
+        fs::rename(&tmp, &target).with_path(&target)?;
+        info!("Copied {} to {}", name, dir.display());
+    }
+
+    Ok(())
+}
+
+// edition.epub -> edition-1a2b3c4d.epub
+fn with_hash(name: &str, content: &[u8]) -> String {
+    let hash: String = Sha256::digest(content)
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}-{}.{}", stem, hash, ext),
+        None => format!("{}-{}", name, hash),
+    }
+}
@@ -0,0 +1,159 @@
+// Wide tables are unreadable on small e-readers, which cannot scroll them
+// horizontally. Tables wider than the screen are drawn as SVG images scaled to
+// the screen width, with a simple layout: fixed width characters, long cells
+// wrapped on words. The original table is kept as fallback content of the
+// <object> element for readers without SVG support.
+use crate::xhtml::{escape, plain};
+
+// Layout in SVG units
+const FONT_SIZE: f32 = 14.0;
+const CHAR_WIDTH: f32 = FONT_SIZE * 0.6;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.3;
+const PADDING: f32 = 6.0;
+// cells longer than this are wrapped
+const MAX_CELL_CHARS: usize = 30;
+
+struct Cell {
+    header: bool,
+    lines: Vec<String>,
+}
+
+// Replace the tables wider than `screen_width` pixels with images.
+// Returns the new content and the SVG files to add, named after `prefix`.
+pub fn convert(content: &str, prefix: &str, screen_width: u32) -> (String, Vec<(String, String)>) {
+    let mut output = String::with_capacity(content.len());
+    let mut images = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<table") {
+        let end = match rest[start..].find("</table>") {
+            Some(end) => start + end + "</table>".len(),
+            None => break,
+        };
+        let table = &rest[start..end];
+        output.push_str(&rest[..start]);
+
+        let rows = parse_rows(table);
+        let (svg, width) = draw(&rows);
+        // nested tables are left alone
+        if width > screen_width as f32 && table.matches("<table").count() == 1 {
+            // file name is used in the id of xml file and cannot start with number
+            let name = format!("table-{}-{}.svg", prefix, images.len() + 1);
+            output.push_str(&format!(
+                r#"<div class="table-image"><object data="{}" type="image/svg+xml" width="100%">{}</object></div>"#,
+                name, table
+            ));
+            images.push((name, svg));
+        } else {
+            output.push_str(table);
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+
+    (output, images)
+}
+
+fn parse_rows(table: &str) -> Vec<Vec<Cell>> {
+    let mut rows = Vec::new();
+    for row in table.split("<tr").skip(1) {
+        let row = row.split("</tr>").next().unwrap_or_default();
+        let mut cells = Vec::new();
+
+        let mut rest = row;
+        while let Some(start) = rest.find("<t") {
+            let tag = &rest[start..];
+            // not <thead> or <tbody>
+            let header = tag.starts_with("<th>") || tag.starts_with("<th ");
+            if !header && !tag.starts_with("<td>") && !tag.starts_with("<td ") {
+                rest = &rest[start + 2..];
+                continue;
+            }
+            let close = if header { "</th>" } else { "</td>" };
+            let open_end = tag.find('>').map(|i| i + 1).unwrap_or(tag.len());
+            let close_start = tag.find(close).unwrap_or(tag.len());
+            let text = plain(&tag[open_end.min(close_start)..close_start]);
+            cells.push(Cell {
+                header,
+                lines: wrap(&text),
+            });
+            rest = &tag[(close_start + close.len()).min(tag.len())..];
+        }
+
+        if !cells.is_empty() {
+            rows.push(cells);
+        }
+    }
+    rows
+}
+
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > MAX_CELL_CHARS {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines
+}
+
+// SVG drawing of the table and its natural width
+fn draw(rows: &[Vec<Cell>]) -> (String, f32) {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut widths = vec![0.0f32; columns];
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let chars = cell.lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+            widths[i] = widths[i].max(chars as f32 * CHAR_WIDTH + 2.0 * PADDING);
+        }
+    }
+    let heights: Vec<f32> = rows
+        .iter()
+        .map(|row| {
+            let lines = row.iter().map(|cell| cell.lines.len()).max().unwrap_or(1);
+            lines as f32 * LINE_HEIGHT + 2.0 * PADDING
+        })
+        .collect();
+
+    let width: f32 = widths.iter().sum();
+    let height: f32 = heights.iter().sum();
+
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {:.0} {:.0}" font-family="serif" font-size="{}">"#,
+        width.ceil() + 1.0,
+        height.ceil() + 1.0,
+        FONT_SIZE
+    );
+    svg.push_str(r#"<rect width="100%" height="100%" fill="white"/>"#);
+
+    let mut y = 0.5;
+    for (row, row_height) in rows.iter().zip(&heights) {
+        let mut x = 0.5;
+        for (cell, cell_width) in row.iter().zip(&widths) {
+            svg.push_str(&format!(
+                r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="none" stroke="black"/>"#,
+                x, y, cell_width, row_height
+            ));
+            for (i, line) in cell.lines.iter().enumerate() {
+                svg.push_str(&format!(
+                    r#"<text x="{:.1}" y="{:.1}"{}>{}</text>"#,
+                    x + PADDING,
+                    y + PADDING + FONT_SIZE + i as f32 * LINE_HEIGHT,
+                    if cell.header { r#" font-weight="bold""# } else { "" },
+                    escape(line)
+                ));
+            }
+            x += cell_width;
+        }
+        y += row_height;
+    }
+    svg.push_str("</svg>");
+
+    (svg, width)
+}
@@ -0,0 +1,75 @@
+// Delivery to a Telegram chat of the e-books among the produced files (PDF,
+// ePUB, Kindle and CBZ files, not HTML, text or audio), as documents sent by a
+// bot: many e-readers and phones can open them straight from there. The bot is
+// created with @BotFather, the chat id is that of the user, group or channel
+// where the bot can write.
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::error::{Error, FileContext, Result};
+use crate::output;
+
+const API: &str = "https://api.telegram.org";
+
+// Largest file a bot can send
+const MAX_SIZE: u64 = 50 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    description: String,
+}
+
+pub struct Telegram<'a> {
+    pub client: &'a reqwest::Client,
+    pub token: &'a str,
+    pub chat: &'a str,
+}
+
+impl Telegram<'_> {
+    // Files that could not be sent, with the reason
+    pub async fn deliver(&self, files: &[PathBuf], caption: &str) -> Vec<(PathBuf, Error)> {
+        let mut failed = Vec::new();
+        for file in files.iter().filter(|file| output::is_ebook(file)) {
+            match self.send(file, caption).await {
+                Ok(()) => info!("Sent {} to Telegram", file.display()),
+                Err(e) => failed.push((file.clone(), e)),
+            }
+        }
+        failed
+    }
+
+    async fn send(&self, file: &Path, caption: &str) -> Result<()> {
+        let size = std::fs::metadata(file).with_path(file)?.len();
+        if size > MAX_SIZE {
+            return Err(format!("{} MB, bots can send up to {} MB", size >> 20, MAX_SIZE >> 20).into());
+        }
+        let name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let content = std::fs::read(file).with_path(file)?;
+        let form = Form::new()
+            .text("chat_id", self.chat.to_string())
+            .text("caption", caption.to_string())
+            .part("document", Part::bytes(content).file_name(name));
+
+        // the URL has the token, it is left out of the errors
+        let response: Response = self
+            .client
+            .post(format!("{}/bot{}/sendDocument", API, self.token))
+            .multipart(form)
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?
+            .json()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        if !response.ok {
+            return Err(format!("Telegram refused the file: {}", response.description).into());
+        }
+        Ok(())
+    }
+}
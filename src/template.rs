@@ -0,0 +1,88 @@
+// Variables of the text templates: the copyright notice of the locale strings,
+// the names of the output files and the generated pages (see pages.rs).
+// Placeholders are written as {name}, fields are reached with dots
+// ({edition.title}) and list items by position ({posts.0.title}).
+// Unknown placeholders are left as they are.
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{Edition, Post};
+
+// See "Template variables" in the README for the list of fields
+#[derive(Serialize)]
+pub struct Context<'a> {
+    pub edition: &'a Edition,
+    // edition date, or slug if the API does not report it
+    pub date: &'a str,
+    pub lang: &'a str,
+    // articles included, in the order of the front page
+    pub posts: &'a [Post],
+    // articles left out by --max-articles
+    pub omitted: &'a [Post],
+    // slugs of the articles changed since the previous download
+    pub updated: &'a [String],
+}
+
+// Name of the produced files, {slug}.{ext} by default
+pub const DEFAULT_NAME: &str = "{slug}.{ext}";
+
+// Characters not allowed in file names on some file systems (e.g. FAT on e-readers)
+const INVALID_NAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// Name of an output file: {date}, {slug}, {title} and {ext} (or {format}) can be
+// used, as well as the edition fields ({edition.id})
+pub fn file_name(template: &str, edition: &Edition, ext: &str) -> String {
+    let context = json!({
+        "date": edition.date_or_slug(),
+        "slug": edition.slug,
+        "title": edition.title,
+        "ext": ext,
+        "format": ext,
+        "edition": edition,
+    });
+    render(template, &context).replace(INVALID_NAME_CHARS, "-")
+}
+
+pub fn render(template: &str, context: &Value) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest
+            .find('}')
+            .and_then(|end| lookup(context, &rest[1..end]).map(|value| (end, value)));
+        match value {
+            Some((end, value)) => {
+                output.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn lookup(context: &Value, name: &str) -> Option<String> {
+    let mut value = context;
+    for key in name.split('.') {
+        value = match value {
+            Value::Object(map) => map.get(key)?,
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => Some(String::new()),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        Value::Array(_) | Value::Object(_) => None,
+    }
+}
@@ -0,0 +1,152 @@
+// Plain text of the articles, made from the single ePUB file, for
+// text-to-speech and text analysis: one UTF-8 file for each article,
+// numbered in reading order (001-<slug>.txt), and with --text-all one file
+// with all of them. Paragraphs are separated by an empty line, list items
+// start with a dash, images are left out.
+use epub::doc::EpubDoc;
+use serde::Deserialize;
+
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+use crate::article_chapters;
+use crate::locators;
+use crate::xhtml;
+
+// Elements ending a paragraph of the text
+const BLOCKS: &[&str] = &[
+    "p", "div", "h1", "h2", "h3", "h4", "h5", "h6", "li", "ul", "ol", "dt", "dd", "blockquote", "pre", "table", "tr",
+    "figure", "figcaption", "section", "article", "header", "footer",
+];
+
+// Between the articles of the whole file
+const SEPARATOR: &str = "\n* * *\n\n";
+
+#[derive(Deserialize)]
+struct Index {
+    articles: Vec<Article>,
+}
+
+#[derive(Deserialize)]
+struct Article {
+    slug: String,
+    href: String,
+    position: usize,
+}
+
+// The files of the articles in `dir` (replaced), and the whole text in `all`
+pub fn write(epub: &[u8], dir: &Path, all: Option<&Path>) -> Result<()> {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir).with_path(dir)?;
+    }
+    std::fs::create_dir_all(dir).with_path(dir)?;
+
+    let mut whole = Vec::new();
+    for (number, page) in pages(epub)?.into_iter().enumerate() {
+        let text = text(xhtml::body(&page.xhtml).unwrap_or_default());
+        let path = dir.join(format!("{:03}-{}.txt", number + 1, page.slug));
+        std::fs::write(&path, &text).with_path(&path)?;
+        whole.push(text);
+    }
+
+    if let Some(path) = all {
+        std::fs::write(path, whole.join(SEPARATOR)).with_path(path)?;
+    }
+    Ok(())
+}
+
+// Page of an article in the single ePUB file
+pub struct Page {
+    pub slug: String,
+    // index in the reading order
+    pub position: usize,
+    pub xhtml: String,
+}
+
+// Pages of the articles, in reading order
+pub fn pages(epub: &[u8]) -> Result<Vec<Page>> {
+    let mut doc = EpubDoc::from_reader(Cursor::new(epub))?;
+    let index = doc.get_resource_by_path(Path::new("OEBPS").join(locators::FILE_NAME))?;
+    let mut index: Index = serde_json::from_slice(&index)?;
+    index.articles.sort_by_key(|article| article.position);
+
+    index
+        .articles
+        .into_iter()
+        .map(|article| {
+            let xhtml = doc.get_resource_str_by_path(Path::new("OEBPS").join(&article.href))?;
+            Ok(Page {
+                slug: article.slug,
+                position: article.position,
+                xhtml,
+            })
+        })
+        .collect()
+}
+
+// Text of the body of a page, a paragraph for each block element
+pub fn text(body: &str) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut paragraph = String::new();
+    // a list item, its first paragraph starts with a dash
+    let mut item = false;
+    let mut rest = body;
+
+    let mut flush = |paragraph: &mut String, item: &mut bool| {
+        let lines: Vec<String> = paragraph
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect();
+        paragraph.clear();
+        if !lines.is_empty() {
+            let dash = if std::mem::take(item) { "- " } else { "" };
+            paragraphs.push(format!("{}{}", dash, lines.join("\n")));
+        }
+    };
+
+    while let Some((start, end)) = xhtml::next_tag(rest) {
+        paragraph.push_str(&xhtml::unescape(&rest[..start]).replace('\n', " "));
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+
+        let name = xhtml::tag_name(tag).to_ascii_lowercase();
+        if name == "script" || name == "style" {
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(i) => rest[i..].find('>').map_or("", |end| &rest[i + end + 1..]),
+                None => "",
+            };
+        } else if name == "br" {
+            paragraph.push('\n');
+        } else if BLOCKS.contains(&name.as_str()) {
+            flush(&mut paragraph, &mut item);
+            if name == "li" && !tag.starts_with("</") {
+                item = true;
+            }
+        }
+    }
+    paragraph.push_str(&xhtml::unescape(rest));
+    flush(&mut paragraph, &mut item);
+
+    paragraphs.join("\n\n") + "\n"
+}
+
+// The text of an article ePUB file as HTML, a <p> for each paragraph
+// (headings and captions included, the images are left out), for the
+// read-it-later services
+pub fn article_html(path: &Path) -> Result<String> {
+    let mut doc = EpubDoc::new(path)?;
+    let mut paragraphs = Vec::new();
+    for (_, content) in article_chapters(&mut doc)? {
+        let text = text(xhtml::body(&content).unwrap_or_default());
+        paragraphs.extend(
+            text.split("\n\n")
+                .map(str::trim)
+                .filter(|paragraph| !paragraph.is_empty())
+                .map(|paragraph| format!("<p>{}</p>", xhtml::escape(paragraph))),
+        );
+    }
+    Ok(paragraphs.join("\n"))
+}
@@ -0,0 +1,39 @@
+// Stylesheet of the single ePUB file: the page break rules, then a bundled
+// theme (--theme) and a file of the user (--stylesheet), so that rules given
+// later take precedence. Every generated page and article links it.
+use clap::ValueEnum;
+
+use std::path::Path;
+
+use crate::error::{FileContext, Result};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Theme {
+    // the fonts and margins of the reader
+    #[default]
+    None,
+    // justified text with hyphenation
+    Serif,
+    Sans,
+    // larger text for small screens
+    Large,
+}
+
+impl Theme {
+    fn css(self) -> &'static str {
+        match self {
+            Theme::None => "",
+            Theme::Serif => include_str!("../assets/themes/serif.css"),
+            Theme::Sans => include_str!("../assets/themes/sans.css"),
+            Theme::Large => include_str!("../assets/themes/large.css"),
+        }
+    }
+}
+
+pub fn stylesheet(base: &str, theme: Theme, path: Option<&Path>) -> Result<String> {
+    let mut css = format!("{}{}", base, theme.css());
+    if let Some(path) = path {
+        css.push_str(&std::fs::read_to_string(path).with_path(path)?);
+    }
+    Ok(css)
+}
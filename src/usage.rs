@@ -0,0 +1,55 @@
+// Summary of the downloads recorded in the history, to see how much the
+// subscription is actually used.
+use chrono::{Duration, Utc};
+
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use crate::history::History;
+
+#[derive(Default)]
+struct Month {
+    editions: usize,
+    articles: usize,
+}
+
+pub fn print(history: &History, days: i64) -> Result<()> {
+    let since = Utc::now() - Duration::days(days.max(0));
+
+    // articles of an edition downloaded more than once are counted once
+    let mut editions: BTreeMap<String, (String, usize)> = BTreeMap::new();
+    for record in history.records()? {
+        if record.time < since {
+            continue;
+        }
+        let month = record.time.format("%Y-%m").to_string();
+        let edition = editions.entry(record.edition).or_insert((month, 0));
+        edition.1 = edition.1.max(record.articles);
+    }
+
+    let mut months: BTreeMap<String, Month> = BTreeMap::new();
+    for (month, articles) in editions.values() {
+        let month = months.entry(month.clone()).or_default();
+        month.editions += 1;
+        month.articles += articles;
+    }
+
+    println!("Last {} days", days);
+    if months.is_empty() {
+        println!("No edition downloaded");
+        return Ok(());
+    }
+
+    println!("{:<8} {:>9} {:>9}", "", "editions", "articles");
+    for (name, month) in &months {
+        println!("{:<8} {:>9} {:>9}", name, month.editions, month.articles);
+    }
+    println!(
+        "{:<8} {:>9} {:>9}",
+        "total",
+        editions.len(),
+        editions.values().map(|(_, articles)| articles).sum::<usize>()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// Checksums and validation of the downloaded files.
+// Files are handed to a pool of worker threads while the downloads go on,
+// so that verifying them does not add to the total time.
+use bytes::Bytes;
+use epub::doc::EpubDoc;
+use sha2::{Digest, Sha256};
+
+use std::io::Cursor;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub struct Verified {
+    pub name: String,
+    pub sha256: String,
+    // reason why the file is not valid
+    pub error: Option<String>,
+}
+
+pub struct Verifier {
+    jobs: Option<Sender<(String, Bytes)>>,
+    results: Receiver<Verified>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Verifier {
+    // Start a pool with one worker per CPU
+    pub fn new() -> Verifier {
+        let size = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+        let (jobs, job_receiver) = channel::<(String, Bytes)>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..size)
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let result_sender = result_sender.clone();
+                thread::spawn(move || loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    match job {
+                        Ok((name, content)) => {
+                            let _ = result_sender.send(verify(name, &content));
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        Verifier {
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+
+    pub fn submit(&self, name: &str, content: Bytes) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send((name.to_string(), content));
+        }
+    }
+
+    // Wait for the pending files and return all the results
+    pub fn finish(mut self) -> Vec<Verified> {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.results.try_iter().collect()
+    }
+}
+
+fn verify(name: String, content: &[u8]) -> Verified {
+    let sha256 = Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    let error = if content.is_empty() {
+        Some("empty file".to_string())
+    } else if name.ends_with(".epub") {
+        EpubDoc::from_reader(Cursor::new(content))
+            .err()
+            .map(|e| format!("invalid ePUB: {}", e))
+    } else if name.ends_with(".pdf") {
+        if content.starts_with(b"%PDF") {
+            None
+        } else {
+            Some("invalid PDF".to_string())
+        }
+    } else if crate::svg::is_svg(content) {
+        None
+    } else {
+        image::guess_format(content)
+            .err()
+            .map(|e| format!("invalid image: {}", e))
+    };
+
+    Verified { name, sha256, error }
+}
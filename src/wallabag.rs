@@ -0,0 +1,137 @@
+// Export of the articles to a Wallabag server, for reading them one at a time
+// in a read-it-later app: each article is saved with its link, title,
+// authors, section (as tag) and text, taken from the archived ePUB file, so
+// that Wallabag does not need to get past the paywall. Articles already saved
+// (same link) are left as they are. The API client (id and secret) is created
+// in Wallabag under API clients management. Pocket is not supported, it was
+// closed in 2025.
+use serde::Deserialize;
+use serde_json::json;
+
+use std::path::Path;
+
+use log::info;
+
+use crate::archive::Archive;
+use crate::error::{Error, Result};
+use crate::text;
+use crate::xhtml;
+use crate::{Edition, Post};
+
+const TAG: &str = "il manifesto";
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct Exists {
+    exists: bool,
+}
+
+pub struct Wallabag<'a> {
+    pub client: &'a reqwest::Client,
+    // e.g. https://app.wallabag.it
+    pub url: &'a str,
+    pub client_id: &'a str,
+    pub client_secret: &'a str,
+    pub user: &'a str,
+    pub password: &'a str,
+}
+
+impl Wallabag<'_> {
+    // Articles that could not be saved, with the reason
+    pub async fn deliver(&self, edition: &Edition, posts: &[Post], archive: &Archive) -> Vec<(String, Error)> {
+        if posts.is_empty() {
+            return Vec::new();
+        }
+        let token = match self.token().await {
+            Ok(token) => token,
+            Err(e) => return vec![(self.url.to_string(), e)],
+        };
+        let archived = archive.latest();
+
+        let mut failed = Vec::new();
+        let mut count = 0;
+        for post in posts {
+            let Some((_, path)) = archived.iter().find(|(slug, _)| *slug == post.slug) else {
+                continue;
+            };
+            match self.save(&token, edition, post, path).await {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => failed.push((post.slug.clone(), e)),
+            }
+        }
+        info!("Saved {} articles to Wallabag", count);
+        failed
+    }
+
+    async fn token(&self) -> Result<String> {
+        let form = [
+            ("grant_type", "password"),
+            ("client_id", self.client_id),
+            ("client_secret", self.client_secret),
+            ("username", self.user),
+            ("password", self.password),
+        ];
+        let token: Token = self
+            .client
+            .post(self.endpoint("oauth/v2/token"))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()
+            .map_err(|e| format!("Wallabag refused the login: {}", e))?
+            .json()
+            .await?;
+        Ok(token.access_token)
+    }
+
+    // Whether the article was saved, false when it already was
+    async fn save(&self, token: &str, edition: &Edition, post: &Post, path: &Path) -> Result<bool> {
+        if post.link.is_empty() {
+            return Err("the article has no link".into());
+        }
+        let exists: Exists = self
+            .client
+            .get(self.endpoint("api/entries/exists.json"))
+            .bearer_auth(token)
+            .query(&[("url", post.link.as_str())])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if exists.exists {
+            return Ok(false);
+        }
+
+        let mut tags = vec![TAG.to_string()];
+        tags.extend(post.section.as_ref().map(|section| xhtml::plain(&section.name)));
+        let mut entry = json!({
+            "url": post.link,
+            "title": xhtml::plain(&post.title),
+            "content": text::article_html(path)?,
+            "tags": tags.join(","),
+            "authors": post.authors.join(","),
+            "language": "it",
+        });
+        if let Some(day) = edition.day() {
+            entry["published_at"] = json!(day.to_string());
+        }
+        self.client
+            .post(self.endpoint("api/entries.json"))
+            .bearer_auth(token)
+            .json(&entry)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(true)
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), path)
+    }
+}
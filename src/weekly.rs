@@ -0,0 +1,88 @@
+// Summary of the last days for unattended installs: editions fetched,
+// failures and disk used by the produced files. It is mailed with the SMTP
+// settings of the delivery of the files (see mail.rs), e.g. from cron:
+// `il_manifesto weekly --smtp-server smtp.example.org --to me@example.com`.
+use chrono::{Duration, Utc};
+
+use std::collections::HashSet;
+
+use crate::error::Result;
+use crate::history::History;
+
+pub struct Digest {
+    pub subject: String,
+    pub body: String,
+}
+
+pub fn digest(history: &History, days: i64) -> Result<Digest> {
+    let since = Utc::now() - Duration::days(days.max(0));
+    let editions = history.editions(since)?;
+    let failures = history.failures(since)?;
+
+    // files still on disk, each counted once
+    let mut paths = HashSet::new();
+    let (mut files, mut bytes, mut recent_bytes) = (0, 0, 0);
+    for record in history.records()? {
+        let Ok(metadata) = record.path.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() || !paths.insert(record.path) {
+            continue;
+        }
+        files += 1;
+        bytes += metadata.len();
+        if record.time >= since {
+            recent_bytes += metadata.len();
+        }
+    }
+
+    let subject = format!(
+        "il manifesto: {} editions, {} failures in the last {} days",
+        editions.len(),
+        failures.len(),
+        days
+    );
+
+    let mut lines = vec!["Editions".to_string()];
+    if editions.is_empty() {
+        lines.push("  none".to_string());
+    }
+    for edition in &editions {
+        lines.push(format!(
+            "  {}  {} ({} articles)",
+            edition.downloaded.format("%Y-%m-%d %H:%M"),
+            if edition.title.is_empty() { &edition.slug } else { &edition.title },
+            edition.articles
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("Failures".to_string());
+    if failures.is_empty() {
+        lines.push("  none".to_string());
+    }
+    for failure in &failures {
+        lines.push(format!(
+            "  {}  {} {} {}: {}",
+            failure.time.format("%Y-%m-%d %H:%M"),
+            failure.edition,
+            failure.kind,
+            failure.name,
+            failure.error
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("Disk usage".to_string());
+    lines.push(format!("  {} files, {} in total", files, megabytes(bytes)));
+    lines.push(format!("  {} written in the last {} days", megabytes(recent_bytes), days));
+
+    Ok(Digest {
+        subject,
+        body: lines.join("\n") + "\n",
+    })
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
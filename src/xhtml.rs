@@ -0,0 +1,535 @@
+// Strings of the API written in the generated XHTML pages. Titles, kickers
+// and names are plain text: their tags are removed and the characters XML
+// reserves are escaped, an `&` or `<` in a headline would make the ePUB file
+// invalid. Summaries keep a few inline tags, anything else becomes text.
+// The entities of HTML (&nbsp;, &egrave;) are not defined in XHTML and are
+// written as characters.
+use std::path::{Path, PathBuf};
+
+// Tags kept by `sanitize`, without attributes (but the href of links to
+// sites, addresses and the pages of the book)
+const ALLOWED_TAGS: &[&str] = &["p", "br", "em", "strong", "b", "i", "u", "sub", "sup", "span", "a", "blockquote"];
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Plain text of the string, escaped for XHTML
+pub fn text(html: &str) -> String {
+    escape(&plain(html))
+}
+
+// Plain text of the string, without tags and entities, on one line
+pub fn plain(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some((start, end)) = next_tag(rest) {
+        text.push_str(&rest[..start]);
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    unescape(&text).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Markup of a summary as valid XHTML: allowed tags are kept and closed,
+// the others are removed with their attributes (scripts and styles with
+// their content)
+pub fn sanitize(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut open: Vec<&str> = Vec::new();
+    let mut rest = html;
+
+    while let Some((start, end)) = next_tag(rest) {
+        output.push_str(&escape(&unescape(&rest[..start])));
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        if !closing && (name == "script" || name == "style") {
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(i) => rest[i..].find('>').map_or("", |end| &rest[i + end + 1..]),
+                None => "",
+            };
+            continue;
+        }
+        let Some(&name) = ALLOWED_TAGS.iter().find(|&&allowed| allowed == name) else {
+            continue;
+        };
+
+        if closing {
+            // also closes the tags left open inside it
+            if let Some(i) = open.iter().rposition(|&o| o == name) {
+                for name in open.drain(i..).rev() {
+                    output.push_str(&format!("</{}>", name));
+                }
+            }
+        } else if name == "br" {
+            output.push_str("<br />");
+        } else if !tag.ends_with('/') {
+            match attribute(tag, "href").filter(|href| name == "a" && is_safe_link(href)) {
+                Some(href) => output.push_str(&format!(r#"<a href="{}">"#, escape(&unescape(href)))),
+                None => output.push_str(&format!("<{}>", name)),
+            }
+            open.push(name);
+        }
+    }
+    output.push_str(&escape(&unescape(rest)));
+    for name in open.into_iter().rev() {
+        output.push_str(&format!("</{}>", name));
+    }
+    output
+}
+
+// Article documents without scripts, event handlers (onload...), links
+// running code (javascript:...) and the resources loaded from other sites (tracking pixels, iframes, remote
+// stylesheets and images, also in srcset candidates and CSS url()): the ePUB
+// file is read offline and reading it should not tell anyone. Links to other
+// sites are kept.
+pub fn strip_external(xhtml: &str) -> String {
+    let mut output = String::with_capacity(xhtml.len());
+    // elements removed whose closing tag is still to come
+    let mut removed: Vec<String> = Vec::new();
+    let mut rest = xhtml;
+
+    while let Some((start, end)) = next_tag(rest) {
+        output.push_str(&rest[..start]);
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+
+        let inner = &tag[1..tag.len() - 1];
+        let closing = inner.starts_with('/');
+        let name = inner
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        if closing {
+            match removed.iter().rposition(|removed| *removed == name) {
+                Some(i) => {
+                    removed.remove(i);
+                }
+                None => output.push_str(tag),
+            }
+            continue;
+        }
+        if name == "script" {
+            // with its content
+            let close = rest.to_ascii_lowercase().find("</script");
+            rest = close.and_then(|i| Some(&rest[i + rest[i..].find('>')? + 1..])).unwrap_or_default();
+            continue;
+        }
+        let srcset = attribute(inner, "srcset");
+        let local_srcset = srcset.map(local_candidates);
+        let external = ["src", "href", "data", "poster"]
+            .iter()
+            .filter(|&&attr| attr != "href" || name != "a")
+            .filter_map(|attr| attribute(inner, attr))
+            .any(is_external)
+            // e.g. <source> with remote images only
+            || (local_srcset.as_ref().is_some_and(String::is_empty) && attribute(inner, "src").is_none());
+        if external {
+            if !inner.ends_with('/') && !VOID_TAGS.contains(&name.as_str()) {
+                removed.push(name);
+            }
+            continue;
+        }
+
+        let mut local_tag = tag.to_string();
+        if let (Some(srcset), Some(local)) = (srcset, local_srcset) {
+            if local.is_empty() {
+                // the whole attribute, the src is enough
+                if let Some(attribute) = attributes(tag).into_iter().find(|attribute| attribute.name.eq_ignore_ascii_case("srcset")) {
+                    local_tag.replace_range(attribute.start..attribute.end, "");
+                }
+            } else {
+                let at = srcset.as_ptr() as usize - tag.as_ptr() as usize;
+                local_tag.replace_range(at..at + srcset.len(), &local);
+            }
+        }
+        output.push_str(&without_remote_urls(&without_handlers(&local_tag)));
+        if name == "style" {
+            // the rules, up to the closing tag
+            let close = rest.to_ascii_lowercase().find("</style").unwrap_or(rest.len());
+            output.push_str(&without_remote_urls(&without_remote_imports(&rest[..close])));
+            rest = &rest[close..];
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+// Article documents for ePUB 2 readers, which know XHTML 1.1: the elements
+// added by HTML5 (section, figure...) become a div or span with their name as
+// class, the epub: attributes and the HTML5 doctype are removed
+pub fn epub2(xhtml: &str) -> String {
+    let mut output = String::with_capacity(xhtml.len());
+    let mut rest = xhtml;
+
+    while let Some((start, end)) = next_tag(rest) {
+        output.push_str(&rest[..start]);
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+
+        let inner = &tag[1..tag.len() - 1];
+        if inner.to_ascii_lowercase().starts_with("!doctype html") {
+            continue;
+        }
+        let closing = inner.starts_with('/');
+        let name = inner
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let tag = without_epub_attributes(tag);
+        let replacement = if HTML5_BLOCKS.contains(&name) {
+            "div"
+        } else if HTML5_INLINES.contains(&name) {
+            "span"
+        } else {
+            output.push_str(&tag);
+            continue;
+        };
+
+        if closing {
+            output.push_str(&format!("</{}>", replacement));
+            continue;
+        }
+        let attributes = &tag[1 + name.len()..];
+        match attribute(&tag, "class") {
+            Some(class) => {
+                // the name first in the value of the class attribute
+                let at = class.as_ptr() as usize - attributes.as_ptr() as usize;
+                output.push_str(&format!("<{}{}{} {}", replacement, &attributes[..at], name, &attributes[at..]));
+            }
+            None => output.push_str(&format!(r#"<{} class="{}"{}"#, replacement, name, attributes)),
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+const HTML5_BLOCKS: &[&str] = &[
+    "section", "article", "aside", "header", "footer", "nav", "main", "figure", "figcaption", "hgroup",
+];
+const HTML5_INLINES: &[&str] = &["mark", "time"];
+
+// The tag without epub:type and xmlns:epub
+fn without_epub_attributes(tag: &str) -> String {
+    let mut tag = tag.to_string();
+    for name in [" epub:type=", " xmlns:epub="] {
+        while let Some(start) = tag.find(name) {
+            let value = &tag[start + name.len()..];
+            let length = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => value[1..].find(quote).map_or(value.len(), |end| end + 2),
+                _ => 0,
+            };
+            tag.replace_range(start..start + name.len() + length, "");
+        }
+    }
+    tag
+}
+
+// Elements without closing tag in HTML
+pub const VOID_TAGS: &[&str] = &["img", "link", "source", "embed", "meta", "input", "track", "br", "hr"];
+
+fn is_external(link: &str) -> bool {
+    let link = link.trim_start().to_ascii_lowercase();
+    link.starts_with("http:") || link.starts_with("https:") || link.starts_with("//")
+}
+
+// The local candidates of a srcset attribute ("a.jpg 1x, b.jpg 2x"). URLs
+// may contain commas, candidates are made of the URL up to a space and the
+// descriptors up to a comma.
+fn local_candidates(srcset: &str) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = &rest[..url_end];
+        let end = if url.ends_with(',') {
+            url_end
+        } else {
+            url_end + rest[url_end..].find(',').unwrap_or(rest.len() - url_end)
+        };
+        let candidate = rest[..end].trim_end_matches(',').trim();
+        if !is_external(url) {
+            candidates.push(candidate);
+        }
+        rest = &rest[end..];
+    }
+    candidates.join(", ")
+}
+
+// CSS (a style attribute or the rules of a style element) with the remote
+// url() replaced by none
+fn without_remote_urls(css: &str) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.to_ascii_lowercase().find("url(") {
+        let Some(length) = rest[start..].find(')') else {
+            break;
+        };
+        let url = rest[start + 4..start + length]
+            .trim()
+            .trim_start_matches("&quot;")
+            .trim_start_matches(['"', '\'']);
+        output.push_str(&rest[..start]);
+        if is_external(url) {
+            output.push_str("none");
+        } else {
+            output.push_str(&rest[start..=start + length]);
+        }
+        rest = &rest[start + length + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Rules of a style element without the @import of remote stylesheets
+fn without_remote_imports(css: &str) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.to_ascii_lowercase().find("@import") {
+        let length = rest[start..].find(';').map_or(rest.len() - start, |end| end + 1);
+        let target = rest[start + "@import".len()..start + length]
+            .trim()
+            .trim_start_matches("url(")
+            .trim_start_matches(['"', '\'']);
+        output.push_str(&rest[..start]);
+        if !is_external(target) {
+            output.push_str(&rest[start..start + length]);
+        }
+        rest = &rest[start + length..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// The tag without on... attributes (onclick, ONLOAD) and without links
+// running code (javascript:, vbscript:, data:)
+fn without_handlers(tag: &str) -> String {
+    let mut output = String::with_capacity(tag.len());
+    let mut from = 0;
+    for attribute in attributes(tag) {
+        let name = attribute.name.to_ascii_lowercase();
+        let handler = name.len() > 2 && name.starts_with("on");
+        let script = name == "href" && attribute.value.and_then(scheme).is_some_and(|scheme| ["javascript", "vbscript", "data"].contains(&scheme.as_str()));
+        if handler || script {
+            output.push_str(&tag[from..attribute.start]);
+            from = attribute.end;
+        }
+    }
+    output.push_str(&tag[from..]);
+    output
+}
+
+// Links kept in summaries: http, https, mailto, relative and fragment links
+fn is_safe_link(link: &str) -> bool {
+    scheme(link).is_none_or(|scheme| ["http", "https", "mailto"].contains(&scheme.as_str()))
+}
+
+// Scheme of a link in lowercase, none for relative links. Browsers ignore
+// blanks and control characters in it ("java&#x09;script:").
+fn scheme(link: &str) -> Option<String> {
+    let link: String = unescape(link).chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect();
+    let end = link.find(':')?;
+    let scheme = &link[..end];
+    let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    Some(scheme.to_ascii_lowercase()).filter(|_| valid)
+}
+
+// An attribute of a tag, at start..end from the blank before its name
+struct Attribute<'a> {
+    name: &'a str,
+    // without the quotes, none for a name alone
+    value: Option<&'a str>,
+    start: usize,
+    end: usize,
+}
+
+// Attributes of a tag (<a href="...">, also without the brackets). A quoted
+// value is taken whole, title="x onclick=1" is one attribute.
+fn attributes(tag: &str) -> Vec<Attribute<'_>> {
+    let mut attributes = Vec::new();
+    let delimiter = |c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/');
+    // after the element name
+    let mut i = tag.len() - tag.trim_start_matches(['<', '/']).len();
+    i += tag[i..].find(|c: char| c.is_whitespace() || matches!(c, '>' | '/')).unwrap_or(tag.len() - i);
+    loop {
+        let start = i;
+        i += tag[i..].find(|c: char| !c.is_whitespace() && c != '/').unwrap_or(tag.len() - i);
+        let first = match tag[i..].chars().next() {
+            Some('>') | None => break,
+            Some(first) => first,
+        };
+        let name_start = i;
+        i += first.len_utf8();
+        i += tag[i..].find(delimiter).unwrap_or(tag.len() - i);
+        let name = &tag[name_start..i];
+
+        let mut value = None;
+        let equals = i + tag[i..].find(|c: char| !c.is_whitespace()).unwrap_or(tag.len() - i);
+        if tag[equals..].starts_with('=') {
+            i = equals + 1;
+            i += tag[i..].find(|c: char| !c.is_whitespace()).unwrap_or(tag.len() - i);
+            match tag[i..].chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &tag[i + 1..];
+                    let length = quoted.find(quote).unwrap_or(quoted.len());
+                    value = Some(&quoted[..length]);
+                    i = (i + length + 2).min(tag.len());
+                }
+                _ => {
+                    let length = tag[i..].find(|c: char| c.is_whitespace() || c == '>').unwrap_or(tag.len() - i);
+                    value = Some(&tag[i..i + length]);
+                    i += length;
+                }
+            }
+        }
+        attributes.push(Attribute { name, value, start, end: i });
+    }
+    attributes
+}
+
+// Position of the < and > of the next tag. A "<" not followed by a name, or
+// never closed, is text.
+pub fn next_tag(html: &str) -> Option<(usize, usize)> {
+    let mut from = 0;
+    while let Some(start) = html[from..].find('<').map(|i| from + i) {
+        let starts_tag = html[start + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        match html[start..].find('>') {
+            Some(end) if starts_tag => return Some((start, start + end)),
+            Some(_) => from = start + 1,
+            None => return None,
+        }
+    }
+    None
+}
+
+// Content of the <body> element
+pub fn body(xhtml: &str) -> Option<&str> {
+    let start = xhtml.find("<body")?;
+    let start = start + xhtml[start..].find('>')? + 1;
+    xhtml.get(start..xhtml.rfind("</body>")?)
+}
+
+// Path in the ePUB file of a link relative to a document
+pub fn resolve(document: &Path, link: &str) -> PathBuf {
+    let mut path = document.parent().map(Path::to_path_buf).unwrap_or_default();
+    let link = link.split(['#', '?']).next().unwrap_or_default();
+    for part in link.split('/') {
+        match part {
+            ".." => {
+                path.pop();
+            }
+            "." | "" => {}
+            part => path.push(part),
+        }
+    }
+    path
+}
+
+// Name of the element of a tag, e.g. p for </p>
+pub fn tag_name(tag: &str) -> &str {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()
+        .unwrap_or_default()
+}
+
+// Value of an attribute, e.g. the ... of href="...", with the name in any case
+pub fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    attributes(tag)
+        .into_iter()
+        .find(|attribute| attribute.name.eq_ignore_ascii_case(name))
+        .and_then(|attribute| attribute.value)
+}
+
+// Characters of the entities, unknown ones are left as they are
+pub fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| entity(&rest[1..end]).map(|c| (end, c)));
+        match entity {
+            Some((end, c)) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "laquo" => '«',
+        "raquo" => '»',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "euro" => '€',
+        "deg" => '°',
+        "agrave" => 'à',
+        "egrave" => 'è',
+        "eacute" => 'é',
+        "igrave" => 'ì',
+        "ograve" => 'ò',
+        "ugrave" => 'ù',
+        "Agrave" => 'À',
+        "Egrave" => 'È',
+        "Eacute" => 'É',
+        "Igrave" => 'Ì',
+        "Ograve" => 'Ò',
+        "Ugrave" => 'Ù',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests;
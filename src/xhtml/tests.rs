@@ -0,0 +1,65 @@
+// The markup of the articles and summaries, as written in the ePUB file
+use super::*;
+
+#[test]
+fn handlers_are_removed_in_any_case() {
+    let xhtml = r#"<p onClick="go()" ONLOAD='go()' class="a" onmouseover=go()>x</p>"#;
+    assert_eq!(strip_external(xhtml), r#"<p class="a">x</p>"#);
+}
+
+#[test]
+fn quoted_values_are_kept_whole() {
+    let xhtml = r#"<img src="a.jpg" title="x onerror=1" alt='y onload="2"' />"#;
+    assert_eq!(strip_external(xhtml), xhtml);
+}
+
+#[test]
+fn handlers_after_a_slash_are_removed() {
+    assert_eq!(strip_external(r#"<img/onerror="go()" src="a.jpg"/>"#), r#"<img src="a.jpg"/>"#);
+}
+
+#[test]
+fn links_running_code_are_removed() {
+    for href in [
+        "javascript:go()",
+        "JavaScript:go()",
+        " java&#x09;script:go()",
+        "vbscript:go",
+        "data:text/html;base64,PHNjcmlwdD4=",
+    ] {
+        let xhtml = format!(r#"<a href="{}" class="a">x</a>"#, href);
+        assert_eq!(strip_external(&xhtml), r#"<a class="a">x</a>"#, "{}", href);
+    }
+}
+
+#[test]
+fn other_links_are_kept() {
+    for href in ["https://example.org/a", "mailto:a@example.org", "../Text/b.xhtml#n1", "#n1"] {
+        let xhtml = format!(r#"<a href="{}">x</a>"#, href);
+        assert_eq!(strip_external(&xhtml), xhtml);
+    }
+}
+
+#[test]
+fn attributes_are_found_in_any_case() {
+    assert_eq!(attribute(r#"<img SRC="a.jpg">"#, "src"), Some("a.jpg"));
+    assert_eq!(attribute(r#"<img src=a.jpg>"#, "src"), Some("a.jpg"));
+    assert_eq!(attribute(r#"<img alt="src=b.jpg">"#, "src"), None);
+    assert_eq!(attribute(r#"<input disabled>"#, "disabled"), None);
+}
+
+#[test]
+fn summaries_keep_links_to_sites_and_pages() {
+    for href in ["http://example.org/a", "HTTPS://example.org/a", "mailto:a@example.org", "b.xhtml", "/a/b", "#n1"] {
+        let html = format!(r#"<a href="{}" onclick="go()">x</a>"#, href);
+        assert_eq!(sanitize(&html), format!(r#"<a href="{}">x</a>"#, href));
+    }
+}
+
+#[test]
+fn summaries_drop_other_links() {
+    for href in ["javascript:go()", " JAVA&#x09;SCRIPT:go()", "vbscript:go", "data:text/html,x", "file:///etc/passwd"] {
+        let html = format!(r#"<a href="{}">x</a>"#, href);
+        assert_eq!(sanitize(&html), "<a>x</a>", "{}", href);
+    }
+}